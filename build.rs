@@ -49,6 +49,17 @@ fn build_lgfx_linux() -> anyhow::Result<()> {
         .include("LovyanGFX/src")
         .include("/usr/include/x86_64-linux-gnu")
         .compile("libLovyanGFX_c.a");
+    // Offscreen framebuffer of the headless mode.
+    cc::Build::new()
+        .cpp(true)
+        .warnings(false)
+        .flag("-std=c++17")
+        .flag("-g")
+        .flag("-DLGFX_SDL")
+        .file("src/framebuffer.cpp")
+        .include("LovyanGFX/src")
+        .include("/usr/include/x86_64-linux-gnu")
+        .compile("libframebuffer.a");
     println!("cargo:rustc-link-lib=SDL2");
     println!("cargo:rustc-link-lib=SDL2main");
     Ok(())
@@ -0,0 +1,241 @@
+//! Control of the air conditioners registered to the Cloud API.
+//!
+//! A change is applied to the local state immediately (optimistic update) and sent by
+//! `POST /1/appliances/{id}/aircon_settings` in the background. If the request fails,
+//! the previous settings are restored and the error is shown on the page.
+
+use std::{fmt::Write, str::FromStr, sync::Mutex};
+
+use fuga_remo_api::Appliance;
+use uuid::Uuid;
+
+use crate::{api_post, input::{self, InputEvent}, json_string_of, spawn_background};
+
+pub const MAX_AIRCONS: usize = 4;
+/// Operation modes in the order switched by the mode button.
+const MODES: [&str; 5] = ["cool", "warm", "dry", "blow", "auto"];
+/// Air volumes in the order switched by the fan button.
+const VOLUMES: [&str; 6] = ["auto", "1", "2", "3", "4", "5"];
+const MIN_TEMPERATURE: f32 = 16.0;
+const MAX_TEMPERATURE: f32 = 30.0;
+const BUTTON_POWER_OFF: &str = "power-off";
+
+/// Settings of an air conditioner, in the values of the Cloud API.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AirconSettings {
+    /// Target temperature. Empty or relative (e.g. "-1") in some modes.
+    pub temperature: heapless::String<8>,
+    pub mode: heapless::String<8>,
+    pub volume: heapless::String<8>,
+    /// "power-off" while the air conditioner is off.
+    pub button: heapless::String<16>,
+}
+
+impl AirconSettings {
+    pub fn is_on(&self) -> bool {
+        self.button != BUTTON_POWER_OFF
+    }
+
+    /// Extract the settings from the JSON of the settings, e.g. the response of `aircon_settings`.
+    fn parse(body: &str) -> Option<Self> {
+        let string_of = |key: &str| json_string_of(body, key);
+        Some(Self {
+            temperature: heapless::String::from_str(string_of("\"temp\"")?).ok()?,
+            mode: heapless::String::from_str(string_of("\"mode\"")?).ok()?,
+            volume: heapless::String::from_str(string_of("\"vol\"")?).ok()?,
+            button: heapless::String::from_str(string_of("\"button\"")?).ok()?,
+        })
+    }
+
+    /// Body of `POST /1/appliances/{id}/aircon_settings`.
+    fn form(&self) -> String {
+        format!("temperature={}&operation_mode={}&air_volume={}&button={}", self.temperature, self.mode, self.volume, self.button)
+    }
+}
+
+/// Fields of the `aircon_settings` form which can be written in the automation rules.
+const FORM_FIELDS: [&str; 6] = ["temperature", "operation_mode", "air_volume", "air_direction", "air_direction_h", "button"];
+
+/// Convert a setting written in an automation rule to a field of the `aircon_settings` form.
+/// `mode`, `temp` and `volume` are short for `operation_mode`, `temperature` and `air_volume`,
+/// and `on` and `off` are short for the power button.
+pub fn form_field(setting: &str) -> Option<(&'static str, &str)> {
+    match setting {
+        "on" => return Some(("button", "")),
+        "off" => return Some(("button", BUTTON_POWER_OFF)),
+        _ => {},
+    }
+    let (name, value) = setting.split_once('=')?;
+    let name = match name {
+        "mode" => "operation_mode",
+        "temp" => "temperature",
+        "volume" => "air_volume",
+        name => FORM_FIELDS.iter().find(|field| **field == name)?,
+    };
+    Some((name, value))
+}
+
+/// A change requested from the control page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AirconChange {
+    TogglePower,
+    NextMode,
+    TemperatureUp,
+    TemperatureDown,
+    NextVolume,
+}
+
+fn next_of<const N: usize>(values: &[&str; N], current: &str) -> heapless::String<8> {
+    let index = values.iter().position(|value| *value == current).map(|index| (index + 1) % N).unwrap_or(0);
+    heapless::String::from_str(values[index]).unwrap()
+}
+
+impl AirconChange {
+    /// Apply the change. Returns None if it does not change anything.
+    fn apply(&self, settings: &AirconSettings) -> Option<AirconSettings> {
+        let mut changed = settings.clone();
+        match self {
+            AirconChange::TogglePower => {
+                changed.button.clear();
+                if settings.is_on() {
+                    changed.button.push_str(BUTTON_POWER_OFF).ok();
+                }
+            },
+            AirconChange::NextMode => changed.mode = next_of(&MODES, &settings.mode),
+            AirconChange::NextVolume => changed.volume = next_of(&VOLUMES, &settings.volume),
+            AirconChange::TemperatureUp | AirconChange::TemperatureDown => {
+                // Relative temperatures such as "-1" are not changed.
+                let temperature: f32 = settings.temperature.parse().ok().filter(|temperature| *temperature >= MIN_TEMPERATURE)?;
+                let step = if *self == AirconChange::TemperatureUp { 1.0 } else { -1.0 };
+                let temperature = (temperature + step).clamp(MIN_TEMPERATURE, MAX_TEMPERATURE);
+                changed.temperature.clear();
+                write!(&mut changed.temperature, "{}", temperature).ok();
+            },
+        }
+        (changed != *settings).then_some(changed)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Aircon {
+    pub id: Uuid,
+    pub nickname: heapless::String<64>,
+    pub settings: AirconSettings,
+    /// True while a change is being sent.
+    pub pending: bool,
+    /// Error of the last change, cleared by the next change.
+    pub error: Option<heapless::String<64>>,
+}
+
+pub struct AirconStatus {
+    pub aircons: heapless::Vec<Aircon, MAX_AIRCONS>,
+}
+
+impl AirconStatus {
+    pub const fn new() -> Self {
+        Self {
+            aircons: heapless::Vec::new(),
+        }
+    }
+}
+
+/// Air conditioners and their settings, shown on the control page.
+pub static AIRCON_STATUS: Mutex<AirconStatus> = Mutex::new(AirconStatus::new());
+
+/// Read the air conditioner from an appliance of `GET /1/appliances`. Only air conditioners have the settings.
+pub fn from_appliance(appliance: &Appliance) -> Option<Aircon> {
+    let settings = appliance.settings.as_ref()?;
+    Some(Aircon {
+        id: appliance.id,
+        nickname: appliance.nickname.clone(),
+        settings: AirconSettings {
+            temperature: heapless::String::from_str(&settings.temp).ok()?,
+            mode: heapless::String::from_str(&settings.mode).ok()?,
+            volume: heapless::String::from_str(&settings.vol).ok()?,
+            button: heapless::String::from_str(&settings.button).ok()?,
+        },
+        pending: false,
+        error: None,
+    })
+}
+
+/// Update the list with the appliances read from the Cloud API.
+/// The settings being sent are kept, since the Cloud API may not reflect them yet.
+pub fn update_aircons(aircons: heapless::Vec<Aircon, MAX_AIRCONS>) {
+    let mut status = AIRCON_STATUS.lock().unwrap();
+    let previous = core::mem::replace(&mut status.aircons, aircons);
+    for aircon in status.aircons.iter_mut() {
+        if let Some(previous) = previous.iter().find(|previous| previous.id == aircon.id) {
+            if previous.pending {
+                aircon.settings = previous.settings.clone();
+                aircon.pending = true;
+            }
+            aircon.error = previous.error.clone();
+        }
+    }
+}
+
+/// POST the form to `aircon_settings` and return the settings after the change, if the response has them.
+fn post_settings(id: Uuid, form: &str) -> anyhow::Result<Option<AirconSettings>> {
+    let mut path = heapless::String::<80>::new();
+    write!(&mut path, "/1/appliances/{}/aircon_settings", id).ok();
+    let body = api_post(&path, form)?;
+    Ok(AirconSettings::parse(&String::from_utf8_lossy(&body)))
+}
+
+fn send_settings(id: Uuid, settings: &AirconSettings) -> anyhow::Result<AirconSettings> {
+    // The Cloud API returns the settings after the change.
+    Ok(post_settings(id, &settings.form())?.unwrap_or_else(|| settings.clone()))
+}
+
+/// Send the form of `aircon_settings` as is, e.g. `operation_mode=dry&temperature=-1`, and show the settings after the change.
+pub fn send_form(id: Uuid, form: &str) -> anyhow::Result<()> {
+    if let Some(settings) = post_settings(id, form)? {
+        let mut status = AIRCON_STATUS.lock().unwrap();
+        if let Some(aircon) = status.aircons.iter_mut().find(|aircon| aircon.id == id && !aircon.pending) {
+            aircon.settings = settings;
+        }
+    }
+    input::push_event(InputEvent::Refresh);
+    Ok(())
+}
+
+/// Apply the change to the air conditioner at the index and send it.
+/// Returns false if nothing is changed, e.g. while the previous change is being sent.
+pub fn change(index: usize, change: AirconChange) -> bool {
+    let (id, previous, changed) = {
+        let mut status = AIRCON_STATUS.lock().unwrap();
+        let aircon = match status.aircons.get_mut(index) {
+            Some(aircon) if !aircon.pending => aircon,
+            _ => return false,
+        };
+        let changed = match change.apply(&aircon.settings) {
+            Some(changed) => changed,
+            None => return false,
+        };
+        let previous = core::mem::replace(&mut aircon.settings, changed.clone());
+        aircon.pending = true;
+        aircon.error = None;
+        (aircon.id, previous, changed)
+    };
+    log::info!("aircon {} change {:?}: {:?}", id, change, changed);
+    spawn_background("AIRCON", move || {
+        let result = send_settings(id, &changed);
+        let mut status = AIRCON_STATUS.lock().unwrap();
+        if let Some(aircon) = status.aircons.iter_mut().find(|aircon| aircon.id == id) {
+            aircon.pending = false;
+            match result {
+                Ok(settings) => aircon.settings = settings,
+                Err(err) => {
+                    log::warn!("Failed to change the aircon settings - {:?}", err);
+                    aircon.settings = previous;
+                    let mut error = heapless::String::new();
+                    write!(&mut error, "{}", err).ok();
+                    aircon.error = Some(error);
+                },
+            }
+        }
+        input::push_event(InputEvent::Refresh);
+    });
+    true
+}
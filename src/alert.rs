@@ -0,0 +1,386 @@
+//! Threshold alerts evaluated on each sample.
+//!
+//! Rules are written like `temperature > 28 for 10m buzzer; humidity < 35; power > 3000 webhook`.
+//! An alert is raised when the condition holds for the duration, and cleared when the value goes back
+//! beyond the threshold by the hysteresis, so that it does not flap around the threshold.
+
+use std::{sync::Mutex, time::Duration};
+
+use anyhow::anyhow;
+
+use crate::{post_http, spawn_background, ui::SensorKind, SensorRecord, Timestamp, CONFIG};
+
+pub const MAX_ALERT_RULES: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+impl Comparison {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Above => ">",
+            Comparison::Below => "<",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AlertRule {
+    pub kind: SensorKind,
+    pub comparison: Comparison,
+    pub threshold: f32,
+    /// The condition must hold for this duration before the alert is raised.
+    pub duration: Duration,
+    pub hysteresis: f32,
+    pub buzzer: bool,
+    pub webhook: bool,
+}
+
+fn parse_kind(name: &str) -> Option<SensorKind> {
+    match name {
+        "temperature" => Some(SensorKind::Temperature),
+        "humidity" => Some(SensorKind::Humidity),
+        "illuminance" => Some(SensorKind::Illuminance),
+        "power" => Some(SensorKind::Power),
+        _ => None,
+    }
+}
+
+pub fn kind_name(kind: SensorKind) -> &'static str {
+    match kind {
+        SensorKind::Temperature => "temperature",
+        SensorKind::Humidity => "humidity",
+        SensorKind::Illuminance => "illuminance",
+        SensorKind::Power => "power",
+    }
+}
+
+fn default_hysteresis(kind: SensorKind) -> f32 {
+    match kind {
+        SensorKind::Temperature => 0.5,
+        SensorKind::Humidity => 2.0,
+        SensorKind::Illuminance => 10.0,
+        SensorKind::Power => 100.0,
+    }
+}
+
+/// Parse a duration such as "30s", "10m" or "1h".
+fn parse_duration(duration: &str) -> Option<Duration> {
+    let unit = match duration.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        _ => return None,
+    };
+    let value: u64 = duration[..duration.len() - 1].parse().ok()?;
+    Some(Duration::from_secs(value * unit))
+}
+
+impl AlertRule {
+    /// Parse a rule: `<sensor> <'>'|'<'> <threshold> [for <duration>] [hysteresis <value>] [buzzer] [webhook]`
+    pub fn parse(rule: &str) -> anyhow::Result<Self> {
+        let mut tokens = rule.split_whitespace();
+        let kind = tokens.next().and_then(parse_kind).ok_or_else(|| anyhow!("unknown sensor in \"{}\"", rule))?;
+        let comparison = match tokens.next() {
+            Some(">") => Comparison::Above,
+            Some("<") => Comparison::Below,
+            _ => return Err(anyhow!("invalid comparison in \"{}\"", rule)),
+        };
+        let threshold = tokens.next().and_then(|threshold| threshold.parse().ok()).ok_or_else(|| anyhow!("invalid threshold in \"{}\"", rule))?;
+        let mut parsed = Self {
+            kind,
+            comparison,
+            threshold,
+            duration: Duration::ZERO,
+            hysteresis: default_hysteresis(kind),
+            buzzer: false,
+            webhook: false,
+        };
+        while let Some(token) = tokens.next() {
+            match token {
+                "for" => parsed.duration = tokens.next().and_then(parse_duration).ok_or_else(|| anyhow!("invalid duration in \"{}\"", rule))?,
+                "hysteresis" => parsed.hysteresis = tokens.next().and_then(|value| value.parse().ok()).ok_or_else(|| anyhow!("invalid hysteresis in \"{}\"", rule))?,
+                "buzzer" => parsed.buzzer = true,
+                "webhook" => parsed.webhook = true,
+                _ => return Err(anyhow!("unknown option \"{}\" in \"{}\"", token, rule)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    pub fn is_exceeded(&self, value: f32) -> bool {
+        match self.comparison {
+            Comparison::Above => value > self.threshold,
+            Comparison::Below => value < self.threshold,
+        }
+    }
+
+    pub fn is_recovered(&self, value: f32) -> bool {
+        match self.comparison {
+            Comparison::Above => value <= self.threshold - self.hysteresis,
+            Comparison::Below => value >= self.threshold + self.hysteresis,
+        }
+    }
+}
+
+/// Parse the rules separated by ';'. Invalid rules are skipped.
+pub fn parse_rules(rules: &str) -> heapless::Vec<AlertRule, MAX_ALERT_RULES> {
+    let mut parsed = heapless::Vec::new();
+    for rule in rules.split(';').map(str::trim).filter(|rule| !rule.is_empty()) {
+        match AlertRule::parse(rule) {
+            Ok(rule) => {
+                if parsed.push(rule).is_err() {
+                    log::warn!("Too many alert rules. A rule of {} is ignored", kind_name(rule.kind));
+                }
+            },
+            Err(err) => log::warn!("Invalid alert rule - {}", err),
+        }
+    }
+    parsed
+}
+
+/// An alert currently raised.
+#[derive(Clone, Copy, Debug)]
+pub struct ActiveAlert {
+    pub rule: AlertRule,
+    /// The latest value of the sensor.
+    pub value: f32,
+    /// When the condition started to hold.
+    pub since: Timestamp,
+}
+
+struct RuleState {
+    rule: AlertRule,
+    /// When the condition started to hold.
+    exceeded_since: Option<Timestamp>,
+    active: Option<ActiveAlert>,
+}
+
+impl RuleState {
+    fn new(rule: AlertRule) -> Self {
+        Self {
+            rule,
+            exceeded_since: None,
+            active: None,
+        }
+    }
+
+    /// Update the state with the value. Returns the alert and true if it is raised, or false if it is cleared.
+    fn update(&mut self, value: f32, timestamp: Timestamp) -> Option<(ActiveAlert, bool)> {
+        if let Some(active) = self.active.as_mut() {
+            active.value = value;
+            if self.rule.is_recovered(value) {
+                let cleared = *active;
+                self.active = None;
+                self.exceeded_since = None;
+                return Some((cleared, false));
+            }
+            return None;
+        }
+        if !self.rule.is_exceeded(value) {
+            self.exceeded_since = None;
+            return None;
+        }
+        let since = *self.exceeded_since.get_or_insert(timestamp);
+        let elapsed = (timestamp - since).to_std().unwrap_or(Duration::ZERO);
+        if elapsed < self.rule.duration {
+            return None;
+        }
+        let active = ActiveAlert {
+            rule: self.rule,
+            value,
+            since,
+        };
+        self.active = Some(active);
+        Some((active, true))
+    }
+}
+
+static ALERTS: Mutex<heapless::Vec<RuleState, MAX_ALERT_RULES>> = Mutex::new(heapless::Vec::new());
+
+pub fn init() {
+    let rules = parse_rules(CONFIG.lock().unwrap().as_ref().unwrap().alert_rules.as_str());
+    log::info!("alert rules: {:?}", rules);
+    *ALERTS.lock().unwrap() = rules.into_iter().map(RuleState::new).collect();
+}
+
+/// Evaluate the rules with the sample. Called from the sample task.
+pub fn evaluate(record: &SensorRecord, timestamp: Timestamp) {
+    let mut events: heapless::Vec<(ActiveAlert, bool), MAX_ALERT_RULES> = heapless::Vec::new();
+    for state in ALERTS.lock().unwrap().iter_mut() {
+        let value = state.rule.kind.value(record);
+        if let Some(event) = state.update(value, timestamp) {
+            events.push(event).ok();
+        }
+    }
+    // Notify without holding the lock.
+    for (alert, raised) in events {
+        notify(&alert, raised, timestamp);
+    }
+}
+
+/// Alerts currently raised, for the banner on the display.
+pub fn active_alerts() -> heapless::Vec<ActiveAlert, MAX_ALERT_RULES> {
+    ALERTS.lock().unwrap().iter().filter_map(|state| state.active).collect()
+}
+
+fn notify(alert: &ActiveAlert, raised: bool, timestamp: Timestamp) {
+    let rule = &alert.rule;
+    if raised {
+        log::warn!("Alert raised: {} {} {} (value: {})", kind_name(rule.kind), rule.comparison.symbol(), rule.threshold, alert.value);
+    } else {
+        log::info!("Alert cleared: {} {} {} (value: {})", kind_name(rule.kind), rule.comparison.symbol(), rule.threshold, alert.value);
+    }
+    if raised && rule.buzzer {
+        buzz();
+    }
+    if rule.webhook {
+        let body = format!(
+            "{{\"state\":\"{}\",\"sensor\":\"{}\",\"comparison\":\"{}\",\"threshold\":{},\"value\":{},\"since\":\"{}\",\"timestamp\":\"{}\"}}",
+            if raised { "raised" } else { "cleared" },
+            kind_name(rule.kind),
+            rule.comparison.symbol(),
+            rule.threshold,
+            alert.value,
+            alert.since.to_rfc3339(),
+            timestamp.to_rfc3339(),
+        );
+        post_webhook(body);
+    }
+}
+
+/// POST the JSON body to the webhook URL. Does nothing if the URL is not configured.
+pub fn send_webhook(body: &str) -> anyhow::Result<()> {
+    let url = CONFIG.lock().unwrap().as_ref().unwrap().alert_webhook_url.clone();
+    if url.is_empty() {
+        return Ok(());
+    }
+    let (status, _) = post_http(&url, &[("Content-Type", "application/json")], body.as_bytes())?;
+    if !(200..300).contains(&status) {
+        anyhow::bail!("Webhook returned {}", status);
+    }
+    Ok(())
+}
+
+/// POST the JSON body to the webhook URL in the background. Does nothing if the URL is not configured.
+fn post_webhook(body: String) {
+    if CONFIG.lock().unwrap().as_ref().unwrap().alert_webhook_url.is_empty() {
+        return;
+    }
+    // The webhook must not delay the sampling.
+    spawn_background("WEBHOOK", move || {
+        if let Err(err) = send_webhook(&body) {
+            log::warn!("Webhook failed - {:?}", err);
+        }
+    });
+}
+
+/// Number and length of the beeps.
+const BEEP_COUNT: usize = 3;
+const BEEP_DURATION: Duration = Duration::from_millis(200);
+
+/// Sound the active buzzer connected to the configured GPIO.
+/// M5Paper has no buzzer on board, so an external one is connected to a Grove port.
+#[cfg(target_os="espidf")]
+fn buzz() {
+    let pin = match CONFIG.lock().unwrap().as_ref().unwrap().buzzer_pin {
+        Some(pin) => pin as i32,
+        None => return,
+    };
+    std::thread::Builder::new()
+        .name("BUZZER".into())
+        .stack_size(2048)
+        .spawn(move || unsafe {
+            esp_idf_sys::gpio_reset_pin(pin);
+            esp_idf_sys::gpio_set_direction(pin, esp_idf_sys::gpio_mode_t_GPIO_MODE_OUTPUT);
+            for _ in 0..BEEP_COUNT {
+                esp_idf_sys::gpio_set_level(pin, 1);
+                std::thread::sleep(BEEP_DURATION);
+                esp_idf_sys::gpio_set_level(pin, 0);
+                std::thread::sleep(BEEP_DURATION);
+            }
+        })
+        .ok();
+}
+#[cfg(target_os="linux")]
+fn buzz() {
+    // Ring the terminal bell instead.
+    for _ in 0..BEEP_COUNT {
+        eprint!("\x07");
+    }
+    log::info!("Buzzer: {} beeps of {:?}", BEEP_COUNT, BEEP_DURATION);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(minutes: i64) -> Timestamp {
+        chrono::Utc.with_ymd_and_hms(2024, 7, 1, 12, 0, 0).unwrap() + chrono::Duration::minutes(minutes)
+    }
+
+    #[test]
+    fn parse_rule() {
+        let rule = AlertRule::parse("temperature > 28 for 10m buzzer").unwrap();
+        assert_eq!(rule.kind, SensorKind::Temperature);
+        assert_eq!(rule.comparison, Comparison::Above);
+        assert_eq!(rule.threshold, 28.0);
+        assert_eq!(rule.duration, Duration::from_secs(10 * 60));
+        assert_eq!(rule.hysteresis, 0.5);
+        assert!(rule.buzzer);
+        assert!(!rule.webhook);
+
+        let rule = AlertRule::parse("humidity < 35 hysteresis 5 webhook").unwrap();
+        assert_eq!(rule.comparison, Comparison::Below);
+        assert_eq!(rule.duration, Duration::ZERO);
+        assert_eq!(rule.hysteresis, 5.0);
+        assert!(rule.webhook);
+
+        assert!(AlertRule::parse("pressure > 1000").is_err());
+        assert!(AlertRule::parse("power >= 3000").is_err());
+        assert!(AlertRule::parse("power > high").is_err());
+        assert!(AlertRule::parse("power > 3000 for 10").is_err());
+        assert!(AlertRule::parse("power > 3000 loudly").is_err());
+    }
+
+    #[test]
+    fn skip_invalid_rules() {
+        let rules = parse_rules("temperature > 28; humidity ~ 35; ; power > 3000 webhook");
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[1].kind, SensorKind::Power);
+    }
+
+    #[test]
+    fn raise_after_duration() {
+        let mut state = RuleState::new(AlertRule::parse("temperature > 28 for 10m").unwrap());
+        assert!(state.update(28.5, at(0)).is_none());
+        assert!(state.update(29.0, at(9)).is_none());
+        let (alert, raised) = state.update(29.0, at(10)).unwrap();
+        assert!(raised);
+        assert_eq!(alert.since, at(0));
+        // A value below the threshold in between restarts the duration.
+        let mut state = RuleState::new(AlertRule::parse("temperature > 28 for 10m").unwrap());
+        assert!(state.update(28.5, at(0)).is_none());
+        assert!(state.update(27.9, at(5)).is_none());
+        assert!(state.update(28.5, at(10)).is_none());
+        assert!(state.update(28.5, at(20)).unwrap().1);
+    }
+
+    #[test]
+    fn clear_with_hysteresis() {
+        let mut state = RuleState::new(AlertRule::parse("temperature > 28").unwrap());
+        assert!(state.update(28.5, at(0)).unwrap().1);
+        // Still raised within the hysteresis.
+        assert!(state.update(27.9, at(1)).is_none());
+        assert!(state.update(28.1, at(2)).is_none());
+        let (alert, raised) = state.update(27.5, at(3)).unwrap();
+        assert!(!raised);
+        assert_eq!(alert.value, 27.5);
+        // Raised again when exceeded after the recovery.
+        assert!(state.update(28.2, at(4)).unwrap().1);
+    }
+}
@@ -0,0 +1,364 @@
+//! Automations evaluated locally on each sample.
+//!
+//! Rules are written like `humidity < 40 then signal <id>; power > 2500 then notify; at 22:00 then aircon <id> operation_mode=dry`.
+//! The condition of a sensor rule has the same syntax as the alert rules (`for` and `hysteresis` are available).
+//! A sensor rule fires once when the condition starts to hold, and is armed again when the value recovers.
+//! A time rule fires when the clock passes the time of day in the configured time zone.
+
+use std::{fmt::Write, str::FromStr, sync::{Condvar, Mutex}, time::Duration};
+
+use anyhow::anyhow;
+use chrono::{NaiveTime, Timelike};
+use uuid::Uuid;
+
+use crate::{aircon, clock, copy_truncated, alert::{self, AlertRule}, button_appliance::{self, ButtonApplianceKind}, http_api::write_json_string, remote, spawn_background, SensorRecord, Timestamp, CONFIG};
+
+pub const MAX_AUTOMATION_RULES: usize = 8;
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Trigger {
+    Sensor(AlertRule),
+    /// Time of day in the configured time zone.
+    At(NaiveTime),
+}
+
+#[derive(Clone, Debug)]
+pub enum Action {
+    /// Send the IR signal.
+    Signal(Uuid),
+    /// Press the button of the light or the TV.
+    Button(ButtonApplianceKind, Uuid, heapless::String<32>),
+    /// Send the form of `aircon_settings`.
+    Aircon(Uuid, heapless::String<64>),
+    /// POST the firing to the webhook of the alerts.
+    Notify,
+}
+
+#[derive(Clone, Debug)]
+pub struct AutomationRule {
+    /// The rule as written, for the log.
+    pub text: heapless::String<96>,
+    pub trigger: Trigger,
+    pub action: Action,
+}
+
+fn parse_id(id: Option<&str>, rule: &str) -> anyhow::Result<Uuid> {
+    id.and_then(|id| Uuid::parse_str(id).ok()).ok_or_else(|| anyhow!("invalid appliance or signal ID in \"{}\"", rule))
+}
+
+impl Action {
+    /// Parse an action: `signal <id>`, `light <id> <button>`, `tv <id> <button>`, `aircon <id> <settings...>` or `notify`.
+    /// The settings of `aircon` are separated by spaces or '&', e.g. `aircon <id> mode=cool temp=26` or `aircon <id> off`.
+    fn parse(action: &str, rule: &str) -> anyhow::Result<Self> {
+        let mut tokens = action.split_whitespace();
+        let action = match tokens.next() {
+            Some("signal") => Action::Signal(parse_id(tokens.next(), rule)?),
+            Some(kind @ ("light" | "tv")) => {
+                let kind = if kind == "light" { ButtonApplianceKind::Light } else { ButtonApplianceKind::Tv };
+                let id = parse_id(tokens.next(), rule)?;
+                let button = tokens.next().and_then(|button| heapless::String::from_str(button).ok()).ok_or_else(|| anyhow!("invalid button in \"{}\"", rule))?;
+                Action::Button(kind, id, button)
+            },
+            Some("aircon") => {
+                let id = parse_id(tokens.next(), rule)?;
+                let mut form = heapless::String::<64>::new();
+                for setting in tokens.by_ref().flat_map(|settings| settings.split('&')) {
+                    let (name, value) = aircon::form_field(setting).ok_or_else(|| anyhow!("invalid setting \"{}\" in \"{}\"", setting, rule))?;
+                    let separator = if form.is_empty() { "" } else { "&" };
+                    write!(&mut form, "{}{}={}", separator, name, value).map_err(|_| anyhow!("too many settings in \"{}\"", rule))?;
+                }
+                if form.is_empty() {
+                    return Err(anyhow!("settings are missing in \"{}\"", rule));
+                }
+                Action::Aircon(id, form)
+            },
+            Some("notify") => Action::Notify,
+            _ => return Err(anyhow!("unknown action in \"{}\"", rule)),
+        };
+        match tokens.next() {
+            Some(token) => Err(anyhow!("unexpected \"{}\" in \"{}\"", token, rule)),
+            None => Ok(action),
+        }
+    }
+}
+
+impl AutomationRule {
+    /// Parse a rule: `<condition> then <action>` or `at <HH:MM> then <action>`
+    pub fn parse(rule: &str) -> anyhow::Result<Self> {
+        let (trigger, action) = rule.split_once(" then ").ok_or_else(|| anyhow!("\"then\" is missing in \"{}\"", rule))?;
+        let trigger = match trigger.trim().strip_prefix("at ") {
+            Some(time) => Trigger::At(NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| anyhow!("invalid time in \"{}\"", rule))?),
+            None => Trigger::Sensor(AlertRule::parse(trigger)?),
+        };
+        Ok(Self {
+            text: copy_truncated(rule),
+            trigger,
+            action: Action::parse(action, rule)?,
+        })
+    }
+}
+
+/// Parse the rules separated by ';'. Invalid rules are skipped.
+pub fn parse_rules(rules: &str) -> heapless::Vec<AutomationRule, MAX_AUTOMATION_RULES> {
+    let mut parsed = heapless::Vec::new();
+    for rule in rules.split(';').map(str::trim).filter(|rule| !rule.is_empty()) {
+        match AutomationRule::parse(rule) {
+            Ok(rule) => {
+                if let Err(rule) = parsed.push(rule) {
+                    log::warn!("Too many automation rules. \"{}\" is ignored", rule.text);
+                }
+            },
+            Err(err) => log::warn!("Invalid automation rule - {}", err),
+        }
+    }
+    parsed
+}
+
+struct RuleState {
+    rule: AutomationRule,
+    /// When the condition started to hold.
+    exceeded_since: Option<Timestamp>,
+    /// True after firing until the value recovers.
+    fired: bool,
+}
+
+impl RuleState {
+    fn new(rule: AutomationRule) -> Self {
+        Self {
+            rule,
+            exceeded_since: None,
+            fired: false,
+        }
+    }
+
+    /// Update the state of the sensor rule with the value. Returns true if the rule fires.
+    fn update(&mut self, condition: AlertRule, value: f32, timestamp: Timestamp) -> bool {
+        if self.fired {
+            self.fired = !condition.is_recovered(value);
+            return false;
+        }
+        if !condition.is_exceeded(value) {
+            self.exceeded_since = None;
+            return false;
+        }
+        let since = *self.exceeded_since.get_or_insert(timestamp);
+        if (timestamp - since).to_std().unwrap_or(Duration::ZERO) < condition.duration {
+            return false;
+        }
+        self.fired = true;
+        self.exceeded_since = None;
+        true
+    }
+}
+
+struct Automations {
+    rules: heapless::Vec<RuleState, MAX_AUTOMATION_RULES>,
+    /// When the rules were evaluated last time, to find the times of day passed since then.
+    last_evaluated: Option<Timestamp>,
+}
+
+static AUTOMATIONS: Mutex<Automations> = Mutex::new(Automations {
+    rules: heapless::Vec::new(),
+    last_evaluated: None,
+});
+/// Number of the actions running in the background.
+static RUNNING_ACTIONS: Mutex<usize> = Mutex::new(0);
+static ACTIONS_DONE: Condvar = Condvar::new();
+
+pub fn init() {
+    let rules = parse_rules(CONFIG.lock().unwrap().as_ref().unwrap().automation_rules.as_str());
+    log::info!("automation rules: {:?}", rules);
+    AUTOMATIONS.lock().unwrap().rules = rules.into_iter().map(RuleState::new).collect();
+}
+
+/// When the rules were evaluated last time, which is kept in the RTC memory while sleeping in the low-power mode.
+pub fn last_evaluated() -> Option<Timestamp> {
+    AUTOMATIONS.lock().unwrap().last_evaluated
+}
+
+/// Restore when the rules were evaluated before deep sleep, so that the times of day passed while sleeping fire.
+pub fn set_last_evaluated(timestamp: Timestamp) {
+    AUTOMATIONS.lock().unwrap().last_evaluated = Some(timestamp);
+}
+
+/// Wait for the actions running in the background, e.g. before deep sleep in the low-power mode.
+pub fn wait_actions(timeout: Duration) {
+    let running = RUNNING_ACTIONS.lock().unwrap();
+    let (running, result) = ACTIONS_DONE.wait_timeout_while(running, timeout, |running| *running > 0).unwrap();
+    if result.timed_out() {
+        log::warn!("{} automation(s) still running", *running);
+    }
+}
+
+fn action_done() {
+    *RUNNING_ACTIONS.lock().unwrap() -= 1;
+    ACTIONS_DONE.notify_all();
+}
+
+fn seconds_of_day(time: NaiveTime) -> i64 {
+    time.num_seconds_from_midnight() as i64
+}
+
+/// True if the time of day is in (from, to].
+fn has_passed(time: NaiveTime, from: Timestamp, to: Timestamp) -> bool {
+    let elapsed = (to - from).num_seconds();
+    if elapsed <= 0 || elapsed >= SECONDS_PER_DAY {
+        return false;
+    }
+    let until_time = (seconds_of_day(time) - seconds_of_day(clock::local(from).time())).rem_euclid(SECONDS_PER_DAY);
+    0 < until_time && until_time <= elapsed
+}
+
+/// Evaluate the rules with the sample, if any. Called from the sample task.
+pub fn evaluate(record: Option<&SensorRecord>, timestamp: Timestamp) {
+    let mut firings: heapless::Vec<(AutomationRule, Option<f32>), MAX_AUTOMATION_RULES> = heapless::Vec::new();
+    {
+        let mut automations = AUTOMATIONS.lock().unwrap();
+        let last_evaluated = automations.last_evaluated.replace(timestamp);
+        for state in automations.rules.iter_mut() {
+            match state.rule.trigger {
+                Trigger::At(time) => {
+                    if last_evaluated.map_or(false, |last_evaluated| has_passed(time, last_evaluated, timestamp)) {
+                        firings.push((state.rule.clone(), None)).ok();
+                    }
+                },
+                Trigger::Sensor(condition) => {
+                    let value = match record {
+                        Some(record) => condition.kind.value(record),
+                        None => continue,
+                    };
+                    if state.update(condition, value, timestamp) {
+                        firings.push((state.rule.clone(), Some(value))).ok();
+                    }
+                },
+            }
+        }
+    }
+    for (rule, value) in firings {
+        fire(rule, value, timestamp);
+    }
+}
+
+/// Body of the webhook for `notify`.
+fn notification(rule: &AutomationRule, value: Option<f32>, timestamp: Timestamp) -> String {
+    let mut body = String::from("{\"automation\":");
+    write_json_string(&mut body, &rule.text).ok();
+    write!(&mut body, ",\"timestamp\":\"{}\"", timestamp.to_rfc3339()).ok();
+    if let (Trigger::Sensor(condition), Some(value)) = (rule.trigger, value) {
+        write!(&mut body, ",\"sensor\":\"{}\",\"value\":{}", alert::kind_name(condition.kind), value).ok();
+    }
+    body.push('}');
+    body
+}
+
+fn run(rule: &AutomationRule, value: Option<f32>, timestamp: Timestamp) -> anyhow::Result<()> {
+    match &rule.action {
+        Action::Signal(id) => remote::send_signal(*id),
+        Action::Button(kind, id, button) => button_appliance::send_button(*id, *kind, button).map(|_| ()),
+        Action::Aircon(id, form) => aircon::send_form(*id, form),
+        Action::Notify => alert::send_webhook(&notification(rule, value, timestamp)),
+    }
+}
+
+fn fire(rule: AutomationRule, value: Option<f32>, timestamp: Timestamp) {
+    match value {
+        Some(value) => log::info!("Automation fired: \"{}\" (value: {})", rule.text, value),
+        None => log::info!("Automation fired: \"{}\"", rule.text),
+    }
+    // The Cloud API and the webhook must not delay the sampling.
+    *RUNNING_ACTIONS.lock().unwrap() += 1;
+    let launched = spawn_background("AUTOMATION", move || {
+        match run(&rule, value, timestamp) {
+            Ok(()) => log::info!("Automation done: \"{}\"", rule.text),
+            Err(err) => log::warn!("Automation failed: \"{}\" - {:?}", rule.text, err),
+        }
+        action_done();
+    });
+    if !launched {
+        action_done();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const ID: &str = "00000000-0000-0000-0000-000000000005";
+
+    /// The time of day in the local time zone, as `has_passed` compares it with the local time.
+    fn local(day: u32, hour: u32, minute: u32) -> Timestamp {
+        chrono::Local.with_ymd_and_hms(2024, 7, day, hour, minute, 0).unwrap().with_timezone(&chrono::Utc)
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn parse_rule() {
+        let rule = AutomationRule::parse(&format!("humidity < 40 for 5m then signal {}", ID)).unwrap();
+        assert!(matches!(rule.trigger, Trigger::Sensor(condition) if condition.kind == crate::ui::SensorKind::Humidity && condition.duration == Duration::from_secs(5 * 60)));
+        assert!(matches!(rule.action, Action::Signal(id) if id.to_string() == ID));
+
+        let rule = AutomationRule::parse(&format!("at 22:00 then aircon {} mode=dry temp=26&volume=auto", ID)).unwrap();
+        assert!(matches!(rule.trigger, Trigger::At(at) if at == time(22, 0)));
+        match rule.action {
+            Action::Aircon(_, form) => assert_eq!(form.split('&').count(), 3),
+            action => panic!("unexpected action {:?}", action),
+        }
+
+        let rule = AutomationRule::parse(&format!("power > 2500 then light {} on", ID)).unwrap();
+        assert!(matches!(rule.action, Action::Button(ButtonApplianceKind::Light, _, ref button) if button == "on"));
+        assert!(matches!(AutomationRule::parse("power > 2500 then notify").unwrap().action, Action::Notify));
+
+        assert!(AutomationRule::parse("power > 2500 notify").is_err());
+        assert!(AutomationRule::parse("at 25:00 then notify").is_err());
+        assert!(AutomationRule::parse("power > 2500 then notify now").is_err());
+        assert!(AutomationRule::parse("power > 2500 then signal living").is_err());
+        assert!(AutomationRule::parse(&format!("power > 2500 then tv {}", ID)).is_err());
+        assert!(AutomationRule::parse(&format!("at 7:00 then aircon {}", ID)).is_err());
+        assert!(AutomationRule::parse(&format!("at 7:00 then aircon {} warp=9", ID)).is_err());
+    }
+
+    #[test]
+    fn skip_invalid_rules() {
+        let rules = parse_rules("power > 2500 then notify; humidity < 40; ; at 6:30 then notify");
+        assert_eq!(rules.len(), 2);
+        assert!(matches!(rules[1].trigger, Trigger::At(at) if at == time(6, 30)));
+    }
+
+    #[test]
+    fn time_passed() {
+        assert!(has_passed(time(22, 0), local(1, 21, 59), local(1, 22, 0)));
+        assert!(!has_passed(time(22, 0), local(1, 22, 0), local(1, 22, 1)));
+        assert!(!has_passed(time(22, 0), local(1, 21, 0), local(1, 21, 59)));
+        // Across midnight.
+        assert!(has_passed(time(0, 0), local(1, 23, 59), local(2, 0, 0)));
+        assert!(has_passed(time(0, 30), local(1, 23, 50), local(2, 0, 40)));
+        assert!(has_passed(time(23, 55), local(1, 23, 50), local(2, 0, 40)));
+        assert!(!has_passed(time(1, 0), local(1, 23, 50), local(2, 0, 40)));
+        // Not evaluated for a day or more.
+        assert!(!has_passed(time(12, 0), local(1, 11, 0), local(2, 11, 0)));
+    }
+
+    #[test]
+    fn rearm_after_recovery() {
+        let rule = AutomationRule::parse("humidity < 40 then notify").unwrap();
+        let condition = match rule.trigger {
+            Trigger::Sensor(condition) => condition,
+            trigger => panic!("unexpected trigger {:?}", trigger),
+        };
+        let mut state = RuleState::new(rule);
+        let minute = |minute: u32| local(1, 12, minute);
+        assert!(state.update(condition, 39.0, minute(0)));
+        // Fires only once while the condition holds.
+        assert!(!state.update(condition, 38.0, minute(1)));
+        // Not recovered yet within the hysteresis.
+        assert!(!state.update(condition, 41.0, minute(2)));
+        assert!(!state.update(condition, 39.0, minute(3)));
+        assert!(!state.update(condition, 42.0, minute(4)));
+        assert!(state.update(condition, 39.0, minute(5)));
+    }
+}
@@ -0,0 +1,546 @@
+//! Mock server of the Nature Cloud API to test the update loop offline.
+//!
+//! Serves `/1/devices` and `/1/appliances` from the JSON templates in the data directory,
+//! with the `x-rate-limit-*` headers of the real API.
+//! `POST /1/appliances/{id}/aircon_settings` changes the settings of the simulated air conditioner,
+//! `POST /1/appliances/{id}/light` and `/tv` press the buttons of the simulated light and TV,
+//! and `POST /1/signals/{id}/send` accepts the signals in the template.
+//! The responses follow the scenario file, which can inject error statuses and slow responses.
+//! With `--echonet-port`, it also responds as an ECHONET Lite smart meter over UDP.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context};
+
+#[allow(dead_code)]
+#[path = "../echonet/frame.rs"]
+mod echonet_frame;
+use echonet_frame::{epc, Eoj, Esv, Frame, Property};
+
+/// Length of the rate limit window of the Cloud API.
+const RATE_LIMIT_WINDOW_SECS: u64 = 300;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    Ok,
+    Slow(Duration),
+    Status(u16),
+}
+
+impl Step {
+    fn parse(line: &str) -> anyhow::Result<Self> {
+        let mut tokens = line.split_whitespace();
+        match (tokens.next(), tokens.next()) {
+            (Some("ok"), None) => Ok(Step::Ok),
+            (Some("slow"), Some(millis)) => Ok(Step::Slow(Duration::from_millis(millis.parse()?))),
+            (Some("status"), Some(code)) => Ok(Step::Status(code.parse()?)),
+            _ => Err(anyhow!("invalid scenario step: {}", line)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Options {
+    port: u16,
+    data_dir: PathBuf,
+    pub scenario: Vec<Step>,
+    /// If set, requests without this access token are rejected with 401.
+    pub access_token: Option<String>,
+    pub rate_limit: usize,
+    device_id: String,
+    appliance_id: String,
+    aircon_id: String,
+    /// UDP port of the ECHONET Lite smart meter. Disabled if not set.
+    echonet_port: Option<u16>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            port: 8080,
+            data_dir: PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/mock")),
+            scenario: vec![Step::Ok],
+            access_token: None,
+            rate_limit: 30,
+            device_id: "00000000-0000-0000-0000-000000000001".into(),
+            appliance_id: "00000000-0000-0000-0000-000000000002".into(),
+            aircon_id: "00000000-0000-0000-0000-000000000005".into(),
+            echonet_port: None,
+        }
+    }
+}
+
+fn parse_args() -> anyhow::Result<Options> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow!("{} requires a value", arg));
+        match arg.as_str() {
+            "--port" => options.port = value()?.parse()?,
+            "--data-dir" => options.data_dir = PathBuf::from(value()?),
+            "--scenario" => {
+                let path = value()?;
+                let scenario = std::fs::read_to_string(&path).with_context(|| format!("failed to read {}", path))?;
+                options.scenario = scenario.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(Step::parse)
+                    .collect::<anyhow::Result<_>>()?;
+                if options.scenario.is_empty() {
+                    options.scenario.push(Step::Ok);
+                }
+            },
+            "--access-token" => options.access_token = Some(value()?),
+            "--rate-limit" => options.rate_limit = value()?.parse()?,
+            "--device-id" => options.device_id = value()?,
+            "--appliance-id" => options.appliance_id = value()?,
+            "--aircon-id" => options.aircon_id = value()?,
+            "--echonet-port" => options.echonet_port = Some(value()?.parse()?),
+            _ => return Err(anyhow!("unknown argument: {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+/// Rate limit per fixed window, same as the Cloud API.
+struct RateLimiter {
+    limit: usize,
+    remaining: usize,
+    reset: u64,
+}
+
+impl RateLimiter {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            remaining: limit,
+            reset: 0,
+        }
+    }
+
+    /// Consume a request. Returns false if the limit is exceeded.
+    fn consume(&mut self, now: u64) -> bool {
+        if now >= self.reset {
+            self.remaining = self.limit;
+            self.reset = (now / RATE_LIMIT_WINDOW_SECS + 1) * RATE_LIMIT_WINDOW_SECS;
+        }
+        if self.remaining == 0 {
+            return false;
+        }
+        self.remaining -= 1;
+        true
+    }
+}
+
+/// Settings of the simulated air conditioner, in the values of the Cloud API.
+#[derive(Clone, Debug)]
+struct AirconState {
+    temp: String,
+    mode: String,
+    vol: String,
+    button: String,
+}
+
+impl AirconState {
+    fn new() -> Self {
+        Self {
+            temp: "26".into(),
+            mode: "cool".into(),
+            vol: "auto".into(),
+            button: String::new(),
+        }
+    }
+
+    /// Apply the form of `POST /1/appliances/{id}/aircon_settings`. Unknown parameters are ignored.
+    fn apply(&mut self, form: &str) -> Result<(), String> {
+        let mut changed = self.clone();
+        for (key, value) in form.split('&').filter_map(|param| param.split_once('=')) {
+            match key {
+                "temperature" if value.is_empty() || value.parse::<f32>().is_ok() => changed.temp = value.into(),
+                "operation_mode" if ["cool", "warm", "dry", "blow", "auto"].contains(&value) => changed.mode = value.into(),
+                "air_volume" if ["auto", "1", "2", "3", "4", "5"].contains(&value) => changed.vol = value.into(),
+                "button" if ["", "power-off"].contains(&value) => changed.button = value.into(),
+                "temperature" | "operation_mode" | "air_volume" | "button" => return Err(format!("invalid {}: {}", key, value)),
+                _ => {},
+            }
+        }
+        *self = changed;
+        Ok(())
+    }
+
+    fn json(&self) -> String {
+        format!(
+            "{{\"temp\":\"{}\",\"temp_unit\":\"c\",\"mode\":\"{}\",\"vol\":\"{}\",\"dir\":\"auto\",\"dir_h\":\"\",\"button\":\"{}\",\"updated_at\":\"{}\"}}",
+            self.temp, self.mode, self.vol, self.button, chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+        )
+    }
+}
+
+/// TV buttons which change the input, and the inputs.
+const TV_INPUT_BUTTONS: [(&str, &str); 3] = [("input-terrestrial", "t"), ("input-bs", "bs"), ("input-cs", "cs")];
+
+/// State of the simulated light.
+#[derive(Clone, Debug)]
+struct LightState {
+    brightness: String,
+    power: String,
+    last_button: String,
+}
+
+impl LightState {
+    fn new() -> Self {
+        Self {
+            brightness: "100".into(),
+            power: "off".into(),
+            last_button: "off".into(),
+        }
+    }
+
+    /// Press the button. Buttons other than the power ones only change `last_button`.
+    fn press(&mut self, button: &str) {
+        match button {
+            "on" | "on-favorite" => self.power = "on".into(),
+            "off" => self.power = "off".into(),
+            "onoff" => self.power = if self.power == "on" { "off" } else { "on" }.into(),
+            "on-100" => {
+                self.power = "on".into();
+                self.brightness = "100".into();
+            },
+            _ => {},
+        }
+        self.last_button = button.into();
+    }
+
+    fn json(&self) -> String {
+        format!("{{\"brightness\":\"{}\",\"power\":\"{}\",\"last_button\":\"{}\"}}", self.brightness, self.power, self.last_button)
+    }
+}
+
+enum Route {
+    /// Respond with the JSON template in the data directory.
+    Template(&'static str),
+    AirconSettings,
+    Light,
+    Tv,
+    SendSignal,
+}
+
+struct Server {
+    options: Options,
+    rate_limiter: Mutex<RateLimiter>,
+    step: Mutex<usize>,
+    aircon: Mutex<AirconState>,
+    light: Mutex<LightState>,
+    /// Input of the simulated TV.
+    tv_input: Mutex<String>,
+}
+
+impl Server {
+    fn next_step(&self) -> Step {
+        let mut step = self.step.lock().unwrap();
+        let current = self.options.scenario[*step % self.options.scenario.len()];
+        *step += 1;
+        current
+    }
+
+    /// Fill the placeholders of the template. The sensor values change slowly with time.
+    fn render(&self, template: &str, now: u64) -> String {
+        let t = now as f32;
+        template
+            .replace("{{device_id}}", &self.options.device_id)
+            .replace("{{appliance_id}}", &self.options.appliance_id)
+            .replace("{{aircon_id}}", &self.options.aircon_id)
+            .replace("{{aircon_settings}}", &self.aircon.lock().unwrap().json())
+            .replace("{{light_state}}", &self.light.lock().unwrap().json())
+            .replace("{{tv_input}}", &self.tv_input.lock().unwrap())
+            .replace("{{now}}", &chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string())
+            .replace("{{temperature}}", &format!("{:.1}", 24.0 + 2.0 * (t / 600.0).sin()))
+            .replace("{{humidity}}", &format!("{:.0}", 50.0 + 10.0 * (t / 900.0).sin()))
+            .replace("{{illuminance}}", &format!("{:.0}", 100.0 + 50.0 * (t / 300.0).sin()))
+            .replace("{{power}}", &format!("{:.0}", 400.0 + 300.0 * (t / 120.0).sin().abs()))
+            .replace("{{cumulative_energy}}", &format!("{}", now / 360 % 1_000_000))
+    }
+
+    /// True if the path is `{prefix}{id}{suffix}` and the ID is in the appliances template.
+    fn is_known_id(&self, path: &str, prefix: &str, suffix: &str) -> bool {
+        let id = match path.strip_prefix(prefix).and_then(|path| path.strip_suffix(suffix)) {
+            Some(id) => id,
+            None => return false,
+        };
+        std::fs::read_to_string(self.options.data_dir.join("appliances.json"))
+            .map(|template| template.contains(&format!("\"id\": \"{}\"", id)))
+            .unwrap_or(false)
+    }
+
+    fn handle(&self, stream: TcpStream) -> anyhow::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let method = request_line.split_whitespace().next().unwrap_or("").to_string();
+        let path = request_line.split_whitespace().nth(1).unwrap_or("").to_string();
+        let mut authorization = None;
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("authorization") {
+                    authorization = Some(value.trim().to_string());
+                } else if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse()?;
+                }
+            }
+        }
+
+        let mut request_body = vec![0u8; content_length];
+        reader.read_exact(&mut request_body)?;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let aircon_settings_path = format!("/1/appliances/{}/aircon_settings", self.options.aircon_id);
+        let route = match (method.as_str(), path.as_str()) {
+            ("GET", "/1/devices") => Some(Route::Template("devices.json")),
+            ("GET", "/1/appliances") => Some(Route::Template("appliances.json")),
+            ("POST", path) if path == aircon_settings_path => Some(Route::AirconSettings),
+            ("POST", path) if self.is_known_id(path, "/1/appliances/", "/light") => Some(Route::Light),
+            ("POST", path) if self.is_known_id(path, "/1/appliances/", "/tv") => Some(Route::Tv),
+            ("POST", path) if self.is_known_id(path, "/1/signals/", "/send") => Some(Route::SendSignal),
+            _ => None,
+        };
+        let expected_authorization = self.options.access_token.as_ref().map(|token| format!("Bearer {}", token));
+        let (status, body) = if route.is_none() {
+            (404, String::new())
+        } else if expected_authorization.is_some() && authorization != expected_authorization {
+            (401, String::new())
+        } else if !self.rate_limiter.lock().unwrap().consume(now) {
+            (429, String::new())
+        } else {
+            match self.next_step() {
+                Step::Status(status) => (status, String::new()),
+                step => {
+                    if let Step::Slow(delay) = step {
+                        std::thread::sleep(delay);
+                    }
+                    match route.unwrap() {
+                        Route::Template(template) => {
+                            let template = std::fs::read_to_string(self.options.data_dir.join(template))?;
+                            (200, self.render(&template, now))
+                        },
+                        Route::AirconSettings => {
+                            let mut aircon = self.aircon.lock().unwrap();
+                            match aircon.apply(&String::from_utf8_lossy(&request_body)) {
+                                Ok(()) => (200, aircon.json()),
+                                Err(message) => (400, format!("{{\"code\":400001,\"message\":\"{}\"}}", message)),
+                            }
+                        },
+                        route @ (Route::Light | Route::Tv) => {
+                            let form = String::from_utf8_lossy(&request_body).into_owned();
+                            let button = form.split('&').find_map(|param| param.strip_prefix("button=")).unwrap_or("");
+                            if button.is_empty() {
+                                (400, "{\"code\":400001,\"message\":\"button is required\"}".into())
+                            } else if let Route::Light = route {
+                                let mut light = self.light.lock().unwrap();
+                                light.press(button);
+                                (200, light.json())
+                            } else {
+                                let mut tv_input = self.tv_input.lock().unwrap();
+                                if let Some((_, input)) = TV_INPUT_BUTTONS.iter().find(|(name, _)| *name == button) {
+                                    *tv_input = input.to_string();
+                                }
+                                (200, format!("{{\"input\":\"{}\"}}", tv_input))
+                            }
+                        },
+                        Route::SendSignal => (200, "{}".into()),
+                    }
+                },
+            }
+        };
+        log::info!("{} -> {}", request_line.trim(), status);
+
+        let (limit, remaining, reset) = {
+            let rate_limiter = self.rate_limiter.lock().unwrap();
+            (rate_limiter.limit, rate_limiter.remaining, rate_limiter.reset)
+        };
+        let mut stream = stream;
+        write!(stream, "HTTP/1.1 {} {}\r\n", status, reason_phrase(status))?;
+        write!(stream, "content-type: application/json; charset=utf-8\r\n")?;
+        write!(stream, "content-length: {}\r\n", body.len())?;
+        write!(stream, "x-rate-limit-limit: {}\r\n", limit)?;
+        write!(stream, "x-rate-limit-remaining: {}\r\n", remaining)?;
+        write!(stream, "x-rate-limit-reset: {}\r\n", reset)?;
+        write!(stream, "connection: close\r\n\r\n")?;
+        stream.write_all(body.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "",
+    }
+}
+
+/// Interval of the INF notifications of the instantaneous power.
+const ECHONET_NOTIFICATION_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Property value of the simulated smart meter. Returns None for unsupported properties.
+fn smart_meter_property(epc: u8, now: u64) -> Option<Vec<u8>> {
+    let t = now as f32;
+    match epc {
+        epc::OPERATION_STATUS => Some(vec![0x30]),
+        epc::INSTANTANEOUS_POWER => Some(((400.0 + 300.0 * (t / 120.0).sin().abs()) as i32).to_be_bytes().to_vec()),
+        epc::COEFFICIENT => Some(1u32.to_be_bytes().to_vec()),
+        epc::CUMULATIVE_ENERGY_EFFECTIVE_DIGITS => Some(vec![6]),
+        epc::NORMAL_DIRECTION_CUMULATIVE_ENERGY => Some(((now / 360 % 1_000_000) as u32).to_be_bytes().to_vec()),
+        // 0.1 kWh
+        epc::CUMULATIVE_ENERGY_UNIT => Some(vec![0x01]),
+        _ => None,
+    }
+}
+
+/// Build the response to a Get request. Get_SNA is returned if any property is not available.
+fn echonet_response(request: &Frame, now: u64) -> Option<Frame> {
+    if request.esv != Esv::Get {
+        return None;
+    }
+    let mut properties = heapless::Vec::new();
+    let mut available = true;
+    for property in request.properties.iter() {
+        let edt = if request.deoj.is_same_class(&Eoj::NODE_PROFILE) {
+            match property.epc {
+                epc::SELF_NODE_INSTANCE_LIST_S => Some([&[1u8][..], &Eoj::LOW_VOLTAGE_SMART_METER.0].concat()),
+                _ => None,
+            }
+        } else if request.deoj.is_same_class(&Eoj::LOW_VOLTAGE_SMART_METER) {
+            smart_meter_property(property.epc, now)
+        } else {
+            return None;
+        };
+        available &= edt.is_some();
+        let property = Property::new(property.epc, edt.as_deref().unwrap_or(&[])).ok()?;
+        properties.push(property).ok();
+    }
+    Some(Frame {
+        tid: request.tid,
+        seoj: request.deoj,
+        deoj: request.seoj,
+        esv: if available { Esv::GetRes } else { Esv::GetSna },
+        properties,
+    })
+}
+
+fn send_frame(socket: &UdpSocket, frame: &Frame, address: SocketAddr) -> anyhow::Result<()> {
+    let mut buffer = [0u8; 1500];
+    let length = frame.encode(&mut buffer)?;
+    socket.send_to(&buffer[..length], address)?;
+    Ok(())
+}
+
+/// Respond to the ECHONET Lite requests and notify the instantaneous power to the controllers periodically.
+fn echonet_responder(port: u16) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind(("0.0.0.0", port))?;
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    log::info!("ECHONET Lite smart meter on {}", socket.local_addr()?);
+    let mut controllers: Vec<SocketAddr> = Vec::new();
+    let mut last_notification = std::time::Instant::now();
+    let mut tid = 0u16;
+    let mut buffer = [0u8; 1500];
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((length, address)) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                match Frame::decode(&buffer[..length]) {
+                    Ok(request) => {
+                        if let Some(response) = echonet_response(&request, now) {
+                            log::info!("ECHONET Lite {:?} from {} -> {:?}", request.esv, address, response.esv);
+                            send_frame(&socket, &response, address)?;
+                        }
+                        if !controllers.contains(&address) {
+                            controllers.push(address);
+                        }
+                    },
+                    Err(err) => log::warn!("invalid ECHONET Lite frame from {} - {}", address, err),
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock || err.kind() == std::io::ErrorKind::TimedOut => {},
+            Err(err) => return Err(err.into()),
+        }
+        if last_notification.elapsed() >= ECHONET_NOTIFICATION_INTERVAL {
+            last_notification = std::time::Instant::now();
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            tid = tid.wrapping_add(1);
+            let mut properties = heapless::Vec::new();
+            properties.push(Property::new(epc::INSTANTANEOUS_POWER, &smart_meter_property(epc::INSTANTANEOUS_POWER, now).unwrap())?).ok();
+            let notification = Frame {
+                tid,
+                seoj: Eoj::LOW_VOLTAGE_SMART_METER,
+                deoj: Eoj::CONTROLLER,
+                esv: Esv::Inf,
+                properties,
+            };
+            for address in controllers.iter() {
+                send_frame(&socket, &notification, *address)?;
+            }
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let options = parse_args()?;
+    log::info!("options: {:?}", options);
+    if let Some(port) = options.echonet_port {
+        std::thread::spawn(move || {
+            if let Err(err) = echonet_responder(port) {
+                log::error!("ECHONET Lite responder failed - {:?}", err);
+            }
+        });
+    }
+    let listener = TcpListener::bind(("0.0.0.0", options.port))?;
+    log::info!("listening on {}", listener.local_addr()?);
+    serve(listener, options);
+    Ok(())
+}
+
+/// Serve the Cloud API on the listener. The main crate also runs it in its tests.
+pub fn serve(listener: TcpListener, options: Options) {
+    let server = Arc::new(Server {
+        rate_limiter: Mutex::new(RateLimiter::new(options.rate_limit)),
+        step: Mutex::new(0),
+        aircon: Mutex::new(AirconState::new()),
+        light: Mutex::new(LightState::new()),
+        tv_input: Mutex::new("t".into()),
+        options,
+    });
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::error!("accept failed - {:?}", err);
+                continue;
+            },
+        };
+        let server = server.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = server.handle(stream) {
+                log::error!("request failed - {:?}", err);
+            }
+        });
+    }
+}
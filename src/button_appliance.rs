@@ -0,0 +1,175 @@
+//! Control of the lights and TVs registered to the Cloud API.
+//!
+//! These appliances are operated by pressing the buttons defined for them, e.g. `on` or `ch-up`,
+//! with `POST /1/appliances/{id}/light` or `POST /1/appliances/{id}/tv`.
+//! The Cloud API returns the state after the press, which replaces the shown state.
+
+use std::{fmt::Write, sync::Mutex};
+
+use fuga_remo_api::{Appliance, ApplianceSubNode};
+use uuid::Uuid;
+
+use crate::{api_post, copy_truncated, input::{self, InputEvent}, json_string_of, spawn_background};
+
+pub const MAX_BUTTON_APPLIANCES: usize = 4;
+pub const MAX_APPLIANCE_BUTTONS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ButtonApplianceKind {
+    Light,
+    Tv,
+}
+
+impl ButtonApplianceKind {
+    /// Last segment of the endpoint to press a button.
+    fn endpoint(&self) -> &'static str {
+        match self {
+            ButtonApplianceKind::Light => "light",
+            ButtonApplianceKind::Tv => "tv",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ApplianceButton {
+    /// Name sent to the Cloud API.
+    pub name: heapless::String<32>,
+    /// Label set by the user, which is empty for the predefined buttons.
+    pub label: heapless::String<32>,
+}
+
+impl ApplianceButton {
+    pub fn text(&self) -> &str {
+        if self.label.is_empty() { &self.name } else { &self.label }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ButtonAppliance {
+    pub id: Uuid,
+    pub kind: ButtonApplianceKind,
+    pub nickname: heapless::String<64>,
+    pub buttons: heapless::Vec<ApplianceButton, MAX_APPLIANCE_BUTTONS>,
+    /// Summary of the state, e.g. "on 100" for a light or "t" (the input) for a TV.
+    pub state: heapless::String<32>,
+    /// Index of the button being sent.
+    pub pending: Option<usize>,
+    /// Error of the last press, cleared by the next press.
+    pub error: Option<heapless::String<64>>,
+}
+
+pub type ButtonAppliances = heapless::Vec<ButtonAppliance, MAX_BUTTON_APPLIANCES>;
+
+/// Lights and TVs, shown on their control pages.
+pub static BUTTON_APPLIANCES: Mutex<ButtonAppliances> = Mutex::new(heapless::Vec::new());
+
+fn light_state(power: &str, brightness: &str) -> heapless::String<32> {
+    let mut state = heapless::String::new();
+    write!(&mut state, "{} {}", power, brightness).ok();
+    state
+}
+
+/// Extract the state from the response of the button press.
+fn parse_state(kind: ButtonApplianceKind, body: &str) -> Option<heapless::String<32>> {
+    let string_of = |key: &str| json_string_of(body, key);
+    match kind {
+        ButtonApplianceKind::Light => Some(light_state(string_of("\"power\"")?, string_of("\"brightness\"")?)),
+        ButtonApplianceKind::Tv => Some(copy_truncated(string_of("\"input\"")?)),
+    }
+}
+
+/// Add the light or the TV and its buttons read from a sub node of `GET /1/appliances`.
+pub fn collect(appliances: &mut ButtonAppliances, appliance: &Appliance, sub_node: Option<&ApplianceSubNode>) {
+    if !appliances.iter().any(|known| known.id == appliance.id) {
+        let (kind, state) = if let Some(light) = appliance.light.as_ref() {
+            (ButtonApplianceKind::Light, light_state(&light.power, &light.brightness))
+        } else if let Some(tv) = appliance.tv.as_ref() {
+            (ButtonApplianceKind::Tv, copy_truncated(&tv.input))
+        } else {
+            return;
+        };
+        let added = appliances.push(ButtonAppliance {
+            id: appliance.id,
+            kind,
+            nickname: appliance.nickname.clone(),
+            buttons: heapless::Vec::new(),
+            state,
+            pending: None,
+            error: None,
+        });
+        if added.is_err() {
+            log::warn!("Too many lights and TVs. {} is ignored", appliance.nickname);
+            return;
+        }
+    }
+    let button = match sub_node {
+        Some(ApplianceSubNode::LightButton(button)) | Some(ApplianceSubNode::TvButton(button)) => button,
+        _ => return,
+    };
+    if let Some(target) = appliances.iter_mut().find(|known| known.id == appliance.id) {
+        if !target.buttons.iter().any(|known| known.name == button.name) {
+            target.buttons.push(ApplianceButton {
+                name: copy_truncated(&button.name),
+                label: copy_truncated(&button.label),
+            }).ok();
+        }
+    }
+}
+
+/// Update the list with the appliances read from the Cloud API, keeping the buttons being sent.
+pub fn update_appliances(appliances: ButtonAppliances) {
+    let mut current = BUTTON_APPLIANCES.lock().unwrap();
+    let previous = core::mem::replace(&mut *current, appliances);
+    for appliance in current.iter_mut() {
+        if let Some(previous) = previous.iter().find(|previous| previous.id == appliance.id) {
+            appliance.pending = previous.pending;
+            appliance.error = previous.error.clone();
+        }
+    }
+}
+
+pub fn send_button(id: Uuid, kind: ButtonApplianceKind, button: &str) -> anyhow::Result<Option<heapless::String<32>>> {
+    let mut path = heapless::String::<64>::new();
+    write!(&mut path, "/1/appliances/{}/{}", id, kind.endpoint()).ok();
+    let body = api_post(&path, &format!("button={}", button))?;
+    Ok(parse_state(kind, &String::from_utf8_lossy(&body)))
+}
+
+/// Press the button of the appliance in the background.
+/// Returns false if there is no such button or the previous press is being sent.
+pub fn press(id: Uuid, button_index: usize) -> bool {
+    let (kind, name) = {
+        let mut appliances = BUTTON_APPLIANCES.lock().unwrap();
+        let appliance = match appliances.iter_mut().find(|appliance| appliance.id == id) {
+            Some(appliance) if appliance.pending.is_none() => appliance,
+            _ => return false,
+        };
+        let name = match appliance.buttons.get(button_index) {
+            Some(button) => button.name.clone(),
+            None => return false,
+        };
+        appliance.pending = Some(button_index);
+        appliance.error = None;
+        (appliance.kind, name)
+    };
+    log::info!("{} {} button {}", kind.endpoint(), id, name);
+    spawn_background("BUTTON", move || {
+        let result = send_button(id, kind, &name);
+        let mut appliances = BUTTON_APPLIANCES.lock().unwrap();
+        if let Some(appliance) = appliances.iter_mut().find(|appliance| appliance.id == id) {
+            appliance.pending = None;
+            match result {
+                Ok(Some(state)) => appliance.state = state,
+                Ok(None) => {},
+                Err(err) => {
+                    log::warn!("Failed to press the button - {:?}", err);
+                    let mut error = heapless::String::new();
+                    write!(&mut error, "{}", err).ok();
+                    appliance.error = Some(error);
+                },
+            }
+        }
+        input::push_event(InputEvent::Refresh);
+    });
+    true
+}
@@ -0,0 +1,85 @@
+use lgfx::{self, ColorRgb332, DrawPrimitives, DrawString, FontManupulation, LgfxGuard, textdatum_top_left, textdatum_top_right};
+
+/// RGB332 color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Color(pub u8);
+
+impl Color {
+    pub const BLACK: Color = Color(0x00);
+    pub const WHITE: Color = Color(0xff);
+
+    pub fn to_rgb888(&self) -> [u8; 3] {
+        let r = (self.0 >> 5) & 0x07;
+        let g = (self.0 >> 2) & 0x07;
+        let b = self.0 & 0x03;
+        [(r as u16 * 255 / 7) as u8, (g as u16 * 255 / 7) as u8, (b as u16 * 255 / 3) as u8]
+    }
+}
+
+impl From<Color> for ColorRgb332 {
+    fn from(color: Color) -> Self {
+        ColorRgb332::new(color.0)
+    }
+}
+
+/// Fonts used by the UI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FontFace {
+    FreeMono24,
+    FreeSans18,
+    JapanGothicP28,
+    JapanGothicP32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// Drawing operations used by the UI.
+/// Implemented for the LovyanGFX display and the in-memory framebuffer.
+pub trait Canvas {
+    fn clear(&mut self, color: Color);
+    fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: Color);
+    fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color);
+    fn set_font(&mut self, font: FontFace);
+    fn font_height(&mut self) -> i32;
+    /// Draw a string with the current font. `y` is the top of the text.
+    fn draw_string(&mut self, text: &str, x: i32, y: i32, color: Color, background: Color, scale: f32, align: Align);
+}
+
+impl<'a> Canvas for LgfxGuard<'a> {
+    fn clear(&mut self, color: Color) {
+        DrawPrimitives::clear(self, ColorRgb332::from(color));
+    }
+
+    fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: Color) {
+        DrawPrimitives::fill_rect(self, x, y, width, height, ColorRgb332::from(color));
+    }
+
+    fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+        DrawPrimitives::draw_line(self, x0, y0, x1, y1, ColorRgb332::from(color));
+    }
+
+    fn set_font(&mut self, font: FontFace) {
+        match font {
+            FontFace::FreeMono24 => FontManupulation::set_font(self, lgfx::fonts::FreeMono24pt7b).ok(),
+            FontFace::FreeSans18 => FontManupulation::set_font(self, lgfx::fonts::FreeSans18pt7b).ok(),
+            FontFace::JapanGothicP28 => FontManupulation::set_font(self, lgfx::fonts::lgfxJapanGothicP_28).ok(),
+            FontFace::JapanGothicP32 => FontManupulation::set_font(self, lgfx::fonts::lgfxJapanGothicP_32).ok(),
+        };
+    }
+
+    fn font_height(&mut self) -> i32 {
+        FontManupulation::font_height(self)
+    }
+
+    fn draw_string(&mut self, text: &str, x: i32, y: i32, color: Color, background: Color, scale: f32, align: Align) {
+        let (color, background) = (ColorRgb332::from(color), ColorRgb332::from(background));
+        match align {
+            Align::Left => DrawString::draw_string(self, text, x, y, background, color, scale, scale, textdatum_top_left),
+            Align::Right => DrawString::draw_string(self, text, x, y, background, color, scale, scale, textdatum_top_right),
+        }
+    }
+}
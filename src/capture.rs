@@ -0,0 +1,185 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use anyhow::anyhow;
+use chrono::TimeZone;
+
+use crate::{clock, RateLimitInfo, Timestamp};
+
+const CAPTURE_HEADER: &str = "# remo-monitor capture v1";
+
+/// A Cloud API response in the capture file.
+///
+/// Each entry is stored as a header line followed by the raw body:
+/// `@ <unix time in ms> <path> <status> <limit> <remaining> <reset> <body length>`.
+/// Missing rate limit values are written as `-`.
+#[derive(Clone, Debug)]
+pub struct CaptureEntry {
+    pub time_millis: u64,
+    pub path: String,
+    pub status: u16,
+    pub rate_limit: RateLimitInfo,
+    pub body: Vec<u8>,
+}
+
+fn write_optional<W: Write, T: std::fmt::Display>(writer: &mut W, value: Option<T>) -> std::io::Result<()> {
+    match value {
+        Some(value) => write!(writer, " {}", value),
+        None => write!(writer, " -"),
+    }
+}
+
+fn parse_optional<T: std::str::FromStr>(value: Option<&str>) -> anyhow::Result<Option<T>> {
+    match value {
+        Some("-") => Ok(None),
+        Some(value) => value.parse().map(Some).map_err(|_| anyhow!("invalid value {}", value)),
+        None => Err(anyhow!("missing field")),
+    }
+}
+
+impl CaptureEntry {
+    fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        write!(writer, "@ {} {} {}", self.time_millis, self.path, self.status)?;
+        write_optional(writer, self.rate_limit.limit)?;
+        write_optional(writer, self.rate_limit.remaining)?;
+        write_optional(writer, self.rate_limit.reset)?;
+        writeln!(writer, " {}", self.body.len())?;
+        writer.write_all(&self.body)?;
+        writeln!(writer)
+    }
+
+    /// Wait until the time of the response. The timing follows the clock, which can be accelerated.
+    pub fn wait(&self) {
+        if let Some(time) = timestamp_from_millis(self.time_millis) {
+            if let Ok(wait) = (time - clock::now()).to_std() {
+                clock::sleep(wait);
+            }
+        }
+    }
+
+    /// Read the next entry. Returns `None` at the end of the file.
+    fn read<R: BufRead>(reader: &mut R) -> anyhow::Result<Option<Self>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                break;
+            }
+        }
+        let mut fields = line.split_whitespace();
+        if fields.next() != Some("@") {
+            return Err(anyhow!("invalid entry header: {}", line.trim()));
+        }
+        let time_millis = parse_optional(fields.next())?.ok_or_else(|| anyhow!("missing time"))?;
+        let path = fields.next().ok_or_else(|| anyhow!("missing path"))?.to_string();
+        let status = parse_optional(fields.next())?.ok_or_else(|| anyhow!("missing status"))?;
+        let rate_limit = RateLimitInfo {
+            limit: parse_optional(fields.next())?,
+            remaining: parse_optional(fields.next())?,
+            reset: parse_optional(fields.next())?,
+        };
+        let length: usize = parse_optional(fields.next())?.ok_or_else(|| anyhow!("missing body length"))?;
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        Ok(Some(Self {
+            time_millis,
+            path,
+            status,
+            rate_limit,
+            body,
+        }))
+    }
+}
+
+/// Appends the Cloud API responses to the capture file.
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", CAPTURE_HEADER)?;
+        writer.flush()?;
+        Ok(Self { writer })
+    }
+
+    /// Append the response with the time of the clock, which may be simulated.
+    pub fn record(&mut self, path: &str, status: u16, rate_limit: RateLimitInfo, body: Vec<u8>) -> anyhow::Result<()> {
+        let entry = CaptureEntry {
+            time_millis: clock::now().timestamp_millis().max(0) as u64,
+            path: path.to_string(),
+            status,
+            rate_limit,
+            body,
+        };
+        entry.write(&mut self.writer)?;
+        // Flush each entry so that the capture survives a crash.
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn timestamp_from_millis(millis: u64) -> Option<Timestamp> {
+    chrono::Utc.timestamp_millis_opt(millis as i64).single()
+}
+
+/// Feeds the captured responses back in the captured order.
+/// The caller waits for the captured timing with `CaptureEntry::wait`, without holding `REPLAYER`.
+pub struct Replayer {
+    entries: VecDeque<CaptureEntry>,
+}
+
+impl Replayer {
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut entries = VecDeque::new();
+        while let Some(entry) = CaptureEntry::read(&mut reader)? {
+            entries.push_back(entry);
+        }
+        log::info!("{} captured responses loaded", entries.len());
+        Ok(Self { entries })
+    }
+
+    /// Time of the first captured response.
+    pub fn start_time(&self) -> Option<Timestamp> {
+        self.entries.front().and_then(|entry| timestamp_from_millis(entry.time_millis))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Take the next response for the path.
+    /// The replay ends when the path has no response left, since the update loop cannot go on without it.
+    pub fn next(&mut self, path: &str) -> Option<CaptureEntry> {
+        match self.entries.iter().position(|entry| entry.path == path) {
+            Some(index) => self.entries.remove(index),
+            None => {
+                if !self.entries.is_empty() {
+                    log::warn!("No captured response left for {}. The replay ends with {} response(s) unused", path, self.entries.len());
+                    self.entries.clear();
+                }
+                None
+            },
+        }
+    }
+}
+
+pub static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+pub static REPLAYER: Mutex<Option<Replayer>> = Mutex::new(None);
+
+/// True while the captured responses are being replayed.
+/// The replay paces the update loop instead of the fixed interval.
+pub fn is_replaying() -> bool {
+    REPLAYER.lock().unwrap().as_ref().map(|replayer| !replayer.is_finished()).unwrap_or(false)
+}
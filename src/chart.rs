@@ -1,17 +1,14 @@
-use lgfx::{self, ColorRgb332, Gfx, LgfxGuard};
-use lgfx::{DrawImage, DrawPrimitives};
-use crate::lgfx::{EpdMode, DrawChars, FontManupulation, LgfxDisplay};
-use anyhow::Result;
+use crate::canvas::{Canvas, Color};
 
 pub struct Chart {
     width: i32,
     height: i32,
-    foreground: ColorRgb332,
-    background: ColorRgb332,
+    foreground: Color,
+    background: Color,
 }
 
 impl Chart {
-    pub fn new(width: i32, height: i32, foreground: ColorRgb332, background: ColorRgb332) -> Self {
+    pub fn new(width: i32, height: i32, foreground: Color, background: Color) -> Self {
         Self {
             width,
             height,
@@ -20,7 +17,7 @@ impl Chart {
         }
     }
 
-    pub fn draw<D: DrawPrimitives<ColorRgb332>, F: FnMut(usize) -> Option<f32>>(&self, target: &mut D, left: i32, top: i32, item_count: usize, min_value: f32, max_value: f32, mut values: F) -> anyhow::Result<()> {
+    pub fn draw<D: Canvas + ?Sized, F: FnMut(usize) -> Option<f32>>(&self, target: &mut D, left: i32, top: i32, item_count: usize, min_value: f32, max_value: f32, mut values: F) -> anyhow::Result<()> {
         if item_count == 0 {
             return Ok(());
         }
@@ -0,0 +1,126 @@
+use std::{sync::Mutex, time::{Duration, Instant}};
+
+use chrono::Timelike;
+
+use crate::Timestamp;
+
+/// Clock which runs `speed` times faster than the real time from `origin`.
+struct Simulation {
+    speed: f64,
+    real_origin: Instant,
+    origin: Timestamp,
+}
+
+static SIMULATION: Mutex<Option<Simulation>> = Mutex::new(None);
+
+/// Run the clock `speed` times faster than the real time, starting from `start`.
+/// Must be called before the tasks start.
+pub fn simulate(speed: f64, start: Timestamp) {
+    log::info!("simulated clock: x{} from {}", speed, start);
+    *SIMULATION.lock().unwrap() = Some(Simulation {
+        speed,
+        real_origin: Instant::now(),
+        origin: start,
+    });
+}
+
+/// Current time of the clock. Same as the system time unless simulated.
+pub fn now() -> Timestamp {
+    match SIMULATION.lock().unwrap().as_ref() {
+        Some(simulation) => {
+            let elapsed = simulation.real_origin.elapsed().mul_f64(simulation.speed);
+            simulation.origin + chrono::Duration::from_std(elapsed).unwrap_or_else(|_| chrono::Duration::zero())
+        },
+        None => chrono::Utc::now(),
+    }
+}
+
+/// Real time which `duration` of the clock takes.
+pub fn real_duration(duration: Duration) -> Duration {
+    match SIMULATION.lock().unwrap().as_ref() {
+        Some(simulation) => duration.div_f64(simulation.speed),
+        None => duration,
+    }
+}
+
+/// Real time until the minute of the clock changes.
+pub fn until_next_minute() -> Duration {
+    let now = now();
+    let elapsed = Duration::new(now.second() as u64, now.nanosecond().min(999_999_999));
+    real_duration(Duration::from_secs(60).saturating_sub(elapsed))
+}
+
+/// Sleep for `duration` of the clock.
+pub fn sleep(duration: Duration) {
+    std::thread::sleep(real_duration(duration));
+}
+
+/// Set the time zone as a POSIX TZ string, e.g. "JST-9". Must be called before the tasks start.
+pub fn set_time_zone(time_zone: &str) {
+    std::env::set_var("TZ", time_zone);
+    #[cfg(target_os="espidf")]
+    unsafe {
+        esp_idf_sys::tzset();
+    }
+}
+
+/// The time in the configured time zone, to show it.
+pub fn local(timestamp: Timestamp) -> chrono::DateTime<chrono::Local> {
+    timestamp.with_timezone(&chrono::Local)
+}
+
+#[cfg(target_os="espidf")]
+static SNTP: Mutex<Option<esp_idf_svc::sntp::EspSntp>> = Mutex::new(None);
+/// Set once the clock is synchronized, since the status of SNTP is reset after it is read.
+#[cfg(target_os="espidf")]
+static SYNCED: Mutex<bool> = Mutex::new(false);
+/// `RTC_SYNCED_MAGIC` once the clock is synchronized. The RTC keeps the system time while in deep sleep,
+/// so the clock is still synchronized after the wakeup in the low-power mode.
+#[cfg(target_os="espidf")]
+#[link_section = ".rtc_noinit"]
+static mut RTC_SYNCED: u32 = 0;
+#[cfg(target_os="espidf")]
+const RTC_SYNCED_MAGIC: u32 = 0x4e54_5053;
+
+/// Start synchronizing the system clock with SNTP. It keeps synchronizing in the background.
+#[cfg(target_os="espidf")]
+pub fn start_sync() {
+    // The RTC memory is not initialized after the power is turned on.
+    let is_deep_sleep_wakeup = unsafe { esp_idf_sys::esp_reset_reason() } == esp_idf_sys::esp_reset_reason_t_ESP_RST_DEEPSLEEP;
+    // Only the main task accesses RTC_SYNCED before the other tasks start.
+    if is_deep_sleep_wakeup && unsafe { RTC_SYNCED } == RTC_SYNCED_MAGIC {
+        log::info!("Clock synchronized before deep sleep: {}", local(chrono::Utc::now()));
+        *SYNCED.lock().unwrap() = true;
+    } else {
+        unsafe { RTC_SYNCED = 0 };
+    }
+    match esp_idf_svc::sntp::EspSntp::new_default() {
+        Ok(sntp) => *SNTP.lock().unwrap() = Some(sntp),
+        Err(err) => log::error!("Failed to start SNTP - {:?}", err),
+    }
+}
+/// The host synchronizes its clock.
+#[cfg(target_os="linux")]
+pub fn start_sync() {
+}
+
+/// True once the system clock has been synchronized.
+#[cfg(target_os="espidf")]
+pub fn is_synced() -> bool {
+    let mut synced = SYNCED.lock().unwrap();
+    if !*synced {
+        let completed = SNTP.lock().unwrap().as_ref()
+            .map_or(false, |sntp| sntp.get_sync_status() == esp_idf_svc::sntp::SyncStatus::Completed);
+        if completed {
+            log::info!("Clock synchronized: {}", local(chrono::Utc::now()));
+            *synced = true;
+            // Written while holding SYNCED.
+            unsafe { RTC_SYNCED = RTC_SYNCED_MAGIC };
+        }
+    }
+    *synced
+}
+#[cfg(target_os="linux")]
+pub fn is_synced() -> bool {
+    true
+}
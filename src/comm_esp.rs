@@ -1,6 +1,6 @@
-use std::fmt::Write;
+use std::{fmt::Write, sync::Mutex, time::Duration};
 
-use crate::{RateLimitInfo};
+use crate::{http_api::ApiResponse, RateLimitInfo, HTTP_TIMEOUT};
 
 use esp_idf_sys as _; // If using the `binstart` feature of `esp-idf-sys`, always keep this module imported
 
@@ -14,6 +14,7 @@ pub fn fetch_http_and_parse<F, ParserResult>(url: &str, access_token: &str, mut
 
     let mut client = EspHttpConnection::new(&Configuration {
         crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
+        timeout: Some(HTTP_TIMEOUT),
         ..Default::default()
     })?;
     let mut autiorization_header = heapless::String::<ACCESS_TOKEN_BEARER_LENGTH>::new();
@@ -32,6 +33,99 @@ pub fn fetch_http_and_parse<F, ParserResult>(url: &str, access_token: &str, mut
         remaining: client.header("x-rate-limit-remaining").and_then(|v| v.parse().ok()),
         reset: client.header("x-rate-limit-reset").and_then(|v| v.parse().ok()),
     };
+    let status = embedded_svc::http::Status::status(&client);
+    if !(200..300).contains(&status) {
+        anyhow::bail!("HTTP error status {} - {:?}", status, rate_limit);
+    }
     let result = response_parser(client)?;
     Ok((result, rate_limit))
 }
+
+/// GET a resource on the LAN such as the local API of Remo.
+pub fn fetch_local(url: &str, timeout: Duration) -> anyhow::Result<Vec<u8>> {
+    use esp_idf_svc::http::client::*;
+
+    let mut client = EspHttpConnection::new(&Configuration {
+        timeout: Some(timeout),
+        ..Default::default()
+    })?;
+    let headers = [("X-Requested-With", "local")];
+    client.initiate_request(embedded_svc::http::Method::Get, url, &headers)?;
+    client.initiate_response()?;
+    let status = embedded_svc::http::Status::status(&client);
+    if !(200..300).contains(&status) {
+        anyhow::bail!("HTTP error status {}", status);
+    }
+    let mut body = Vec::new();
+    let mut buffer = [0u8; 512];
+    loop {
+        let length = embedded_io::blocking::Read::read(&mut client, &mut buffer)?;
+        if length == 0 {
+            break;
+        }
+        body.extend_from_slice(&buffer[..length]);
+    }
+    Ok(body)
+}
+
+/// POST the body with the headers. Returns the status and the response body.
+pub fn post_http(url: &str, headers: &[(&str, &str)], body: &[u8]) -> anyhow::Result<(u16, Vec<u8>)> {
+    use esp_idf_svc::http::client::*;
+
+    let mut client = EspHttpConnection::new(&Configuration {
+        crt_bundle_attach: Some(esp_idf_sys::esp_crt_bundle_attach),
+        timeout: Some(HTTP_TIMEOUT),
+        ..Default::default()
+    })?;
+    let mut content_length = heapless::String::<10>::new();
+    write!(&mut content_length, "{}", body.len()).unwrap();
+    let mut request_headers = heapless::Vec::<(&str, &str), 8>::new();
+    request_headers.push(("Content-Length", content_length.as_str())).unwrap();
+    request_headers.extend_from_slice(headers).map_err(|_| anyhow::anyhow!("too many headers"))?;
+    client.initiate_request(embedded_svc::http::Method::Post, url, &request_headers)?;
+    embedded_io::blocking::Write::write_all(&mut client, body)?;
+    client.initiate_response()?;
+    let status = embedded_svc::http::Status::status(&client);
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 512];
+    loop {
+        let length = embedded_io::blocking::Read::read(&mut client, &mut buffer)?;
+        if length == 0 {
+            break;
+        }
+        response.extend_from_slice(&buffer[..length]);
+    }
+    Ok((status, response))
+}
+
+/// The HTTP server stops when dropped, so it is kept for the lifetime of the firmware.
+struct HttpServer {
+    _server: esp_idf_svc::http::server::EspHttpServer,
+}
+// The server is only kept here and not used from Rust after it is started. ESP-IDF accesses it from its own task.
+unsafe impl Send for HttpServer {}
+
+static HTTP_SERVER: Mutex<Option<HttpServer>> = Mutex::new(None);
+
+/// Start the HTTP server which answers GET requests to the paths with the handler.
+pub fn start_http_server(port: u16, paths: &[&str], handler: fn(&str) -> ApiResponse) -> anyhow::Result<()> {
+    use esp_idf_svc::http::server::*;
+    use embedded_io::blocking::Write as _;
+
+    let mut server = EspHttpServer::new(&Configuration {
+        http_port: port,
+        ..Default::default()
+    })?;
+    for path in paths {
+        // The query string is not used to match the path.
+        server.fn_handler(path, embedded_svc::http::Method::Get, move |request| {
+            let response = handler(request.uri());
+            let headers = [("Content-Type", response.content_type)];
+            let mut writer = request.into_response(response.status, None, &headers)?;
+            writer.write_all(response.body.as_bytes())?;
+            Ok(())
+        })?;
+    }
+    *HTTP_SERVER.lock().unwrap() = Some(HttpServer { _server: server });
+    Ok(())
+}
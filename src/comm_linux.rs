@@ -1,6 +1,7 @@
 use std::{fmt::Write, io::Read, time::{Duration, Instant}, thread::JoinHandle, sync::{Arc, Mutex, Condvar}};
 
-use crate::{RateLimitInfo, config::ACCESS_TOKEN};
+use crate::{http_api::ApiResponse, RateLimitInfo, HTTP_TIMEOUT};
+use crate::capture::{RECORDER, REPLAYER};
 
 pub const MAX_ACCESS_TOKEN_LEN: usize = 128;
 pub const ACCESS_TOKEN_BEARER_LENGTH: usize = "Bearer ".len() + MAX_ACCESS_TOKEN_LEN;
@@ -43,12 +44,13 @@ impl WifiWait {
 }
 
 pub struct HttpResponse<'a> {
-    response: &'a mut reqwest::blocking::Response,
+    reader: &'a mut dyn Read,
+    content_length: Option<usize>,
 }
 
 impl<'a> HttpResponse<'a> {
     pub fn content_len(&self) -> Option<usize> {
-        self.response.content_length().map(|v| v as usize)
+        self.content_length
     }
 }
 
@@ -58,17 +60,27 @@ impl<'a> embedded_io::Io for HttpResponse<'a> {
 impl<'a> embedded_io::blocking::Read for HttpResponse<'a> {
     
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
-        self.response.read(buf).map_err(|_| embedded_io::ErrorKind::Other)
+        self.reader.read(buf).map_err(|_| embedded_io::ErrorKind::Other)
     }
     fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<(), embedded_io::blocking::ReadExactError<Self::Error>> {
-        self.response.read_exact(buf).map_err(|_| embedded_io::blocking::ReadExactError::Other(embedded_io::ErrorKind::Other))
+        self.reader.read_exact(buf).map_err(|_| embedded_io::blocking::ReadExactError::Other(embedded_io::ErrorKind::Other))
     }
 }
 
 impl<'a> From<&'a mut reqwest::blocking::Response> for HttpResponse<'a> {
     fn from(response: &'a mut reqwest::blocking::Response) -> Self {
         Self {
-            response,
+            content_length: response.content_length().map(|v| v as usize),
+            reader: response,
+        }
+    }
+}
+
+impl<'a> From<&'a mut &'a [u8]> for HttpResponse<'a> {
+    fn from(body: &'a mut &'a [u8]) -> Self {
+        Self {
+            content_length: Some(body.len()),
+            reader: body,
         }
     }
 }
@@ -76,12 +88,28 @@ impl<'a> From<&'a mut reqwest::blocking::Response> for HttpResponse<'a> {
 pub fn fetch_http_and_parse<F, ParserResult>(url: &str, access_token: &str, mut response_parser: F) -> anyhow::Result<(ParserResult, RateLimitInfo)> 
     where F: for <'a> FnMut(HttpResponse<'a>) -> anyhow::Result<ParserResult>
 {
-    let mut access_token = heapless::String::<ACCESS_TOKEN_BEARER_LENGTH>::new();
-    access_token.write_str("Bearer ").unwrap();
-    access_token.write_str(ACCESS_TOKEN).unwrap();
-    let mut client = reqwest::blocking::Client::new();
+    let path = reqwest::Url::parse(url)?.path().to_string();
+    // The other fetchers must not wait for the replay of this response.
+    let replayed = REPLAYER.lock().unwrap().as_mut().map(|replayer| replayer.next(&path));
+    if let Some(entry) = replayed {
+        let entry = entry.ok_or_else(|| anyhow::anyhow!("no captured response for {}", path))?;
+        entry.wait();
+        if !(200..300).contains(&entry.status) {
+            anyhow::bail!("HTTP error status {} - {:?}", entry.status, entry.rate_limit);
+        }
+        let mut body = entry.body.as_slice();
+        let result = response_parser(HttpResponse::from(&mut body))?;
+        return Ok((result, entry.rate_limit));
+    }
+
+    let mut authorization_header = heapless::String::<ACCESS_TOKEN_BEARER_LENGTH>::new();
+    authorization_header.write_str("Bearer ").unwrap();
+    authorization_header.write_str(access_token).unwrap();
+    let client = reqwest::blocking::Client::builder()
+        .timeout(HTTP_TIMEOUT)
+        .build()?;
     let mut response = client.get(url)
-        .header("Authorization", access_token.as_str())
+        .header("Authorization", authorization_header.as_str())
         .send()?;
 
     //< x-rate-limit-limit: 30
@@ -93,10 +121,116 @@ pub fn fetch_http_and_parse<F, ParserResult>(url: &str, access_token: &str, mut
         remaining: response.headers().get("x-rate-limit-remaining").and_then(|v| v.to_str().ok()).and_then(|v| v.parse().ok()),
         reset: response.headers().get("x-rate-limit-reset").and_then(|v| v.to_str().ok()).and_then(|v| v.parse().ok()),
     };
+    if RECORDER.lock().unwrap().is_some() {
+        // The body is read entirely to record it, then parsed from the buffer.
+        let status = response.status();
+        let mut body = Vec::new();
+        response.read_to_end(&mut body)?;
+        if let Some(recorder) = RECORDER.lock().unwrap().as_mut() {
+            // A failure of the capture must not fail the fetch.
+            if let Err(err) = recorder.record(&path, status.as_u16(), rate_limit, body.clone()) {
+                log::warn!("Failed to record the response of {} - {:?}", path, err);
+            }
+        }
+        if !status.is_success() {
+            anyhow::bail!("HTTP error status {} - {:?}", status, rate_limit);
+        }
+        let mut body = body.as_slice();
+        let result = response_parser(HttpResponse::from(&mut body))?;
+        return Ok((result, rate_limit));
+    }
+    if !response.status().is_success() {
+        anyhow::bail!("HTTP error status {} - {:?}", response.status(), rate_limit);
+    }
     let result = response_parser(HttpResponse::from(&mut response))?;
     Ok((result, rate_limit))
 }
 
+/// GET a resource on the LAN such as the local API of Remo.
+pub fn fetch_local(url: &str, timeout: Duration) -> anyhow::Result<Vec<u8>> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(timeout)
+        .build()?;
+    let response = client.get(url)
+        .header("X-Requested-With", "local")
+        .send()?;
+    if !response.status().is_success() {
+        anyhow::bail!("HTTP error status {}", response.status());
+    }
+    Ok(response.bytes()?.to_vec())
+}
+
+/// POST the body with the headers. Returns the status and the response body.
+pub fn post_http(url: &str, headers: &[(&str, &str)], body: &[u8]) -> anyhow::Result<(u16, Vec<u8>)> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(HTTP_TIMEOUT)
+        .build()?;
+    let mut request = client.post(url).body(body.to_vec());
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let response = request.send()?;
+    let status = response.status().as_u16();
+    Ok((status, response.bytes()?.to_vec()))
+}
+
+/// Start the HTTP server which answers GET requests to the paths with the handler.
+pub fn start_http_server(port: u16, paths: &[&str], handler: fn(&str) -> ApiResponse) -> anyhow::Result<()> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let listener = std::net::TcpListener::bind(("0.0.0.0", port))?;
+    log::info!("HTTP server listening on {}", listener.local_addr()?);
+    let paths: Vec<String> = paths.iter().map(|path| path.to_string()).collect();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.map_err(anyhow::Error::from).and_then(|mut stream| {
+                stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+                let mut reader = BufReader::new(stream.try_clone()?);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line)?;
+                // Skip the headers.
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                        break;
+                    }
+                }
+                let mut tokens = request_line.split_whitespace();
+                let (method, uri) = (tokens.next().unwrap_or(""), tokens.next().unwrap_or(""));
+                let path = uri.split('?').next().unwrap_or("");
+                let response = if method != "GET" {
+                    ApiResponse { status: 405, content_type: "text/plain", body: String::new() }
+                } else if !paths.iter().any(|registered| registered == path) {
+                    ApiResponse { status: 404, content_type: "text/plain", body: String::new() }
+                } else {
+                    handler(uri)
+                };
+                log::debug!("{} -> {}", request_line.trim(), response.status);
+                write!(stream, "HTTP/1.1 {} {}\r\n", response.status, reason_phrase(response.status))?;
+                write!(stream, "Content-Type: {}\r\n", response.content_type)?;
+                write!(stream, "Content-Length: {}\r\n", response.body.len())?;
+                write!(stream, "Connection: close\r\n\r\n")?;
+                stream.write_all(response.body.as_bytes())?;
+                Ok(())
+            });
+            if let Err(err) = result {
+                log::warn!("HTTP request failed - {:?}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "",
+    }
+}
+
 pub struct EspTaskTimerService{}
 impl EspTaskTimerService {
     pub fn new() -> Result<Self, ()> { Ok(Self {}) }
@@ -134,7 +268,7 @@ impl EspTimer {
                     break;
                 }
                 let now = Instant::now();
-                if now.duration_since(last_time) < interval {
+                if now.duration_since(last_time) < crate::clock::real_duration(interval) {
                     std::thread::sleep(Duration::from_millis(1));
                     continue;
                 }
@@ -0,0 +1,279 @@
+//! Connectivity manager which keeps Wi-Fi connected, and the status of the network shown on the top bar.
+//!
+//! The manager task owns the Wi-Fi driver. It tries the configured networks in order, and waits with
+//! exponential backoff after all of them failed. Other tasks read the status or subscribe to the events
+//! instead of touching the driver.
+
+use std::{net::Ipv4Addr, sync::{mpsc, Mutex}, time::Duration};
+
+#[cfg(target_os="espidf")]
+use embedded_svc::wifi::Wifi;
+
+use crate::{clock, copy_truncated, input::{self, InputEvent}, EspWifi, RateLimitInfo, Timestamp, WifiWait, CONFIG};
+
+#[cfg(target_os="espidf")]
+type WifiDriver = EspWifi<'static>;
+#[cfg(target_os="linux")]
+type WifiDriver = EspWifi;
+
+pub const MAX_WIFI_NETWORKS: usize = 4;
+const MAX_SUBSCRIBERS: usize = 4;
+/// Timeout of starting the driver and of connecting to each network.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Interval to check the connection and update the RSSI while connected.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Wait after all the networks failed, doubled at each failure.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+#[cfg(target_os="espidf")]
+const CONNECTIVITY_TASK_STACK_SIZE: usize = 6 * 1024;
+#[cfg(target_os="linux")]
+const CONNECTIVITY_TASK_STACK_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Debug, Default)]
+pub struct WifiNetwork {
+    pub ssid: heapless::String<32>,
+    pub password: heapless::String<64>,
+}
+
+pub type WifiNetworks = heapless::Vec<WifiNetwork, MAX_WIFI_NETWORKS>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WifiEvent {
+    Connected,
+    Disconnected,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct WifiStatus {
+    pub connected: bool,
+    /// SSID of the network connected to, or being tried.
+    pub ssid: heapless::String<32>,
+    /// Signal strength in dBm while connected.
+    pub rssi: Option<i8>,
+    /// Address assigned by DHCP while connected.
+    pub ip: Option<Ipv4Addr>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NetworkStatus {
+    pub wifi: WifiStatus,
+    /// Rate limit of the Cloud API returned by the last fetch.
+    pub rate_limit: RateLimitInfo,
+    /// When the sensor data was fetched successfully last time.
+    pub last_fetched: Option<Timestamp>,
+    /// Last error of fetching the sensor data, cleared when a fetch succeeds.
+    pub last_error: Option<(heapless::String<128>, Timestamp)>,
+    /// True once the clock has been synchronized with SNTP.
+    pub time_synced: bool,
+}
+
+static STATUS: Mutex<NetworkStatus> = Mutex::new(NetworkStatus {
+    wifi: WifiStatus {
+        connected: false,
+        ssid: heapless::String::new(),
+        rssi: None,
+        ip: None,
+    },
+    rate_limit: RateLimitInfo::new(),
+    last_fetched: None,
+    last_error: None,
+    time_synced: false,
+});
+static SUBSCRIBERS: Mutex<heapless::Vec<mpsc::Sender<WifiEvent>, MAX_SUBSCRIBERS>> = Mutex::new(heapless::Vec::new());
+
+pub fn status() -> NetworkStatus {
+    let mut status = STATUS.lock().unwrap().clone();
+    status.time_synced = clock::is_synced();
+    status
+}
+
+pub fn is_connected() -> bool {
+    STATUS.lock().unwrap().wifi.connected
+}
+
+pub fn set_fetched(rate_limit: RateLimitInfo, timestamp: Timestamp) {
+    let mut status = STATUS.lock().unwrap();
+    status.rate_limit = rate_limit;
+    status.last_fetched = Some(timestamp);
+    status.last_error = None;
+}
+
+pub fn set_fetch_error(message: &str, timestamp: Timestamp) {
+    STATUS.lock().unwrap().last_error = Some((copy_truncated(message), timestamp));
+}
+
+/// Receive the events from now on.
+pub fn subscribe() -> mpsc::Receiver<WifiEvent> {
+    let (sender, receiver) = mpsc::channel();
+    if SUBSCRIBERS.lock().unwrap().push(sender).is_err() {
+        log::error!("Too many subscribers of the Wi-Fi events");
+    }
+    receiver
+}
+
+/// Block until connected. Returns false if the timeout elapsed first.
+pub fn wait_connected(events: &mpsc::Receiver<WifiEvent>, timeout: Duration) -> bool {
+    let deadline = std::time::Instant::now() + timeout;
+    while !is_connected() {
+        let now = std::time::Instant::now();
+        if now >= deadline || events.recv_timeout(deadline - now).is_err() {
+            return is_connected();
+        }
+    }
+    true
+}
+
+fn update_status(wifi: WifiStatus) {
+    let connected = wifi.connected;
+    let changed = {
+        let mut status = STATUS.lock().unwrap();
+        let changed = status.wifi.connected != connected;
+        status.wifi = wifi;
+        changed
+    };
+    if !changed {
+        return;
+    }
+    let event = if connected { WifiEvent::Connected } else { WifiEvent::Disconnected };
+    log::info!("Wi-Fi {:?} {}", event, STATUS.lock().unwrap().wifi.ssid);
+    // Subscribers which dropped the receiver are removed.
+    SUBSCRIBERS.lock().unwrap().retain(|subscriber| subscriber.send(event).is_ok());
+    input::push_event(InputEvent::Refresh);
+}
+
+/// Start the driver if not started yet. Returns true if started.
+fn start(wifi: &mut WifiDriver, wifi_wait: &WifiWait) -> bool {
+    if wifi.is_started().unwrap_or(false) {
+        return true;
+    }
+    log::info!("Starting WiFi...");
+    match wifi.start() {
+        Ok(_) => wifi_wait.wait_with_timeout(CONNECT_TIMEOUT, || wifi.is_started().unwrap_or(false)),
+        Err(err) => {
+            log::error!("Failed to start WiFi - {:?}", err);
+            false
+        },
+    }
+}
+
+/// Connect to the network. Returns true if connected.
+fn connect(wifi: &mut WifiDriver, wifi_wait: &WifiWait, network: &WifiNetwork) -> bool {
+    log::info!("Connecting WiFi {}...", network.ssid);
+    if let Err(err) = configure(wifi, network) {
+        log::error!("Failed to configure WiFi - {:?}", err);
+        return false;
+    }
+    match wifi.connect() {
+        Ok(_) => wifi_wait.wait_with_timeout(CONNECT_TIMEOUT, || wifi.is_connected().unwrap_or(false)),
+        Err(err) => {
+            log::error!("Failed to connect WiFi - {:?}", err);
+            false
+        },
+    }
+}
+
+#[cfg(target_os="espidf")]
+fn configure(wifi: &mut WifiDriver, network: &WifiNetwork) -> anyhow::Result<()> {
+    use embedded_svc::wifi::{ClientConfiguration, Configuration};
+    // Give up the previous attempt, which may be still in progress.
+    wifi.disconnect().ok();
+    wifi.set_configuration(&Configuration::Client(ClientConfiguration {
+        ssid: network.ssid.clone(),
+        password: network.password.clone(),
+        channel: None,
+        ..Default::default()
+    }))?;
+    Ok(())
+}
+#[cfg(target_os="linux")]
+fn configure(_wifi: &mut WifiDriver, _network: &WifiNetwork) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// Signal strength of the access point in dBm.
+#[cfg(target_os="espidf")]
+fn rssi() -> Option<i8> {
+    let mut record = esp_idf_sys::wifi_ap_record_t::default();
+    match unsafe { esp_idf_sys::esp_wifi_sta_get_ap_info(&mut record) } {
+        esp_idf_sys::ESP_OK => Some(record.rssi),
+        _ => None,
+    }
+}
+/// Signal strength given by the environment variable `WIFI_RSSI`, since there is no Wi-Fi.
+#[cfg(target_os="linux")]
+fn rssi() -> Option<i8> {
+    std::env::var("WIFI_RSSI").ok().and_then(|rssi| rssi.parse().ok())
+}
+
+#[cfg(target_os="espidf")]
+fn ip_address(wifi: &WifiDriver) -> Option<Ipv4Addr> {
+    wifi.sta_netif().get_ip_info().ok().map(|info| info.ip).filter(|ip| !ip.is_unspecified())
+}
+/// Address of the interface to the default route of the host.
+#[cfg(target_os="linux")]
+fn ip_address(_wifi: &WifiDriver) -> Option<Ipv4Addr> {
+    // Connecting a UDP socket only selects the route and sends nothing.
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:9").ok()?;
+    match socket.local_addr().ok()?.ip() {
+        std::net::IpAddr::V4(ip) => Some(ip),
+        std::net::IpAddr::V6(_) => None,
+    }
+}
+
+fn connectivity_task(mut wifi: WifiDriver, wifi_wait: WifiWait, networks: WifiNetworks) -> ! {
+    let mut backoff = MIN_BACKOFF;
+    // Index of the network connected to or tried last. The same network is tried first after disconnected.
+    let mut current = 0;
+    let mut failures = 0;
+    loop {
+        if wifi.is_connected().unwrap_or(false) {
+            update_status(WifiStatus {
+                connected: true,
+                ssid: networks[current].ssid.clone(),
+                rssi: rssi(),
+                ip: ip_address(&wifi),
+            });
+            backoff = MIN_BACKOFF;
+            failures = 0;
+            std::thread::sleep(CHECK_INTERVAL);
+            continue;
+        }
+        update_status(WifiStatus {
+            connected: false,
+            ssid: networks[current].ssid.clone(),
+            rssi: None,
+            ip: None,
+        });
+        if start(&mut wifi, &wifi_wait) {
+            if connect(&mut wifi, &wifi_wait, &networks[current]) {
+                continue;
+            }
+            current = (current + 1) % networks.len();
+            failures += 1;
+            // Try the other networks before waiting.
+            if failures % networks.len() != 0 {
+                continue;
+            }
+        }
+        log::warn!("WiFi is not available. Retrying in {:?}", backoff);
+        std::thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Start the manager task with the networks in the configuration.
+pub fn start_task(wifi: WifiDriver, wifi_wait: WifiWait) {
+    let networks = CONFIG.lock().unwrap().as_ref().unwrap().wifi_networks.clone();
+    if networks.is_empty() {
+        log::error!("No Wi-Fi network is configured");
+        return;
+    }
+    std::thread::Builder::new()
+        .name("CONNECTIVITY".into())
+        .stack_size(CONNECTIVITY_TASK_STACK_SIZE)
+        .spawn(move || connectivity_task(wifi, wifi_wait, networks))
+        .expect("Failed to launch CONNECTIVITY task");
+}
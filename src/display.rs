@@ -0,0 +1,58 @@
+use std::sync::Mutex;
+#[cfg(target_os="linux")]
+use std::{path::PathBuf, sync::Arc};
+
+use lgfx::{EpdMode, LgfxDisplay};
+
+use crate::canvas::Canvas;
+#[cfg(target_os="linux")]
+use crate::framebuffer::Framebuffer;
+
+/// Held while accessing the SPI bus, which M5Paper shares between the display controller and the microSD card.
+/// The drivers of both do not know each other, so their transactions must not interleave.
+pub static SPI_BUS: Mutex<()> = Mutex::new(());
+
+/// Render target of the UI.
+pub enum Display {
+    Lgfx(lgfx::SharedLgfxTarget),
+    /// Render into an in-memory framebuffer instead of the SDL window.
+    /// If `screenshot_path` is set, the framebuffer is written as PNG at each refresh.
+    #[cfg(target_os="linux")]
+    Headless {
+        framebuffer: Arc<Mutex<Framebuffer>>,
+        screenshot_path: Option<PathBuf>,
+    },
+}
+
+impl Display {
+    /// Redraw the whole screen with the best quality.
+    pub fn draw<F: FnOnce(&mut dyn Canvas)>(&mut self, draw: F) {
+        self.draw_with_mode(EpdMode::Quality, draw);
+    }
+
+    /// Redraw a part of the screen. The e-ink panel updates only the area drawn, without flashing the whole screen.
+    pub fn draw_partial<F: FnOnce(&mut dyn Canvas)>(&mut self, draw: F) {
+        self.draw_with_mode(EpdMode::Text, draw);
+    }
+
+    fn draw_with_mode<F: FnOnce(&mut dyn Canvas)>(&mut self, mode: EpdMode, draw: F) {
+        match self {
+            Display::Lgfx(target) => {
+                let _bus = SPI_BUS.lock().unwrap();
+                let mut guard = target.lock_without_auto_update();
+                guard.set_epd_mode(mode);
+                draw(&mut guard);
+            },
+            #[cfg(target_os="linux")]
+            Display::Headless { framebuffer, screenshot_path } => {
+                let mut framebuffer = framebuffer.lock().unwrap();
+                draw(&mut *framebuffer);
+                if let Some(path) = screenshot_path {
+                    if let Err(err) = framebuffer.write_png(&path) {
+                        log::error!("Failed to write screenshot to {:?} - {:?}", path, err);
+                    }
+                }
+            },
+        }
+    }
+}
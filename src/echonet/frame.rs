@@ -0,0 +1,332 @@
+use std::net::Ipv4Addr;
+
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+pub const ECHONET_PORT: u16 = 3610;
+pub const MULTICAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 23, 0);
+
+const EHD1: u8 = 0x10;
+/// Format 1, which has the specified message format.
+const EHD2_FORMAT1: u8 = 0x81;
+const HEADER_LENGTH: usize = 12;
+
+pub const MAX_PROPERTIES: usize = 16;
+pub const MAX_EDT_LENGTH: usize = 64;
+
+/// ECHONET Lite object, i.e. class group code, class code and instance code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Eoj(pub [u8; 3]);
+
+impl Eoj {
+    pub const CONTROLLER: Eoj = Eoj([0x05, 0xff, 0x01]);
+    pub const NODE_PROFILE: Eoj = Eoj([0x0e, 0xf0, 0x01]);
+    pub const LOW_VOLTAGE_SMART_METER: Eoj = Eoj([0x02, 0x88, 0x01]);
+
+    /// True if both objects are of the same class regardless of the instance.
+    pub fn is_same_class(&self, other: &Eoj) -> bool {
+        self.0[..2] == other.0[..2]
+    }
+}
+
+/// ECHONET Lite service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum Esv {
+    SetI = 0x60,
+    SetC = 0x61,
+    Get = 0x62,
+    InfReq = 0x63,
+    SetRes = 0x71,
+    GetRes = 0x72,
+    Inf = 0x73,
+    Infc = 0x74,
+    InfcRes = 0x7a,
+    SetISna = 0x50,
+    SetCSna = 0x51,
+    GetSna = 0x52,
+    InfSna = 0x53,
+}
+
+/// Well-known property codes.
+pub mod epc {
+    pub const OPERATION_STATUS: u8 = 0x80;
+    pub const SELF_NODE_INSTANCE_LIST_S: u8 = 0xd6;
+    pub const COEFFICIENT: u8 = 0xd3;
+    pub const CUMULATIVE_ENERGY_EFFECTIVE_DIGITS: u8 = 0xd7;
+    pub const NORMAL_DIRECTION_CUMULATIVE_ENERGY: u8 = 0xe0;
+    pub const CUMULATIVE_ENERGY_UNIT: u8 = 0xe1;
+    pub const INSTANTANEOUS_POWER: u8 = 0xe7;
+}
+
+/// Convert the cumulative energy unit (EPC 0xE1) to kWh.
+pub fn energy_unit_kwh(code: u8) -> Option<f64> {
+    match code {
+        0x00 => Some(1.0),
+        0x01 => Some(0.1),
+        0x02 => Some(0.01),
+        0x03 => Some(0.001),
+        0x04 => Some(0.0001),
+        0x0a => Some(10.0),
+        0x0b => Some(100.0),
+        0x0c => Some(1000.0),
+        0x0d => Some(10000.0),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Property {
+    pub epc: u8,
+    pub edt: heapless::Vec<u8, MAX_EDT_LENGTH>,
+}
+
+impl Property {
+    /// Property without data, used by Get requests.
+    pub fn request(epc: u8) -> Self {
+        Self {
+            epc,
+            edt: heapless::Vec::new(),
+        }
+    }
+
+    pub fn new(epc: u8, edt: &[u8]) -> Result<Self, FrameError> {
+        Ok(Self {
+            epc,
+            edt: heapless::Vec::from_slice(edt).map_err(|_| FrameError::EdtTooLong)?,
+        })
+    }
+
+    /// Interpret the data as a big endian signed integer such as the instantaneous power.
+    pub fn as_i32(&self) -> Option<i32> {
+        let bytes: [u8; 4] = self.edt.as_slice().try_into().ok()?;
+        Some(i32::from_be_bytes(bytes))
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        let bytes: [u8; 4] = self.edt.as_slice().try_into().ok()?;
+        Some(u32::from_be_bytes(bytes))
+    }
+
+    /// Interpret the data as the instance list (number of instances followed by the objects).
+    pub fn as_instance_list(&self) -> heapless::Vec<Eoj, MAX_PROPERTIES> {
+        self.edt.get(1..).unwrap_or(&[])
+            .chunks_exact(3)
+            .take(MAX_PROPERTIES)
+            .map(|eoj| Eoj([eoj[0], eoj[1], eoj[2]]))
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    TooShort,
+    InvalidHeader,
+    UnknownEsv(u8),
+    TooManyProperties,
+    EdtTooLong,
+    BufferTooSmall,
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// ECHONET Lite frame in the specified message format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub tid: u16,
+    pub seoj: Eoj,
+    pub deoj: Eoj,
+    pub esv: Esv,
+    pub properties: heapless::Vec<Property, MAX_PROPERTIES>,
+}
+
+impl Frame {
+    pub fn property(&self, epc: u8) -> Option<&Property> {
+        self.properties.iter().find(|property| property.epc == epc)
+    }
+
+    /// Encode the frame to the buffer. Returns the length of the frame.
+    pub fn encode(&self, buffer: &mut [u8]) -> Result<usize, FrameError> {
+        let length = HEADER_LENGTH + self.properties.iter().map(|property| 2 + property.edt.len()).sum::<usize>();
+        if buffer.len() < length {
+            return Err(FrameError::BufferTooSmall);
+        }
+        buffer[0] = EHD1;
+        buffer[1] = EHD2_FORMAT1;
+        buffer[2..4].copy_from_slice(&self.tid.to_be_bytes());
+        buffer[4..7].copy_from_slice(&self.seoj.0);
+        buffer[7..10].copy_from_slice(&self.deoj.0);
+        buffer[10] = self.esv.into();
+        buffer[11] = self.properties.len() as u8;
+        let mut offset = HEADER_LENGTH;
+        for property in self.properties.iter() {
+            buffer[offset] = property.epc;
+            buffer[offset + 1] = property.edt.len() as u8;
+            buffer[offset + 2..offset + 2 + property.edt.len()].copy_from_slice(&property.edt);
+            offset += 2 + property.edt.len();
+        }
+        Ok(length)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, FrameError> {
+        if data.len() < HEADER_LENGTH {
+            return Err(FrameError::TooShort);
+        }
+        if data[0] != EHD1 || data[1] != EHD2_FORMAT1 {
+            return Err(FrameError::InvalidHeader);
+        }
+        let esv = Esv::try_from(data[10]).map_err(|_| FrameError::UnknownEsv(data[10]))?;
+        let count = data[11] as usize;
+        if count > MAX_PROPERTIES {
+            return Err(FrameError::TooManyProperties);
+        }
+        let mut properties = heapless::Vec::new();
+        let mut offset = HEADER_LENGTH;
+        for _ in 0..count {
+            let header = data.get(offset..offset + 2).ok_or(FrameError::TooShort)?;
+            let (epc, pdc) = (header[0], header[1] as usize);
+            let edt = data.get(offset + 2..offset + 2 + pdc).ok_or(FrameError::TooShort)?;
+            properties.push(Property::new(epc, edt)?).ok();
+            offset += 2 + pdc;
+        }
+        Ok(Self {
+            tid: u16::from_be_bytes([data[2], data[3]]),
+            seoj: Eoj([data[4], data[5], data[6]]),
+            deoj: Eoj([data[7], data[8], data[9]]),
+            esv,
+            properties,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_response() -> Frame {
+        Frame {
+            tid: 0x1234,
+            seoj: Eoj::LOW_VOLTAGE_SMART_METER,
+            deoj: Eoj::CONTROLLER,
+            esv: Esv::GetRes,
+            properties: [
+                Property::new(epc::INSTANTANEOUS_POWER, &[0x00, 0x00, 0x02, 0x32]).unwrap(),
+                Property::new(epc::CUMULATIVE_ENERGY_UNIT, &[0x01]).unwrap(),
+            ].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let frame = get_response();
+        let mut buffer = [0u8; 64];
+        let length = frame.encode(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], &[
+            0x10, 0x81, 0x12, 0x34, 0x02, 0x88, 0x01, 0x05, 0xff, 0x01, 0x72, 0x02,
+            0xe7, 0x04, 0x00, 0x00, 0x02, 0x32,
+            0xe1, 0x01, 0x01,
+        ]);
+        assert_eq!(Frame::decode(&buffer[..length]), Ok(frame));
+    }
+
+    #[test]
+    fn encode_request() {
+        let frame = Frame {
+            tid: 1,
+            seoj: Eoj::CONTROLLER,
+            deoj: Eoj::NODE_PROFILE,
+            esv: Esv::Get,
+            properties: [Property::request(epc::SELF_NODE_INSTANCE_LIST_S)].into_iter().collect(),
+        };
+        let mut buffer = [0u8; 64];
+        let length = frame.encode(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], &[0x10, 0x81, 0x00, 0x01, 0x05, 0xff, 0x01, 0x0e, 0xf0, 0x01, 0x62, 0x01, 0xd6, 0x00]);
+        assert_eq!(Frame::decode(&buffer[..length]), Ok(frame));
+    }
+
+    #[test]
+    fn encode_to_small_buffer() {
+        let mut buffer = [0u8; 20];
+        assert_eq!(get_response().encode(&mut buffer), Err(FrameError::BufferTooSmall));
+    }
+
+    #[test]
+    fn decode_malformed() {
+        let mut buffer = [0u8; 64];
+        let length = get_response().encode(&mut buffer).unwrap();
+        let data = &buffer[..length];
+        // Shorter than the header.
+        assert_eq!(Frame::decode(&data[..HEADER_LENGTH - 1]), Err(FrameError::TooShort));
+        // OPC is larger than the properties in the data.
+        let mut more_properties = data.to_vec();
+        more_properties[11] = 3;
+        assert_eq!(Frame::decode(&more_properties), Err(FrameError::TooShort));
+        // PDC of the last property is past the end.
+        assert_eq!(Frame::decode(&data[..length - 1]), Err(FrameError::TooShort));
+        let mut long_pdc = data.to_vec();
+        long_pdc[19] = 0xff;
+        assert_eq!(Frame::decode(&long_pdc), Err(FrameError::TooShort));
+        // Only EPC without PDC.
+        assert_eq!(Frame::decode(&data[..HEADER_LENGTH + 7]), Err(FrameError::TooShort));
+
+        let mut header = data.to_vec();
+        header[1] = 0x82;
+        assert_eq!(Frame::decode(&header), Err(FrameError::InvalidHeader));
+        let mut esv = data.to_vec();
+        esv[10] = 0x00;
+        assert_eq!(Frame::decode(&esv), Err(FrameError::UnknownEsv(0x00)));
+        let mut opc = data.to_vec();
+        opc[11] = MAX_PROPERTIES as u8 + 1;
+        assert_eq!(Frame::decode(&opc), Err(FrameError::TooManyProperties));
+    }
+
+    #[test]
+    fn decode_too_long_edt() {
+        let mut data = vec![0x10, 0x81, 0x00, 0x01, 0x02, 0x88, 0x01, 0x05, 0xff, 0x01, 0x72, 0x01, 0xe7, MAX_EDT_LENGTH as u8 + 1];
+        data.resize(data.len() + MAX_EDT_LENGTH + 1, 0);
+        assert_eq!(Frame::decode(&data), Err(FrameError::EdtTooLong));
+    }
+
+    #[test]
+    fn property_values() {
+        let power = Property::new(epc::INSTANTANEOUS_POWER, &[0xff, 0xff, 0xff, 0xfe]).unwrap();
+        assert_eq!(power.as_i32(), Some(-2));
+        assert_eq!(power.as_u32(), Some(0xffff_fffe));
+        let short = Property::new(epc::INSTANTANEOUS_POWER, &[0x00, 0x01]).unwrap();
+        assert_eq!(short.as_i32(), None);
+        assert_eq!(short.as_u32(), None);
+        assert_eq!(Property::request(epc::COEFFICIENT).as_u32(), None);
+    }
+
+    #[test]
+    fn instance_list() {
+        let list = Property::new(epc::SELF_NODE_INSTANCE_LIST_S, &[0x02, 0x02, 0x88, 0x01, 0x05, 0xff, 0x01]).unwrap();
+        assert_eq!(list.as_instance_list().as_slice(), &[Eoj::LOW_VOLTAGE_SMART_METER, Eoj::CONTROLLER]);
+        // An incomplete object at the end is ignored.
+        let truncated = Property::new(epc::SELF_NODE_INSTANCE_LIST_S, &[0x02, 0x02, 0x88, 0x01, 0x05]).unwrap();
+        assert_eq!(truncated.as_instance_list().as_slice(), &[Eoj::LOW_VOLTAGE_SMART_METER]);
+        assert!(Property::request(epc::SELF_NODE_INSTANCE_LIST_S).as_instance_list().is_empty());
+    }
+
+    #[test]
+    fn energy_unit() {
+        assert_eq!(energy_unit_kwh(0x00), Some(1.0));
+        assert_eq!(energy_unit_kwh(0x01), Some(0.1));
+        assert_eq!(energy_unit_kwh(0x04), Some(0.0001));
+        assert_eq!(energy_unit_kwh(0x0a), Some(10.0));
+        assert_eq!(energy_unit_kwh(0x0d), Some(10000.0));
+        assert_eq!(energy_unit_kwh(0x05), None);
+        assert_eq!(energy_unit_kwh(0x0e), None);
+    }
+
+    #[test]
+    fn same_class() {
+        assert!(Eoj([0x02, 0x88, 0x02]).is_same_class(&Eoj::LOW_VOLTAGE_SMART_METER));
+        assert!(!Eoj::CONTROLLER.is_same_class(&Eoj::LOW_VOLTAGE_SMART_METER));
+    }
+}
@@ -0,0 +1,323 @@
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+
+use crate::{clock, Timestamp, CONFIG};
+
+pub mod frame;
+pub use frame::{energy_unit_kwh, epc, Eoj, Esv, Frame, Property, ECHONET_PORT, MULTICAST_ADDRESS};
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(3);
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+/// INF notifications older than this are not used as the current value.
+const MAX_NOTIFICATION_AGE: Duration = Duration::from_secs(60);
+const MAX_NODES: usize = 8;
+const MAX_FRAME_LENGTH: usize = 1500;
+
+/// An ECHONET Lite node and its device objects.
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub address: SocketAddr,
+    pub objects: heapless::Vec<Eoj, { frame::MAX_PROPERTIES }>,
+}
+
+/// Scale of the cumulative energy, read once per discovered smart meter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct EnergyScale {
+    /// kWh per count, which is the unit multiplied by the coefficient.
+    kwh_per_count: f64,
+    /// The count wraps around to 0 at 10^(effective digits).
+    modulus: u64,
+}
+
+/// Count of the cumulative energy continued over the wrap-arounds.
+#[derive(Debug, Default)]
+struct EnergyCounter {
+    last: Option<u32>,
+    wrapped: u64,
+}
+
+impl EnergyCounter {
+    fn update(&mut self, count: u32, modulus: u64) -> u64 {
+        if self.last.map_or(false, |last| count < last) {
+            self.wrapped += modulus;
+        }
+        self.last = Some(count);
+        self.wrapped + count as u64
+    }
+}
+
+/// ECHONET Lite controller which reads the smart meter on the LAN.
+pub struct EchonetClient {
+    socket: UdpSocket,
+    tid: u16,
+    /// The node to discover. The multicast address is used if not specified.
+    discovery_address: SocketAddrV4,
+    smart_meter: Option<(SocketAddr, Eoj)>,
+    energy_scale: Option<EnergyScale>,
+    energy_counter: EnergyCounter,
+    /// Instantaneous power notified by INF.
+    notified_power: Option<(i32, Timestamp)>,
+}
+
+impl EchonetClient {
+    pub fn new(node: Option<SocketAddrV4>) -> anyhow::Result<Self> {
+        // Nodes usually respond to the port 3610. Use another port if it is occupied, e.g. by a local responder.
+        let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, ECHONET_PORT)) {
+            Ok(socket) => socket,
+            Err(err) => {
+                log::warn!("Failed to bind the ECHONET Lite port - {:?}", err);
+                UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?
+            },
+        };
+        if let Err(err) = socket.join_multicast_v4(&MULTICAST_ADDRESS, &Ipv4Addr::UNSPECIFIED) {
+            log::warn!("Failed to join the ECHONET Lite multicast group - {:?}", err);
+        }
+        Ok(Self {
+            socket,
+            tid: 0,
+            discovery_address: node.unwrap_or(SocketAddrV4::new(MULTICAST_ADDRESS, ECHONET_PORT)),
+            smart_meter: None,
+            energy_scale: None,
+            energy_counter: EnergyCounter::default(),
+            notified_power: None,
+        })
+    }
+
+    fn send(&mut self, address: SocketAddr, deoj: Eoj, esv: Esv, epcs: &[u8]) -> anyhow::Result<u16> {
+        self.tid = self.tid.wrapping_add(1);
+        let frame = Frame {
+            tid: self.tid,
+            seoj: Eoj::CONTROLLER,
+            deoj,
+            esv,
+            properties: epcs.iter().map(|epc| Property::request(*epc)).collect(),
+        };
+        self.send_frame(address, &frame)?;
+        Ok(self.tid)
+    }
+
+    fn send_frame(&self, address: SocketAddr, frame: &Frame) -> anyhow::Result<()> {
+        let mut buffer = [0u8; MAX_FRAME_LENGTH];
+        let length = frame.encode(&mut buffer)?;
+        self.socket.send_to(&buffer[..length], address)?;
+        Ok(())
+    }
+
+    /// Respond to INFC with the same TID, echoing each property without data.
+    fn respond_infc(&self, address: SocketAddr, frame: &Frame) -> anyhow::Result<()> {
+        let response = Frame {
+            tid: frame.tid,
+            seoj: frame.deoj,
+            deoj: frame.seoj,
+            esv: Esv::InfcRes,
+            properties: frame.properties.iter().map(|property| Property::request(property.epc)).collect(),
+        };
+        self.send_frame(address, &response)
+    }
+
+    /// Receive a frame until the deadline. INF notifications are handled here.
+    fn receive(&mut self, deadline: Instant) -> anyhow::Result<Option<(SocketAddr, Frame)>> {
+        let mut buffer = [0u8; MAX_FRAME_LENGTH];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            self.socket.set_read_timeout(Some(deadline - now))?;
+            let (length, address) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            let frame = match Frame::decode(&buffer[..length]) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    log::debug!("invalid ECHONET Lite frame from {} - {:?}", address, err);
+                    continue;
+                },
+            };
+            if frame.esv == Esv::Inf || frame.esv == Esv::Infc {
+                self.handle_notification(address, &frame);
+                if frame.esv == Esv::Infc {
+                    // INFC requires a response, otherwise the node keeps retrying.
+                    if let Err(err) = self.respond_infc(address, &frame) {
+                        log::warn!("Failed to respond to INFC from {} - {:?}", address, err);
+                    }
+                }
+            }
+            return Ok(Some((address, frame)));
+        }
+    }
+
+    fn handle_notification(&mut self, address: SocketAddr, frame: &Frame) {
+        log::debug!("INF from {}: {:?}", address, frame);
+        if frame.seoj.is_same_class(&Eoj::LOW_VOLTAGE_SMART_METER) {
+            if let Some(power) = frame.property(epc::INSTANTANEOUS_POWER).and_then(Property::as_i32) {
+                self.notified_power = Some((power, clock::now()));
+            }
+        }
+    }
+
+    /// Find the nodes by requesting their instance lists.
+    pub fn discover(&mut self) -> anyhow::Result<heapless::Vec<Node, MAX_NODES>> {
+        let tid = self.send(self.discovery_address.into(), Eoj::NODE_PROFILE, Esv::Get, &[epc::SELF_NODE_INSTANCE_LIST_S])?;
+        let deadline = Instant::now() + DISCOVERY_TIMEOUT;
+        let mut nodes = heapless::Vec::new();
+        while let Some((address, frame)) = self.receive(deadline)? {
+            let is_instance_list = frame.tid == tid || frame.esv == Esv::Inf;
+            if let (true, Some(property)) = (is_instance_list, frame.property(epc::SELF_NODE_INSTANCE_LIST_S)) {
+                if nodes.iter().any(|node: &Node| node.address == address) {
+                    continue;
+                }
+                let node = Node {
+                    address,
+                    objects: property.as_instance_list(),
+                };
+                log::info!("ECHONET Lite node found: {} {:?}", address, node.objects);
+                if nodes.push(node).is_err() {
+                    break;
+                }
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Get the properties of the object and wait for the response.
+    pub fn get(&mut self, address: SocketAddr, eoj: Eoj, epcs: &[u8]) -> anyhow::Result<Frame> {
+        let tid = self.send(address, eoj, Esv::Get, epcs)?;
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        while let Some((from, frame)) = self.receive(deadline)? {
+            if from.ip() != address.ip() || frame.tid != tid {
+                continue;
+            }
+            return match frame.esv {
+                Esv::GetRes => Ok(frame),
+                Esv::GetSna => Err(anyhow!("Get_SNA from {} - {:?}", address, frame.properties)),
+                esv => Err(anyhow!("unexpected response {:?} from {}", esv, address)),
+            };
+        }
+        Err(anyhow!("no response from {}", address))
+    }
+
+    fn find_smart_meter(&mut self) -> anyhow::Result<(SocketAddr, Eoj)> {
+        if let Some(smart_meter) = self.smart_meter {
+            return Ok(smart_meter);
+        }
+        for node in self.discover()? {
+            if let Some(eoj) = node.objects.iter().find(|eoj| eoj.is_same_class(&Eoj::LOW_VOLTAGE_SMART_METER)) {
+                self.smart_meter = Some((node.address, *eoj));
+                return Ok((node.address, *eoj));
+            }
+        }
+        Err(anyhow!("no smart meter found"))
+    }
+
+    /// Read the instantaneous power in watts.
+    /// A recent INF notification is used if the smart meter does not respond.
+    pub fn read_instantaneous_power(&mut self) -> anyhow::Result<i32> {
+        let result = self.find_smart_meter().and_then(|(address, eoj)| {
+            let frame = self.get(address, eoj, &[epc::INSTANTANEOUS_POWER])?;
+            frame.property(epc::INSTANTANEOUS_POWER)
+                .and_then(Property::as_i32)
+                .ok_or_else(|| anyhow!("invalid instantaneous power"))
+        });
+        match result {
+            Ok(power) => Ok(power),
+            Err(err) => {
+                // Discover the smart meter again at the next time.
+                self.smart_meter = None;
+                self.energy_scale = None;
+                let now = clock::now();
+                match self.notified_power {
+                    Some((power, timestamp)) if (now - timestamp).to_std().map(|age| age < MAX_NOTIFICATION_AGE).unwrap_or(true) => {
+                        log::warn!("Using the notified power - {:?}", err);
+                        Ok(power)
+                    },
+                    _ => Err(err),
+                }
+            },
+        }
+    }
+
+    fn read_energy_scale(&mut self, address: SocketAddr, eoj: Eoj) -> anyhow::Result<EnergyScale> {
+        if let Some(scale) = self.energy_scale {
+            return Ok(scale);
+        }
+        let frame = self.get(address, eoj, &[epc::CUMULATIVE_ENERGY_UNIT, epc::CUMULATIVE_ENERGY_EFFECTIVE_DIGITS])?;
+        let unit = frame.property(epc::CUMULATIVE_ENERGY_UNIT)
+            .and_then(|property| property.edt.first().copied())
+            .and_then(energy_unit_kwh)
+            .ok_or_else(|| anyhow!("invalid cumulative energy unit"))?;
+        let digits = frame.property(epc::CUMULATIVE_ENERGY_EFFECTIVE_DIGITS)
+            .and_then(|property| property.edt.first().copied())
+            .filter(|digits| (1..=8).contains(digits))
+            .ok_or_else(|| anyhow!("invalid cumulative energy effective digits"))?;
+        // The coefficient is optional and 1 if not supported.
+        let coefficient = self.get(address, eoj, &[epc::COEFFICIENT]).ok()
+            .and_then(|frame| frame.property(epc::COEFFICIENT).and_then(Property::as_u32))
+            .unwrap_or(1);
+        let scale = EnergyScale {
+            kwh_per_count: unit * coefficient as f64,
+            modulus: 10u64.pow(digits as u32),
+        };
+        log::info!("cumulative energy scale: {:?}", scale);
+        self.energy_scale = Some(scale);
+        Ok(scale)
+    }
+
+    /// Read the normal direction cumulative energy in kWh.
+    /// The energy keeps increasing when the count of the smart meter wraps around.
+    pub fn read_cumulative_energy(&mut self) -> anyhow::Result<f64> {
+        let (address, eoj) = self.find_smart_meter()?;
+        let scale = self.read_energy_scale(address, eoj)?;
+        let frame = self.get(address, eoj, &[epc::NORMAL_DIRECTION_CUMULATIVE_ENERGY])?;
+        let count = frame.property(epc::NORMAL_DIRECTION_CUMULATIVE_ENERGY)
+            .and_then(Property::as_u32)
+            .filter(|count| (*count as u64) < scale.modulus)
+            .ok_or_else(|| anyhow!("invalid cumulative energy"))?;
+        Ok(self.energy_counter.update(count, scale.modulus) as f64 * scale.kwh_per_count)
+    }
+}
+
+static CLIENT: Mutex<Option<EchonetClient>> = Mutex::new(None);
+
+fn with_client<T>(f: impl FnOnce(&mut EchonetClient) -> anyhow::Result<T>) -> anyhow::Result<T> {
+    let mut client = CLIENT.lock().unwrap();
+    if client.is_none() {
+        let node = CONFIG.lock().unwrap().as_ref().and_then(|config| config.echonet_node);
+        *client = Some(EchonetClient::new(node)?);
+    }
+    f(client.as_mut().unwrap())
+}
+
+/// Read the instantaneous power from the smart meter on the LAN.
+pub fn read_instantaneous_power() -> anyhow::Result<i32> {
+    with_client(|client| client.read_instantaneous_power())
+}
+
+/// Read the cumulative energy from the smart meter on the LAN.
+pub fn read_cumulative_energy() -> anyhow::Result<f64> {
+    with_client(|client| client.read_cumulative_energy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn energy_counter_wraps_around() {
+        let mut counter = EnergyCounter::default();
+        assert_eq!(counter.update(99_998, 100_000), 99_998);
+        assert_eq!(counter.update(99_999, 100_000), 99_999);
+        assert_eq!(counter.update(3, 100_000), 100_003);
+        assert_eq!(counter.update(3, 100_000), 100_003);
+        assert_eq!(counter.update(99_999, 100_000), 199_999);
+        assert_eq!(counter.update(0, 100_000), 200_000);
+    }
+}
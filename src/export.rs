@@ -0,0 +1,233 @@
+//! Appends each sample to a daily file on the microSD card (a local directory on Linux),
+//! so that the long-term history is kept without any server.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::NaiveDate;
+
+use crate::{clock, display::SPI_BUS, SensorRecord, Timestamp, CONFIG};
+
+/// Mount point of the microSD card.
+#[cfg(target_os="espidf")]
+pub const SD_CARD_MOUNT_POINT: &str = "/sdcard";
+
+const CSV_HEADER: &str = "timestamp,temperature,humidity,illuminance,power\n";
+
+/// Format of the exported files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// A JSON object per line.
+    JsonLines,
+}
+
+impl ExportFormat {
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "csv" => Some(ExportFormat::Csv),
+            "jsonl" => Some(ExportFormat::JsonLines),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::JsonLines => "jsonl",
+        }
+    }
+}
+
+struct Exporter {
+    dir: PathBuf,
+    format: ExportFormat,
+    /// Files older than this are deleted. Kept forever if 0.
+    retention_days: u32,
+    /// Date of the current file, to apply the retention policy when it rotates.
+    current_date: Option<NaiveDate>,
+}
+
+static EXPORTER: Mutex<Option<Exporter>> = Mutex::new(None);
+
+impl Exporter {
+    fn path(&self, date: NaiveDate) -> PathBuf {
+        self.dir.join(format!("{}.{}", date.format("%Y-%m-%d"), self.format.extension()))
+    }
+
+    fn append(&mut self, record: &SensorRecord, timestamp: &Timestamp) -> anyhow::Result<()> {
+        // Files are split at midnight of the configured time zone.
+        let date = clock::local(*timestamp).date_naive();
+        if self.current_date != Some(date) {
+            self.current_date = Some(date);
+            self.remove_expired(date);
+        }
+        let path = self.path(date);
+        let is_new = !path.exists();
+        let mut line = String::new();
+        match self.format {
+            ExportFormat::Csv => {
+                if is_new {
+                    line.push_str(CSV_HEADER);
+                }
+                std::fmt::Write::write_fmt(&mut line, format_args!("{},{:.1},{:.0},{:.0},{:.0}\n",
+                    timestamp.to_rfc3339(),
+                    record.ambient_temperature,
+                    record.relative_humidity,
+                    record.ambient_luminous_level,
+                    record.instant_power_usage,
+                )).ok();
+            },
+            ExportFormat::JsonLines => {
+                record.write_json(&mut line, timestamp).ok();
+                line.push('\n');
+            },
+        }
+        // Write a whole line at once so that a power loss leaves at most one broken line.
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// Delete the exported files older than the retention period.
+    fn remove_expired(&self, today: NaiveDate) {
+        if self.retention_days == 0 {
+            return;
+        }
+        let oldest = today - chrono::Duration::days(self.retention_days as i64 - 1);
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::warn!("Failed to list {:?} - {:?}", self.dir, err);
+                return;
+            },
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(self.format.extension()) {
+                continue;
+            }
+            let date = path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok());
+            if let Some(date) = date {
+                if date < oldest {
+                    log::info!("Removing expired export {:?}", path);
+                    if let Err(err) = fs::remove_file(&path) {
+                        log::warn!("Failed to remove {:?} - {:?}", path, err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Mount the microSD card on the SPI bus shared with the display.
+/// The display driver initializes the bus on the same pins before, so the card is added to it as another device.
+/// Both drivers access the bus only while holding `SPI_BUS`.
+#[cfg(target_os="espidf")]
+fn mount_sd_card() -> anyhow::Result<()> {
+    use esp_idf_sys::*;
+
+    // M5Paper: SCK=GPIO14, MOSI=GPIO12, MISO=GPIO13, CS=GPIO4 (IT8951 uses CS=GPIO15)
+    const SPI_HOST: spi_host_device_t = spi_host_device_t_SPI2_HOST;
+    let _bus = SPI_BUS.lock().unwrap();
+    unsafe {
+        let bus_config = spi_bus_config_t {
+            __bindgen_anon_1: spi_bus_config_t__bindgen_ty_1 { mosi_io_num: 12 },
+            __bindgen_anon_2: spi_bus_config_t__bindgen_ty_2 { miso_io_num: 13 },
+            sclk_io_num: 14,
+            __bindgen_anon_3: spi_bus_config_t__bindgen_ty_3 { quadwp_io_num: -1 },
+            __bindgen_anon_4: spi_bus_config_t__bindgen_ty_4 { quadhd_io_num: -1 },
+            max_transfer_sz: 4000,
+            ..Default::default()
+        };
+        // The bus must have been initialized by the display driver. If not, the display drives these pins
+        // by another host or by itself, and taking them over here would break the display.
+        let result = spi_bus_initialize(SPI_HOST, &bus_config, spi_common_dma_t_SPI_DMA_CH_AUTO);
+        if result == ESP_OK {
+            spi_bus_free(SPI_HOST);
+            anyhow::bail!("the SPI bus is not shared with the display");
+        }
+        if result != ESP_ERR_INVALID_STATE {
+            esp!(result)?;
+        }
+
+        // Same as SDSPI_HOST_DEFAULT(), which is a macro.
+        let host = sdmmc_host_t {
+            flags: SDMMC_HOST_FLAG_SPI | SDMMC_HOST_FLAG_DEINIT_ARG,
+            slot: SPI_HOST as i32,
+            max_freq_khz: SDMMC_FREQ_DEFAULT as i32,
+            io_voltage: 3.3,
+            init: Some(sdspi_host_init),
+            set_card_clk: Some(sdspi_host_set_card_clk),
+            do_transaction: Some(sdspi_host_do_transaction),
+            __bindgen_anon_1: sdmmc_host_t__bindgen_ty_1 { deinit_p: Some(sdspi_host_remove_device) },
+            io_int_enable: Some(sdspi_host_io_int_enable),
+            io_int_wait: Some(sdspi_host_io_int_wait),
+            ..Default::default()
+        };
+        let slot_config = sdspi_device_config_t {
+            host_id: SPI_HOST,
+            gpio_cs: 4,
+            gpio_cd: -1,
+            gpio_wp: -1,
+            gpio_int: -1,
+        };
+        let mount_config = esp_vfs_fat_sdmmc_mount_config_t {
+            format_if_mount_failed: false,
+            max_files: 4,
+            allocation_unit_size: 16 * 1024,
+            ..Default::default()
+        };
+        let mount_point = std::ffi::CString::new(SD_CARD_MOUNT_POINT).unwrap();
+        let mut card: *mut sdmmc_card_t = std::ptr::null_mut();
+        esp!(esp_vfs_fat_sdspi_mount(mount_point.as_ptr(), &host, &slot_config, &mount_config, &mut card))?;
+    }
+    Ok(())
+}
+
+/// Prepare the export directory. Export is disabled if it is not configured or not available.
+pub fn init() {
+    let (dir, format, retention_days) = {
+        let guard = CONFIG.lock().unwrap();
+        let config = guard.as_ref().unwrap();
+        (config.export_dir.clone(), config.export_format, config.export_retention_days)
+    };
+    if dir.is_empty() {
+        return;
+    }
+    #[cfg(target_os="espidf")]
+    if let Err(err) = mount_sd_card() {
+        log::warn!("Failed to mount the microSD card. Export is disabled - {:?}", err);
+        return;
+    }
+    let dir = Path::new(dir.as_str()).to_path_buf();
+    if let Err(err) = fs::create_dir_all(&dir) {
+        log::warn!("Failed to create {:?}. Export is disabled - {:?}", dir, err);
+        return;
+    }
+    log::info!("Exporting samples to {:?} as {:?}", dir, format);
+    *EXPORTER.lock().unwrap() = Some(Exporter {
+        dir,
+        format,
+        retention_days,
+        current_date: None,
+    });
+}
+
+/// Append the sample to the file of the day.
+pub fn append(record: &SensorRecord, timestamp: &Timestamp) {
+    if let Some(exporter) = EXPORTER.lock().unwrap().as_mut() {
+        // Called by the sample timer while the UI task may be refreshing the display.
+        let _bus = SPI_BUS.lock().unwrap();
+        if let Err(err) = exporter.append(record, timestamp) {
+            log::warn!("Failed to export the sample - {:?}", err);
+        }
+    }
+}
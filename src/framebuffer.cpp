@@ -0,0 +1,84 @@
+// Offscreen LovyanGFX sprite used as the framebuffer of the headless Linux build.
+// The UI is drawn with the same fonts and text layout as on the display, without an SDL window.
+
+#include <LovyanGFX.hpp>
+
+using lgfx::LGFX_Sprite;
+
+// Indexed by `Framebuffer::set_font` in src/framebuffer.rs.
+static const lgfx::IFont* const FONTS[] = {
+    &lgfx::fonts::FreeMono24pt7b,
+    &lgfx::fonts::FreeSans18pt7b,
+    &lgfx::fonts::lgfxJapanGothicP_28,
+    &lgfx::fonts::lgfxJapanGothicP_32,
+};
+
+extern "C" {
+
+LGFX_Sprite* framebuffer_create(int32_t width, int32_t height)
+{
+    auto sprite = new LGFX_Sprite();
+    sprite->setColorDepth(8);
+    if (sprite->createSprite(width, height) == nullptr) {
+        delete sprite;
+        return nullptr;
+    }
+    sprite->fillScreen((uint8_t)0xff);
+    return sprite;
+}
+
+void framebuffer_delete(LGFX_Sprite* sprite)
+{
+    sprite->deleteSprite();
+    delete sprite;
+}
+
+const uint8_t* framebuffer_buffer(LGFX_Sprite* sprite)
+{
+    return static_cast<const uint8_t*>(sprite->getBuffer());
+}
+
+// Bytes per line of the buffer.
+int32_t framebuffer_stride(LGFX_Sprite* sprite)
+{
+    return sprite->bufferLength() / sprite->height();
+}
+
+// The colors are RGB332, which LovyanGFX assumes for 8-bit values.
+void framebuffer_clear(LGFX_Sprite* sprite, uint8_t color)
+{
+    sprite->fillScreen(color);
+}
+
+void framebuffer_fill_rect(LGFX_Sprite* sprite, int32_t x, int32_t y, int32_t w, int32_t h, uint8_t color)
+{
+    sprite->fillRect(x, y, w, h, color);
+}
+
+void framebuffer_draw_line(LGFX_Sprite* sprite, int32_t x0, int32_t y0, int32_t x1, int32_t y1, uint8_t color)
+{
+    sprite->drawLine(x0, y0, x1, y1, color);
+}
+
+void framebuffer_set_font(LGFX_Sprite* sprite, int32_t font)
+{
+    if (0 <= font && font < (int32_t)(sizeof(FONTS) / sizeof(FONTS[0]))) {
+        sprite->setFont(FONTS[font]);
+    }
+}
+
+int32_t framebuffer_font_height(LGFX_Sprite* sprite)
+{
+    return sprite->fontHeight();
+}
+
+// `text` is a NUL-terminated UTF-8 string. The background is not filled if it is the same as the color.
+void framebuffer_draw_string(LGFX_Sprite* sprite, const char* text, int32_t x, int32_t y, uint8_t color, uint8_t background, float scale, bool align_right)
+{
+    sprite->setTextColor(color, background);
+    sprite->setTextSize(scale, scale);
+    sprite->setTextDatum(align_right ? lgfx::textdatum_t::top_right : lgfx::textdatum_t::top_left);
+    sprite->drawString(text, x, y);
+}
+
+}
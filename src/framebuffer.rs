@@ -0,0 +1,124 @@
+use std::{ffi::CString, fs::File, io::BufWriter, path::Path, ptr::NonNull};
+
+use crate::canvas::{Align, Canvas, Color, FontFace};
+
+/// `LGFX_Sprite` of LovyanGFX, accessed through the functions in framebuffer.cpp.
+#[repr(C)]
+struct LgfxSprite {
+    _private: [u8; 0],
+}
+
+extern "C" {
+    fn framebuffer_create(width: i32, height: i32) -> *mut LgfxSprite;
+    fn framebuffer_delete(sprite: *mut LgfxSprite);
+    fn framebuffer_buffer(sprite: *mut LgfxSprite) -> *const u8;
+    fn framebuffer_stride(sprite: *mut LgfxSprite) -> i32;
+    fn framebuffer_clear(sprite: *mut LgfxSprite, color: u8);
+    fn framebuffer_fill_rect(sprite: *mut LgfxSprite, x: i32, y: i32, w: i32, h: i32, color: u8);
+    fn framebuffer_draw_line(sprite: *mut LgfxSprite, x0: i32, y0: i32, x1: i32, y1: i32, color: u8);
+    fn framebuffer_set_font(sprite: *mut LgfxSprite, font: i32);
+    fn framebuffer_font_height(sprite: *mut LgfxSprite) -> i32;
+    fn framebuffer_draw_string(sprite: *mut LgfxSprite, text: *const core::ffi::c_char, x: i32, y: i32, color: u8, background: u8, scale: f32, align_right: bool);
+}
+
+/// In-memory RGB332 framebuffer which can be used instead of the display, e.g. to render screenshots on a headless machine.
+/// The drawing is done by LovyanGFX into an offscreen sprite, so the fonts and the layout are same as on the display.
+pub struct Framebuffer {
+    width: i32,
+    height: i32,
+    sprite: NonNull<LgfxSprite>,
+}
+
+// The sprite is owned by the framebuffer and accessed only through it.
+unsafe impl Send for Framebuffer {}
+
+impl Framebuffer {
+    pub fn new(width: i32, height: i32) -> Self {
+        let sprite = NonNull::new(unsafe { framebuffer_create(width, height) }).expect("Failed to allocate the framebuffer");
+        Self {
+            width,
+            height,
+            sprite,
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// Rows of the RGB332 pixels.
+    fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        let sprite = self.sprite.as_ptr();
+        let (stride, buffer) = unsafe { (framebuffer_stride(sprite) as usize, framebuffer_buffer(sprite)) };
+        let buffer = unsafe { std::slice::from_raw_parts(buffer, stride * self.height as usize) };
+        buffer.chunks_exact(stride).map(move |row| &row[..self.width as usize])
+    }
+
+    /// Convert the framebuffer to RGB888 pixels in row-major order.
+    pub fn to_rgb888(&self) -> Vec<u8> {
+        self.rows().flatten().flat_map(|pixel| Color(*pixel).to_rgb888()).collect()
+    }
+
+    /// Write the framebuffer as PNG. The file is replaced atomically so that readers never see a partial image.
+    pub fn write_png<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let temporary_path = path.with_extension("png.tmp");
+        {
+            let file = File::create(&temporary_path)?;
+            let mut encoder = png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&self.to_rgb888())?;
+        }
+        std::fs::rename(&temporary_path, path)?;
+        Ok(())
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe { framebuffer_delete(self.sprite.as_ptr()) };
+    }
+}
+
+impl Canvas for Framebuffer {
+    fn clear(&mut self, color: Color) {
+        unsafe { framebuffer_clear(self.sprite.as_ptr(), color.0) };
+    }
+
+    fn fill_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: Color) {
+        unsafe { framebuffer_fill_rect(self.sprite.as_ptr(), x, y, width, height, color.0) };
+    }
+
+    fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+        unsafe { framebuffer_draw_line(self.sprite.as_ptr(), x0, y0, x1, y1, color.0) };
+    }
+
+    fn set_font(&mut self, font: FontFace) {
+        // Index of the font in framebuffer.cpp.
+        let font = match font {
+            FontFace::FreeMono24 => 0,
+            FontFace::FreeSans18 => 1,
+            FontFace::JapanGothicP28 => 2,
+            FontFace::JapanGothicP32 => 3,
+        };
+        unsafe { framebuffer_set_font(self.sprite.as_ptr(), font) };
+    }
+
+    fn font_height(&mut self) -> i32 {
+        unsafe { framebuffer_font_height(self.sprite.as_ptr()) }
+    }
+
+    fn draw_string(&mut self, text: &str, x: i32, y: i32, color: Color, background: Color, scale: f32, align: Align) {
+        let text = match CString::new(text) {
+            Ok(text) => text,
+            Err(_) => return,
+        };
+        unsafe { framebuffer_draw_string(self.sprite.as_ptr(), text.as_ptr(), x, y, color.0, background.0, scale, align == Align::Right) };
+    }
+}
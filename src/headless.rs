@@ -0,0 +1,80 @@
+use std::{io::BufRead, path::PathBuf, sync::{Arc, Mutex}};
+
+use crate::framebuffer::Framebuffer;
+use crate::input::{self, Button, InputEvent, SwipeDirection};
+use crate::snapshot::SnapshotMode;
+
+const DEFAULT_SCREENSHOT_PATH: &str = "screenshot.png";
+
+/// Options to run the Linux build without the SDL window.
+#[derive(Clone, Debug, Default)]
+pub struct HeadlessOptions {
+    /// Write a screenshot to this path at each refresh.
+    pub screenshot_path: Option<PathBuf>,
+    /// Run the snapshot tests instead of the application.
+    pub snapshot: Option<SnapshotMode>,
+}
+
+/// Read commands from the standard input to operate the headless UI.
+///
+/// * `tap X Y` / `longpress X Y`
+/// * `swipe left|right|up|down`
+/// * `button up|push|down`
+/// * `screenshot [PATH]` writes the current framebuffer.
+pub fn command_loop(framebuffer: Arc<Mutex<Framebuffer>>) {
+    let stdin = std::io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let point = || -> Option<(i32, i32)> {
+            Some((tokens.get(1)?.parse().ok()?, tokens.get(2)?.parse().ok()?))
+        };
+        match tokens.first().copied() {
+            Some("tap") => match point() {
+                Some((x, y)) => input::push_event(InputEvent::Tap { x, y }),
+                None => log::warn!("usage: tap X Y"),
+            },
+            Some("longpress") => match point() {
+                Some((x, y)) => input::push_event(InputEvent::LongPress { x, y }),
+                None => log::warn!("usage: longpress X Y"),
+            },
+            Some("swipe") => {
+                let direction = match tokens.get(1).copied() {
+                    Some("left") => Some(SwipeDirection::Left),
+                    Some("right") => Some(SwipeDirection::Right),
+                    Some("up") => Some(SwipeDirection::Up),
+                    Some("down") => Some(SwipeDirection::Down),
+                    _ => None,
+                };
+                match direction {
+                    Some(direction) => input::push_event(InputEvent::Swipe { direction, x: 0, y: 0 }),
+                    None => log::warn!("usage: swipe left|right|up|down"),
+                }
+            },
+            Some("button") => {
+                let button = match tokens.get(1).copied() {
+                    Some("up") => Some(Button::Up),
+                    Some("push") => Some(Button::Push),
+                    Some("down") => Some(Button::Down),
+                    _ => None,
+                };
+                match button {
+                    Some(button) => input::push_event(InputEvent::Button(button)),
+                    None => log::warn!("usage: button up|push|down"),
+                }
+            },
+            Some("screenshot") => {
+                let path = tokens.get(1).copied().unwrap_or(DEFAULT_SCREENSHOT_PATH);
+                match framebuffer.lock().unwrap().write_png(path) {
+                    Ok(_) => log::info!("Screenshot written to {}", path),
+                    Err(err) => log::error!("Failed to write screenshot to {} - {:?}", path, err),
+                }
+            },
+            Some(command) => log::warn!("Unknown command: {}", command),
+            None => {},
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Read-only HTTP API to pull the sampled data from the monitor without accessing the Cloud API.
+//!
+//! - `GET /api/current`: the latest sample
+//! - `GET /api/history?range=24h&format=csv`: the samples in the range, as JSON (default) or CSV
+//! - `GET /api/status`: Wi-Fi state, rate limit of the Cloud API, uptime and the last error
+//! - `GET /metrics`: metrics in the Prometheus text format
+
+use std::{fmt::Write, time::Duration};
+
+use crate::{
+    connectivity, sample_interval, SensorRecord, Timestamp, SENSOR_RECORDS,
+    STARTED_AT,
+};
+
+/// Paths served by `handle`.
+pub const PATHS: [&str; 4] = ["/api/current", "/api/history", "/api/status", "/metrics"];
+const DEFAULT_HISTORY_RANGE: Duration = Duration::from_secs(24 * 60 * 60);
+const CSV_HEADER: &str = "timestamp,temperature,humidity,illuminance,power\n";
+
+pub struct ApiResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl ApiResponse {
+    fn json(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        let mut body = String::from("{\"error\":");
+        write_json_string(&mut body, message).ok();
+        body.push('}');
+        Self {
+            status,
+            content_type: "application/json",
+            body,
+        }
+    }
+}
+
+/// Write the value as a JSON string, with the quotes and the escapes.
+pub fn write_json_string<W: Write>(writer: &mut W, value: &str) -> std::fmt::Result {
+    writer.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => writer.write_str("\\\"")?,
+            '\\' => writer.write_str("\\\\")?,
+            c if (c as u32) < 0x20 => write!(writer, "\\u{:04x}", c as u32)?,
+            c => writer.write_char(c)?,
+        }
+    }
+    writer.write_char('"')
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&')
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Parse a range such as "30m", "24h" or "7d".
+fn parse_range(range: &str) -> Option<Duration> {
+    let unit = match range.chars().last()? {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    let value: u64 = range[..range.len() - 1].parse().ok()?;
+    Some(Duration::from_secs(value.checked_mul(unit)?))
+}
+
+/// Copy the records in the range with their timestamps. Only the timestamp of the latest record is stored,
+/// so the others are calculated from the sample interval in use.
+fn history_records(range: Duration) -> Vec<(SensorRecord, Timestamp)> {
+    let interval = sample_interval();
+    let sensor_records = SENSOR_RECORDS.lock().unwrap();
+    let last_timestamp = match sensor_records.last_timestamp() {
+        Some(timestamp) => timestamp,
+        None => return Vec::new(),
+    };
+    let count = sensor_records.len();
+    let skip = count.saturating_sub((range.as_secs() / interval.as_secs()) as usize);
+    sensor_records.iter()
+        .enumerate()
+        .skip(skip)
+        .map(|(index, record)| {
+            let age = chrono::Duration::from_std(interval * (count - 1 - index) as u32).unwrap_or_else(|_| chrono::Duration::zero());
+            (*record, last_timestamp - age)
+        })
+        .collect()
+}
+
+fn current() -> ApiResponse {
+    let latest = SENSOR_RECORDS.lock().unwrap().latest();
+    match latest {
+        Some((record, timestamp)) => {
+            let mut body = String::new();
+            record.write_json(&mut body, &timestamp).ok();
+            ApiResponse::json(body)
+        },
+        None => ApiResponse::error(404, "no record yet"),
+    }
+}
+
+fn history(query: &str) -> ApiResponse {
+    let range = match query_param(query, "range") {
+        Some(range) => match parse_range(range) {
+            Some(range) => range,
+            None => return ApiResponse::error(400, "invalid range"),
+        },
+        None => DEFAULT_HISTORY_RANGE,
+    };
+    let records = history_records(range);
+    match query_param(query, "format").unwrap_or("json") {
+        "json" => {
+            let mut body = String::from("[");
+            for (index, (record, timestamp)) in records.iter().enumerate() {
+                if index > 0 {
+                    body.push(',');
+                }
+                record.write_json(&mut body, timestamp).ok();
+            }
+            body.push(']');
+            ApiResponse::json(body)
+        },
+        "csv" => {
+            let mut body = String::from(CSV_HEADER);
+            for (record, timestamp) in records.iter() {
+                writeln!(&mut body, "{},{:.1},{:.0},{:.0},{:.0}",
+                    timestamp.to_rfc3339(),
+                    record.ambient_temperature,
+                    record.relative_humidity,
+                    record.ambient_luminous_level,
+                    record.instant_power_usage,
+                ).ok();
+            }
+            ApiResponse {
+                status: 200,
+                content_type: "text/csv",
+                body,
+            }
+        },
+        _ => ApiResponse::error(400, "invalid format"),
+    }
+}
+
+fn status() -> ApiResponse {
+    let uptime = STARTED_AT.lock().unwrap().map(|started_at| started_at.elapsed().as_secs()).unwrap_or(0);
+    let status = connectivity::status();
+    let mut body = String::new();
+    write!(&mut body, "{{\"wifi_connected\":{},\"ssid\":", status.wifi.connected).ok();
+    write_json_string(&mut body, &status.wifi.ssid).ok();
+    match status.wifi.rssi {
+        Some(rssi) => write!(&mut body, ",\"rssi\":{}", rssi).ok(),
+        None => write!(&mut body, ",\"rssi\":null").ok(),
+    };
+    match status.wifi.ip {
+        Some(ip) => write!(&mut body, ",\"ip\":\"{}\"", ip).ok(),
+        None => write!(&mut body, ",\"ip\":null").ok(),
+    };
+    write!(&mut body, ",\"time_synced\":{},\"rate_limit\":", status.time_synced).ok();
+    status.rate_limit.write_json(&mut body).ok();
+    match status.last_fetched {
+        Some(timestamp) => write!(&mut body, ",\"last_fetched\":\"{}\"", timestamp.to_rfc3339()).ok(),
+        None => write!(&mut body, ",\"last_fetched\":null").ok(),
+    };
+    write!(&mut body, ",\"uptime\":{},\"records\":{},\"last_error\":", uptime, SENSOR_RECORDS.lock().unwrap().len()).ok();
+    match status.last_error.as_ref() {
+        Some((message, timestamp)) => {
+            body.push_str("{\"message\":");
+            write_json_string(&mut body, message).ok();
+            write!(&mut body, ",\"timestamp\":\"{}\"}}", timestamp.to_rfc3339()).ok();
+        },
+        None => body.push_str("null"),
+    }
+    body.push('}');
+    ApiResponse::json(body)
+}
+
+/// Handle a GET request to the URI, which may contain the query string.
+pub fn handle(uri: &str) -> ApiResponse {
+    let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+    match path {
+        "/api/current" => current(),
+        "/api/history" => history(query),
+        "/api/status" => status(),
+        "/metrics" => ApiResponse {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body: crate::metrics::render(),
+        },
+        _ => ApiResponse::error(404, "not found"),
+    }
+}
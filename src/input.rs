@@ -0,0 +1,194 @@
+use std::{sync::{Mutex, Condvar}, time::{Duration, Instant}};
+
+#[cfg(target_os="espidf")]
+pub use crate::input_esp::InputDevice;
+#[cfg(target_os="linux")]
+pub use crate::input_linux::InputDevice;
+
+/// Buttons of the 3-way switch on the side of M5Paper.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Up,
+    Push,
+    Down,
+}
+
+impl Button {
+    pub const ALL: [Button; 3] = [Button::Up, Button::Push, Button::Down];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputEvent {
+    Tap { x: i32, y: i32 },
+    LongPress { x: i32, y: i32 },
+    /// Swipe gesture. `x` and `y` are the coordinates where the touch started.
+    Swipe { direction: SwipeDirection, x: i32, y: i32 },
+    Button(Button),
+    /// State shown on the screen has been changed in the background.
+    Refresh,
+}
+
+/// A touch point in the screen coordinates (after rotation).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TouchPoint {
+    pub x: i32,
+    pub y: i32,
+}
+
+const LONG_PRESS_DURATION: Duration = Duration::from_millis(600);
+const SWIPE_THRESHOLD: i32 = 80;
+const TAP_SLOP: i32 = 20;
+
+/// Converts a sequence of raw touch samples into tap/long-press/swipe events.
+pub struct GestureRecognizer {
+    start: Option<(TouchPoint, Instant)>,
+    last: TouchPoint,
+    long_pressed: bool,
+}
+
+impl GestureRecognizer {
+    pub const fn new() -> Self {
+        Self {
+            start: None,
+            last: TouchPoint { x: 0, y: 0 },
+            long_pressed: false,
+        }
+    }
+
+    /// Feed the current touch state. `None` means the panel is not touched.
+    pub fn update(&mut self, touch: Option<TouchPoint>, now: Instant) -> Option<InputEvent> {
+        match (self.start, touch) {
+            (None, Some(point)) => {
+                // Touch started.
+                self.start = Some((point, now));
+                self.last = point;
+                self.long_pressed = false;
+                None
+            },
+            (Some((start, started_at)), Some(point)) => {
+                // Touch continues.
+                self.last = point;
+                if !self.long_pressed && !Self::has_moved(start, point, TAP_SLOP) && now.duration_since(started_at) >= LONG_PRESS_DURATION {
+                    self.long_pressed = true;
+                    Some(InputEvent::LongPress { x: start.x, y: start.y })
+                } else {
+                    None
+                }
+            },
+            (Some((start, _)), None) => {
+                // Touch released.
+                self.start = None;
+                let end = self.last;
+                let dx = end.x - start.x;
+                let dy = end.y - start.y;
+                if Self::has_moved(start, end, SWIPE_THRESHOLD) {
+                    let direction = if dx.abs() >= dy.abs() {
+                        if dx > 0 { SwipeDirection::Right } else { SwipeDirection::Left }
+                    } else {
+                        if dy > 0 { SwipeDirection::Down } else { SwipeDirection::Up }
+                    };
+                    Some(InputEvent::Swipe { direction, x: start.x, y: start.y })
+                } else if !self.long_pressed && !Self::has_moved(start, end, TAP_SLOP) {
+                    Some(InputEvent::Tap { x: start.x, y: start.y })
+                } else {
+                    None
+                }
+            },
+            (None, None) => None,
+        }
+    }
+
+    fn has_moved(start: TouchPoint, end: TouchPoint, threshold: i32) -> bool {
+        (end.x - start.x).abs() >= threshold || (end.y - start.y).abs() >= threshold
+    }
+}
+
+/// Detects press edges of the buttons.
+pub struct ButtonState {
+    pressed: [bool; 3],
+}
+
+impl ButtonState {
+    pub const fn new() -> Self {
+        Self { pressed: [false; 3] }
+    }
+
+    pub fn update<F: FnMut(InputEvent)>(&mut self, pressed: [bool; 3], mut on_event: F) {
+        for (index, button) in Button::ALL.iter().enumerate() {
+            if pressed[index] && !self.pressed[index] {
+                on_event(InputEvent::Button(*button));
+            }
+        }
+        self.pressed = pressed;
+    }
+}
+
+const INPUT_EVENT_CAPACITY: usize = 16;
+static INPUT_EVENTS: Mutex<heapless::Deque<InputEvent, INPUT_EVENT_CAPACITY>> = Mutex::new(heapless::Deque::new());
+static INPUT_EVENT_ARRIVED: Condvar = Condvar::new();
+
+pub fn push_event(event: InputEvent) {
+    log::info!("input event: {:?}", event);
+    let mut events = INPUT_EVENTS.lock().unwrap();
+    if events.is_full() {
+        // Drop the oldest event so that the latest operation is not lost.
+        events.pop_front();
+    }
+    events.push_back(event).ok();
+    INPUT_EVENT_ARRIVED.notify_all();
+}
+
+/// Waits until an input event arrives or the timeout elapses.
+pub fn wait_event(timeout: Duration) -> Option<InputEvent> {
+    let events = INPUT_EVENTS.lock().unwrap();
+    let (mut events, _) = INPUT_EVENT_ARRIVED.wait_timeout_while(events, timeout, |events| events.is_empty()).unwrap();
+    events.pop_front()
+}
+
+/// Polls an input device and pushes the recognized events to the event queue.
+pub struct InputPoller {
+    device: InputDevice,
+    gesture: GestureRecognizer,
+    buttons: ButtonState,
+}
+
+impl InputPoller {
+    pub const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+    pub fn new(device: InputDevice) -> Self {
+        Self {
+            device,
+            gesture: GestureRecognizer::new(),
+            buttons: ButtonState::new(),
+        }
+    }
+
+    pub fn poll(&mut self) {
+        let touch = match self.device.read_touch() {
+            Ok(touch) => touch,
+            Err(err) => {
+                log::warn!("Failed to read touch panel - {:?}", err);
+                None
+            },
+        };
+        if let Some(event) = self.gesture.update(touch, Instant::now()) {
+            push_event(event);
+        }
+        self.buttons.update(self.device.read_buttons(), push_event);
+    }
+}
+
+pub fn input_task(mut poller: InputPoller) -> ! {
+    loop {
+        poller.poll();
+        std::thread::sleep(InputPoller::POLL_INTERVAL);
+    }
+}
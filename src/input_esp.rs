@@ -0,0 +1,107 @@
+use esp_idf_hal::{i2c::I2cDriver, gpio::{PinDriver, AnyInputPin, Input}, delay::BLOCK};
+use esp_idf_sys::EspError;
+
+use crate::input::TouchPoint;
+
+// GT911 capacitive touch controller on M5Paper.
+// The I2C address depends on the INT pin level at reset.
+const GT911_ADDRESSES: [u8; 2] = [0x14, 0x5d];
+const GT911_REG_PRODUCT_ID: u16 = 0x8140;
+const GT911_REG_STATUS: u16 = 0x814e;
+const GT911_REG_POINT1: u16 = 0x814f;
+const GT911_STATUS_BUFFER_READY: u8 = 0x80;
+const GT911_STATUS_TOUCH_COUNT_MASK: u8 = 0x0f;
+
+// Native resolution of the panel in portrait orientation.
+const PANEL_WIDTH: i32 = 540;
+
+pub struct Gt911 {
+    i2c: I2cDriver<'static>,
+    address: u8,
+    last_point: Option<TouchPoint>,
+}
+
+impl Gt911 {
+    pub fn new(mut i2c: I2cDriver<'static>) -> anyhow::Result<Self> {
+        for address in GT911_ADDRESSES {
+            let mut product_id = [0u8; 4];
+            if i2c.write_read(address, &GT911_REG_PRODUCT_ID.to_be_bytes(), &mut product_id, BLOCK).is_ok() {
+                log::info!("GT911 found at {:#04x}, product id: {:?}", address, core::str::from_utf8(&product_id));
+                return Ok(Self {
+                    i2c,
+                    address,
+                    last_point: None,
+                });
+            }
+        }
+        Err(anyhow::anyhow!("GT911 not found"))
+    }
+
+    fn read_register(&mut self, register: u16, buffer: &mut [u8]) -> Result<(), EspError> {
+        self.i2c.write_read(self.address, &register.to_be_bytes(), buffer, BLOCK)
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) -> Result<(), EspError> {
+        let register = register.to_be_bytes();
+        self.i2c.write(self.address, &[register[0], register[1], value], BLOCK)
+    }
+
+    /// Read the first touch point. Returns the previous state if the controller has no new data.
+    pub fn read_touch(&mut self) -> Result<Option<TouchPoint>, EspError> {
+        let mut status = [0u8; 1];
+        self.read_register(GT911_REG_STATUS, &mut status)?;
+        if status[0] & GT911_STATUS_BUFFER_READY == 0 {
+            return Ok(self.last_point);
+        }
+        let point = if status[0] & GT911_STATUS_TOUCH_COUNT_MASK > 0 {
+            // track id, x (LE), y (LE), size (LE), reserved
+            let mut data = [0u8; 8];
+            self.read_register(GT911_REG_POINT1, &mut data)?;
+            let raw_x = u16::from_le_bytes([data[1], data[2]]) as i32;
+            let raw_y = u16::from_le_bytes([data[3], data[4]]) as i32;
+            // The display is rotated to landscape (rotation 1).
+            Some(TouchPoint {
+                x: raw_y,
+                y: PANEL_WIDTH - 1 - raw_x,
+            })
+        } else {
+            None
+        };
+        // Clear the buffer ready flag to receive the next data.
+        self.write_register(GT911_REG_STATUS, 0)?;
+        self.last_point = point;
+        Ok(point)
+    }
+}
+
+/// Touch panel and the 3-way switch (G37: up, G38: push, G39: down) of M5Paper.
+pub struct InputDevice {
+    touch: Gt911,
+    buttons: [PinDriver<'static, AnyInputPin, Input>; 3],
+}
+
+impl InputDevice {
+    pub fn new(touch: Gt911, up: AnyInputPin, push: AnyInputPin, down: AnyInputPin) -> anyhow::Result<Self> {
+        Ok(Self {
+            touch,
+            buttons: [
+                PinDriver::input(up)?,
+                PinDriver::input(push)?,
+                PinDriver::input(down)?,
+            ],
+        })
+    }
+
+    pub fn read_touch(&mut self) -> Result<Option<TouchPoint>, EspError> {
+        self.touch.read_touch()
+    }
+
+    /// The switches are active low.
+    pub fn read_buttons(&mut self) -> [bool; 3] {
+        [
+            self.buttons[0].is_low(),
+            self.buttons[1].is_low(),
+            self.buttons[2].is_low(),
+        ]
+    }
+}
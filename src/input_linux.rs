@@ -0,0 +1,50 @@
+use std::os::raw::c_int;
+
+use crate::input::TouchPoint;
+
+// SDL2 is already linked for the LovyanGFX SDL panel.
+extern "C" {
+    fn SDL_GetMouseState(x: *mut c_int, y: *mut c_int) -> u32;
+    fn SDL_GetKeyboardState(numkeys: *mut c_int) -> *const u8;
+}
+
+const SDL_BUTTON_LMASK: u32 = 1 << 0;
+const SDL_SCANCODE_RETURN: usize = 40;
+const SDL_SCANCODE_DOWN: usize = 81;
+const SDL_SCANCODE_UP: usize = 82;
+
+/// Emulates the touch panel with the left mouse button and the 3-way switch with Up/Enter/Down keys.
+/// The state is updated by `Gfx::handle_sdl_event`, so this must be polled from the thread which handles the SDL events.
+pub struct InputDevice {}
+
+impl InputDevice {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn read_touch(&mut self) -> Result<Option<TouchPoint>, ()> {
+        let mut x: c_int = 0;
+        let mut y: c_int = 0;
+        let buttons = unsafe { SDL_GetMouseState(&mut x, &mut y) };
+        if buttons & SDL_BUTTON_LMASK != 0 {
+            Ok(Some(TouchPoint { x: x as i32, y: y as i32 }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn read_buttons(&mut self) -> [bool; 3] {
+        let mut numkeys: c_int = 0;
+        let state = unsafe { SDL_GetKeyboardState(&mut numkeys) };
+        if state.is_null() {
+            return [false; 3];
+        }
+        let state = unsafe { std::slice::from_raw_parts(state, numkeys as usize) };
+        let is_pressed = |scancode: usize| state.get(scancode).map_or(false, |s| *s != 0);
+        [
+            is_pressed(SDL_SCANCODE_UP),
+            is_pressed(SDL_SCANCODE_RETURN),
+            is_pressed(SDL_SCANCODE_DOWN),
+        ]
+    }
+}
@@ -6,6 +6,7 @@ mod espidf_imports {
     pub use super::comm_esp::*;
     pub use embedded_svc::{wifi::*, timer::*, http::client::Response};
     pub use esp_idf_hal::prelude::Peripherals;
+    pub use esp_idf_hal::{i2c::{I2cDriver, I2cConfig}, gpio::InputPin, units::FromValueType};
     pub use esp_idf_svc::{netif::*, wifi::*, timer::*, nvs::*, eventloop::EspSystemEventLoop};
     pub use esp_idf_sys as _; // If using the `binstart` feature of `esp-idf-sys`, always keep this module imported
     pub use embedded_svc::http::Headers;
@@ -18,7 +19,14 @@ mod comm_linux;
 #[cfg(target_os="linux")]
 use comm_linux::*;
 
-use std::{sync::{Arc, Mutex}, time::Duration, str::FromStr, fmt::Write, ffi::CStr};
+mod input;
+#[cfg(target_os="espidf")]
+mod input_esp;
+#[cfg(target_os="linux")]
+mod input_linux;
+use input::{InputPoller, InputDevice};
+
+use std::{sync::{Arc, Mutex}, net::SocketAddrV4, time::{Duration, Instant}, str::FromStr, fmt::Write, ffi::CStr};
 
 use anyhow::anyhow;
 use embedded_io::blocking::Read;
@@ -47,17 +55,120 @@ mod config;
 use config::*;
 
 mod chart;
-use chart::Chart;
+
+mod clock;
+
+mod mdns;
+mod remo_local;
+mod echonet;
+mod mqtt;
+mod http_api;
+mod metrics;
+mod export;
+mod alert;
+mod aircon;
+mod remote;
+mod button_appliance;
+mod automation;
+mod power;
+mod connectivity;
+use metrics::FetchTarget;
+
+mod canvas;
+mod display;
+use display::Display;
+#[cfg(target_os="linux")]
+mod framebuffer;
+#[cfg(target_os="linux")]
+mod headless;
+#[cfg(target_os="linux")]
+mod options;
+#[cfg(target_os="linux")]
+mod capture;
+#[cfg(target_os="linux")]
+mod snapshot;
+/// The mock server of the Cloud API, to test the fetches against it.
+#[cfg(all(test, target_os="linux"))]
+#[allow(dead_code)]
+#[path = "bin/mock_server.rs"]
+mod mock_server;
+
+mod ui;
 
 #[derive(Default, Debug)]
 struct Config {
-    wifi_ssid: heapless::String<32>,
-    wifi_password: heapless::String<64>,
+    /// Wi-Fi networks tried in order.
+    wifi_networks: connectivity::WifiNetworks,
     device_id: Uuid,
     appliance_id: Uuid,
     access_token: heapless::String<128>,
+    /// Base URL of the Cloud API. Can be pointed to the mock server.
+    api_base_url: heapless::String<64>,
+    language: ui::Language,
+    /// Time zone to show the time, as a POSIX TZ string.
+    time_zone: heapless::String<32>,
+    power_source: PowerSource,
+    /// ECHONET Lite node to read the smart meter from. Discovered by multicast if not specified.
+    echonet_node: Option<SocketAddrV4>,
+    /// MQTT broker as "host:port". MQTT publishing is disabled if empty.
+    mqtt_broker: heapless::String<64>,
+    mqtt_username: heapless::String<32>,
+    mqtt_password: heapless::String<64>,
+    mqtt_topic_prefix: heapless::String<32>,
+    /// Port of the HTTP API. Disabled if 0.
+    http_port: u16,
+    /// Directory to export the samples. Disabled if empty.
+    export_dir: heapless::String<64>,
+    export_format: export::ExportFormat,
+    /// Exported files older than this are deleted. Kept forever if 0.
+    export_retention_days: u32,
+    /// Alert rules separated by ';'. See `alert::AlertRule::parse`.
+    alert_rules: heapless::String<256>,
+    /// URL to POST the raised and cleared alerts. Disabled if empty.
+    alert_webhook_url: heapless::String<128>,
+    /// GPIO of the active buzzer. Disabled if not specified.
+    buzzer_pin: Option<u8>,
+    /// Appliances whose signals are shown on the remote page. All appliances if empty.
+    remote_appliances: heapless::Vec<Uuid, { remote::MAX_REMOTE_APPLIANCES }>,
+    /// Automation rules separated by ';'. See `automation::AutomationRule::parse`.
+    automation_rules: heapless::String<512>,
+    /// Interval of the low-power mode in minutes, which sleeps between the updates. Disabled if 0.
+    sleep_interval_minutes: u16,
+}
+
+/// Where the instantaneous power is read from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum PowerSource {
+    /// The smart meter appliance of the Cloud API.
+    #[default]
+    Cloud,
+    /// The smart meter on the LAN via ECHONET Lite.
+    EchonetLite,
+}
+
+impl PowerSource {
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "cloud" => Some(PowerSource::Cloud),
+            "echonet" => Some(PowerSource::EchonetLite),
+            _ => None,
+        }
+    }
 }
 
+const DEFAULT_API_BASE_URL: &str = "https://api.nature.global";
+const DEFAULT_TIME_ZONE: &str = "JST-9";
+const DEFAULT_MQTT_TOPIC_PREFIX: &str = "m5paper-remo";
+const DEFAULT_HTTP_PORT: u16 = 80;
+const DEFAULT_EXPORT_DIR: &str = "/sdcard/remo";
+const DEFAULT_EXPORT_RETENTION_DAYS: u32 = 365;
+/// Timeout of the Cloud API requests.
+#[cfg(not(test))]
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Shorter in the tests, so that the slow responses of the mock server time out quickly.
+#[cfg(test)]
+const HTTP_TIMEOUT: Duration = Duration::from_secs(1);
+
 static CONFIG: Mutex<Option<Config>> = Mutex::new(None);
 
 #[cfg(target_os="espidf")]
@@ -68,8 +179,24 @@ fn init_config() {
         let nvs_partition = nvs_partition.clone();
         let nvs = EspDefaultNvs::new(nvs_partition, "wifi", false).unwrap();
         let mut buffer = [0u8; 128];
-        config.wifi_ssid = heapless::String::from_str(nvs.get_str("ssid", &mut buffer).unwrap().unwrap_or("")).unwrap();
-        config.wifi_password = heapless::String::from_str(nvs.get_str("pass", &mut buffer).unwrap().unwrap_or("")).unwrap();
+        // The first network is "ssid" and "pass", and the others are "ssid1" and "pass1" and so on.
+        for index in 0..connectivity::MAX_WIFI_NETWORKS {
+            let mut ssid_key = heapless::String::<8>::new();
+            let mut pass_key = heapless::String::<8>::new();
+            if index == 0 {
+                write!(&mut ssid_key, "ssid").ok();
+                write!(&mut pass_key, "pass").ok();
+            } else {
+                write!(&mut ssid_key, "ssid{}", index).ok();
+                write!(&mut pass_key, "pass{}", index).ok();
+            }
+            let ssid = match nvs.get_str(&ssid_key, &mut buffer).unwrap() {
+                Some(ssid) if !ssid.is_empty() => heapless::String::from_str(ssid).unwrap(),
+                _ => continue,
+            };
+            let password = heapless::String::from_str(nvs.get_str(&pass_key, &mut buffer).unwrap().unwrap_or("")).unwrap();
+            config.wifi_networks.push(connectivity::WifiNetwork { ssid, password }).ok();
+        }
     }
     {
         let nvs_partition = nvs_partition.clone();
@@ -78,6 +205,68 @@ fn init_config() {
         config.device_id = Uuid::from_str(nvs.get_str("device_id", &mut buffer).unwrap().unwrap_or("")).unwrap_or_default();
         config.appliance_id = Uuid::from_str(nvs.get_str("appliance_id", &mut buffer).unwrap().unwrap_or("")).unwrap_or_default();
         config.access_token = heapless::String::from_str(nvs.get_str("access_token", &mut buffer).unwrap().unwrap_or("")).unwrap();
+        config.api_base_url = heapless::String::from_str(nvs.get_str("api_base_url", &mut buffer).unwrap().unwrap_or(DEFAULT_API_BASE_URL)).unwrap();
+        config.power_source = nvs.get_str("power_source", &mut buffer).unwrap().and_then(PowerSource::from_code).unwrap_or_default();
+        config.echonet_node = nvs.get_str("echonet_node", &mut buffer).unwrap().and_then(|node| SocketAddrV4::from_str(node).ok());
+    }
+    {
+        let nvs_partition = nvs_partition.clone();
+        let nvs = EspDefaultNvs::new(nvs_partition, "mqtt", false).unwrap();
+        let mut buffer = [0u8; 128];
+        config.mqtt_broker = heapless::String::from_str(nvs.get_str("broker", &mut buffer).unwrap().unwrap_or("")).unwrap();
+        config.mqtt_username = heapless::String::from_str(nvs.get_str("username", &mut buffer).unwrap().unwrap_or("")).unwrap();
+        config.mqtt_password = heapless::String::from_str(nvs.get_str("password", &mut buffer).unwrap().unwrap_or("")).unwrap();
+        config.mqtt_topic_prefix = heapless::String::from_str(nvs.get_str("topic_prefix", &mut buffer).unwrap().unwrap_or(DEFAULT_MQTT_TOPIC_PREFIX)).unwrap();
+    }
+    {
+        let nvs_partition = nvs_partition.clone();
+        let nvs = EspDefaultNvs::new(nvs_partition, "http", false).unwrap();
+        config.http_port = nvs.get_u16("port").unwrap().unwrap_or(DEFAULT_HTTP_PORT);
+    }
+    {
+        let nvs_partition = nvs_partition.clone();
+        let nvs = EspDefaultNvs::new(nvs_partition, "export", false).unwrap();
+        let mut buffer = [0u8; 64];
+        config.export_dir = heapless::String::from_str(nvs.get_str("dir", &mut buffer).unwrap().unwrap_or(DEFAULT_EXPORT_DIR)).unwrap();
+        config.export_format = nvs.get_str("format", &mut buffer).unwrap().and_then(export::ExportFormat::from_code).unwrap_or_default();
+        config.export_retention_days = nvs.get_u32("retention_days").unwrap().unwrap_or(DEFAULT_EXPORT_RETENTION_DAYS);
+    }
+    {
+        let nvs_partition = nvs_partition.clone();
+        let nvs = EspDefaultNvs::new(nvs_partition, "alert", false).unwrap();
+        let mut buffer = [0u8; 256];
+        config.alert_rules = heapless::String::from_str(nvs.get_str("rules", &mut buffer).unwrap().unwrap_or("")).unwrap();
+        config.alert_webhook_url = heapless::String::from_str(nvs.get_str("webhook_url", &mut buffer).unwrap().unwrap_or("")).unwrap();
+        config.buzzer_pin = nvs.get_u8("buzzer_pin").unwrap();
+    }
+    {
+        let nvs_partition = nvs_partition.clone();
+        let nvs = EspDefaultNvs::new(nvs_partition, "remote", false).unwrap();
+        let mut buffer = [0u8; 160];
+        config.remote_appliances = remote::parse_appliance_ids(nvs.get_str("appliances", &mut buffer).unwrap().unwrap_or(""));
+    }
+    {
+        let nvs_partition = nvs_partition.clone();
+        let nvs = EspDefaultNvs::new(nvs_partition, "automation", false).unwrap();
+        let mut buffer = [0u8; 512];
+        config.automation_rules = heapless::String::from_str(nvs.get_str("rules", &mut buffer).unwrap().unwrap_or("")).unwrap();
+    }
+    {
+        let nvs_partition = nvs_partition.clone();
+        let nvs = EspDefaultNvs::new(nvs_partition, "power", false).unwrap();
+        config.sleep_interval_minutes = nvs.get_u16("sleep_minutes").unwrap().unwrap_or(0);
+    }
+    {
+        let nvs_partition = nvs_partition.clone();
+        let nvs = EspDefaultNvs::new(nvs_partition, "ui", false).unwrap();
+        let mut buffer = [0u8; 16];
+        config.language = nvs.get_str("language", &mut buffer).unwrap().and_then(ui::Language::from_code).unwrap_or_default();
+    }
+    {
+        let nvs_partition = nvs_partition.clone();
+        let nvs = EspDefaultNvs::new(nvs_partition, "time", false).unwrap();
+        let mut buffer = [0u8; 32];
+        config.time_zone = heapless::String::from_str(nvs.get_str("tz", &mut buffer).unwrap().unwrap_or(DEFAULT_TIME_ZONE)).unwrap();
     }
     log::info!("init_config {:?}", config);
     *CONFIG.lock().unwrap() = Some(config);
@@ -85,11 +274,43 @@ fn init_config() {
 #[cfg(target_os="linux")]
 fn init_config() {
     let config = Config {
-        wifi_ssid: heapless::String::from_str(config::WIFI_AP).unwrap(),
-        wifi_password: heapless::String::from_str(config::WIFI_PASS).unwrap(),
+        wifi_networks: core::iter::once((config::WIFI_AP, config::WIFI_PASS))
+            .chain(config::WIFI_NETWORKS.iter().copied())
+            .take(connectivity::MAX_WIFI_NETWORKS)
+            .map(|(ssid, password)| connectivity::WifiNetwork {
+                ssid: heapless::String::from_str(ssid).unwrap(),
+                password: heapless::String::from_str(password).unwrap(),
+            })
+            .collect(),
         device_id: config::SENSOR_REMO_DEVICE_ID,
         appliance_id: config::ECHONETLITE_APPLIANCE_ID,
         access_token: heapless::String::from_str(config::ACCESS_TOKEN).unwrap(),
+        // REMO_API_BASE_URL overrides the configuration, e.g. to use the mock server.
+        api_base_url: heapless::String::from_str(&std::env::var("REMO_API_BASE_URL").unwrap_or(config::API_BASE_URL.into())).unwrap(),
+        language: ui::Language::from_code(config::LANGUAGE).unwrap_or_default(),
+        // TZ overrides the configuration.
+        time_zone: heapless::String::from_str(&std::env::var("TZ").unwrap_or(config::TIME_ZONE.into())).unwrap(),
+        power_source: PowerSource::from_code(config::POWER_SOURCE).unwrap_or_default(),
+        // ECHONET_NODE overrides the configuration, e.g. to use the responder of the mock server.
+        echonet_node: SocketAddrV4::from_str(&std::env::var("ECHONET_NODE").unwrap_or(config::ECHONET_NODE.into())).ok(),
+        // MQTT_BROKER overrides the configuration, e.g. to use a local broker.
+        mqtt_broker: heapless::String::from_str(&std::env::var("MQTT_BROKER").unwrap_or(config::MQTT_BROKER.into())).unwrap(),
+        mqtt_username: heapless::String::from_str(config::MQTT_USERNAME).unwrap(),
+        mqtt_password: heapless::String::from_str(config::MQTT_PASSWORD).unwrap(),
+        mqtt_topic_prefix: heapless::String::from_str(config::MQTT_TOPIC_PREFIX).unwrap(),
+        // HTTP_PORT overrides the configuration, e.g. to run several instances.
+        http_port: std::env::var("HTTP_PORT").ok().and_then(|port| port.parse().ok()).unwrap_or(config::HTTP_PORT),
+        // EXPORT_DIR overrides the configuration.
+        export_dir: heapless::String::from_str(&std::env::var("EXPORT_DIR").unwrap_or(config::EXPORT_DIR.into())).unwrap(),
+        export_format: export::ExportFormat::from_code(config::EXPORT_FORMAT).unwrap_or_default(),
+        export_retention_days: config::EXPORT_RETENTION_DAYS,
+        // ALERT_RULES overrides the configuration.
+        alert_rules: heapless::String::from_str(&std::env::var("ALERT_RULES").unwrap_or(config::ALERT_RULES.into())).unwrap(),
+        alert_webhook_url: heapless::String::from_str(config::ALERT_WEBHOOK_URL).unwrap(),
+        buzzer_pin: None,
+        remote_appliances: remote::parse_appliance_ids(config::REMOTE_APPLIANCES),
+        automation_rules: heapless::String::from_str(&std::env::var("AUTOMATION_RULES").unwrap_or(config::AUTOMATION_RULES.into())).unwrap(),
+        sleep_interval_minutes: config::SLEEP_INTERVAL_MINUTES,
     };
     log::info!("init_config {:?}", config);
     *CONFIG.lock().unwrap() = Some(config);
@@ -104,6 +325,20 @@ struct SensorRecord
     pub instant_power_usage: f32,
 }
 
+impl SensorRecord {
+    /// Write the record as a JSON object, which is used by MQTT and the HTTP server.
+    fn write_json<W: std::fmt::Write>(&self, writer: &mut W, timestamp: &Timestamp) -> std::fmt::Result {
+        write!(writer,
+            "{{\"timestamp\":\"{}\",\"temperature\":{:.1},\"humidity\":{:.0},\"illuminance\":{:.0},\"power\":{:.0}}}",
+            timestamp.to_rfc3339(),
+            self.ambient_temperature,
+            self.relative_humidity,
+            self.ambient_luminous_level,
+            self.instant_power_usage,
+        )
+    }
+}
+
 impl Default for SensorRecord {
     fn default() -> Self {
         Self {
@@ -117,7 +352,18 @@ impl Default for SensorRecord {
 
 //type Timestamp = std::time::SystemTime;
 type Timestamp = chrono::DateTime<chrono::Utc>;
-fn timestamp_now() -> Timestamp { chrono::Utc::now() }
+fn timestamp_now() -> Timestamp { clock::now() }
+
+/// Copy the string as long as it fits, e.g. names from the Cloud API to the fixed capacity.
+fn copy_truncated<const N: usize>(value: &str) -> heapless::String<N> {
+    let mut copied = heapless::String::new();
+    for c in value.chars() {
+        if copied.push(c).is_err() {
+            break;
+        }
+    }
+    copied
+}
 
 struct SensorRecords<const N: usize>
 {
@@ -154,6 +400,55 @@ impl<const N: usize> SensorRecords<N>
         self.records.is_empty()
     }
 
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns the minimum and maximum values of each field, or the default range if no record exists.
+    pub fn min_max(&self) -> (SensorRecord, SensorRecord) {
+        if self.is_empty() {
+            // Default max/min
+            let max = SensorRecord {
+                ambient_temperature: 40.0,
+                relative_humidity: 100.0,
+                ambient_luminous_level: 100.0,
+                instant_power_usage: 1000.0,
+            };
+            let min = SensorRecord {
+                ambient_temperature: 0.0,
+                relative_humidity: 0.0,
+                ambient_luminous_level: 0.0,
+                instant_power_usage: 0.0,
+            };
+            (min, max)
+        } else {
+            // Calculate max/min
+            let mut max = SensorRecord {
+                ambient_temperature: f32::NEG_INFINITY,
+                relative_humidity: f32::NEG_INFINITY,
+                ambient_luminous_level: f32::NEG_INFINITY,
+                instant_power_usage: f32::NEG_INFINITY,
+            };
+            let mut min = SensorRecord {
+                ambient_temperature: f32::INFINITY,
+                relative_humidity: f32::INFINITY,
+                ambient_luminous_level: f32::INFINITY,
+                instant_power_usage: f32::INFINITY,
+            };
+            for record in self.iter() {
+                max.ambient_temperature = max.ambient_temperature.max(record.ambient_temperature);
+                max.relative_humidity = max.relative_humidity.max(record.relative_humidity);
+                max.ambient_luminous_level = max.ambient_luminous_level.max(record.ambient_luminous_level);
+                max.instant_power_usage = max.instant_power_usage.max(record.instant_power_usage);
+                min.ambient_temperature = min.ambient_temperature.min(record.ambient_temperature);
+                min.relative_humidity = min.relative_humidity.min(record.relative_humidity);
+                min.ambient_luminous_level = min.ambient_luminous_level.min(record.ambient_luminous_level);
+                min.instant_power_usage = min.instant_power_usage.min(record.instant_power_usage);
+            }
+            (min, max)
+        }
+    }
+
     pub fn latest(&self) -> Option<(SensorRecord, Timestamp)> {
         if let Some(timestamp) = self.timestamp {
             self.records.iter().last().and_then(|record| Some((*record, timestamp)))
@@ -164,13 +459,22 @@ impl<const N: usize> SensorRecords<N>
 }
 
 const SENSOR_RECORD_CAPACITY: usize = 60*24+1;
-static mut SENSOR_RECORDS: SensorRecords<SENSOR_RECORD_CAPACITY> = SensorRecords::new();
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Interval between the stored records, which is the sleep interval in the low-power mode.
+fn sample_interval() -> Duration {
+    match CONFIG.lock().unwrap().as_ref().map_or(0, |config| config.sleep_interval_minutes) {
+        0 => SAMPLE_INTERVAL,
+        minutes => Duration::from_secs(minutes as u64 * 60),
+    }
+}
+/// Sampled records shared by the UI task and the HTTP server.
+static SENSOR_RECORDS: std::sync::Mutex<SensorRecords<SENSOR_RECORD_CAPACITY>> = std::sync::Mutex::new(SensorRecords::new());
 static LAST_RECORD: std::sync::Mutex<Option<(SensorRecord, Timestamp)>> = std::sync::Mutex::new(None);
-static SAMPLED_RECORD: std::sync::Mutex<Option<(SensorRecord, Timestamp)>> = std::sync::Mutex::new(None);
-static LAST_RATE_LIMIT: std::sync::Mutex<RateLimitInfo> = std::sync::Mutex::new(RateLimitInfo::new());
-static IS_WIFI_CONNECTED: std::sync::Mutex<bool> = std::sync::Mutex::new(false);
+static STARTED_AT: std::sync::Mutex<Option<Instant>> = std::sync::Mutex::new(None);
+static TARGET_DEVICE_NAME: std::sync::Mutex<heapless::String<64>> = std::sync::Mutex::new(heapless::String::new());
 
-fn update_task_random(_wifi: Arc<Mutex<EspWifi>>) -> ! {
+fn update_task_random() -> ! {
     let mut rng = rand::thread_rng();
     loop {
         let record = SensorRecord {
@@ -182,15 +486,18 @@ fn update_task_random(_wifi: Arc<Mutex<EspWifi>>) -> ! {
         let timestamp = timestamp_now();
         log::info!("update task: {:?} {:?}", record, timestamp);
         *LAST_RECORD.lock().unwrap() = Some((record, timestamp));
-        std::thread::sleep(Duration::from_secs(1));
+        clock::sleep(Duration::from_secs(1));
     }
 }
 
 fn fetch_remo_sensor_data() -> anyhow::Result<(SensorRecord, Timestamp, RateLimitInfo)> {
     let mut record = SensorRecord::default();
     let mut timestamp = timestamp_now();
-    let ((_, newest_events), _) = get_target_device()?;
-    
+    let ((device, newest_events), device_rate_limit) = metrics::observe_fetch(FetchTarget::Devices, get_target_device)?;
+    if let Some(device) = device {
+        *TARGET_DEVICE_NAME.lock().unwrap() = copy_truncated(&device.name);
+    }
+
     if let Some(events) = newest_events {
         if let Some(temperature) = events.temperature {
             record.ambient_temperature = temperature.val;
@@ -206,81 +513,78 @@ fn fetch_remo_sensor_data() -> anyhow::Result<(SensorRecord, Timestamp, RateLimi
         }
     }
 
-    let ((_, properties), rate_limit) = get_target_appliance()?;
+    let power_source = CONFIG.lock().unwrap().as_ref().unwrap().power_source;
+    if power_source == PowerSource::EchonetLite {
+        match metrics::observe_fetch(FetchTarget::EchonetLite, echonet::read_instantaneous_power) {
+            Ok(power) => {
+                record.instant_power_usage = power as f32;
+                match metrics::observe_fetch(FetchTarget::EchonetLite, echonet::read_cumulative_energy) {
+                    Ok(energy) => metrics::set_cumulative_energy(energy),
+                    Err(err) => log::warn!("Failed to read the cumulative energy - {:?}", err),
+                }
+                return Ok((record, timestamp, device_rate_limit));
+            },
+            // Read the power via the Cloud API instead, rather than recording 0 W while the smart meter is unreachable.
+            Err(err) => log::warn!("Failed to read the smart meter. Falling back to the Cloud API - {:?}", err),
+        }
+    }
+
+    let ((_, properties), rate_limit) = metrics::observe_fetch(FetchTarget::Appliances, get_target_appliance)?;
     let instant: Option<u32> = properties.iter().find(|property| property.epc == 231 )
         .and_then(|property| property.val.parse().ok());
     let coefficient: Option<u32> = properties.iter().find(|property| property.epc == 211 )
         .and_then(|property| property.val.parse().ok());
     if let Some(instant_power) = instant.and_then(|instant| coefficient.and_then(|coefficient| Some(instant*coefficient))) {
         record.instant_power_usage = instant_power as f32;
+    } else if power_source == PowerSource::EchonetLite {
+        return Err(anyhow!("No power reading from either the smart meter or the Cloud API"));
+    }
+    let energy: Option<u32> = properties.iter().find(|property| property.epc == 224 )
+        .and_then(|property| property.val.parse().ok());
+    let unit: Option<f64> = properties.iter().find(|property| property.epc == 225 )
+        .and_then(|property| property.val.parse().ok())
+        .and_then(echonet::energy_unit_kwh);
+    if let (Some(energy), Some(unit)) = (energy, unit) {
+        metrics::set_cumulative_energy(energy as f64 * coefficient.unwrap_or(1) as f64 * unit);
     }
     Ok((record, timestamp, rate_limit))
 }
 
-fn update_task_cloudapi(wifi: Arc<Mutex<EspWifi>>, wifi_wait: WifiWait) -> ! {
-    let mut prev_has_network_connection = false;
+/// Fetch the sensor data and keep it for the next sample. Returns true if fetched.
+fn update_sensor_data() -> bool {
+    match fetch_remo_sensor_data() {
+        Ok((record, timestamp, rate_limit)) => {
+            *LAST_RECORD.lock().unwrap() = Some((record, timestamp));
+            connectivity::set_fetched(rate_limit, timestamp_now());
+            mqtt::publish_rate_limit(rate_limit);
+            true
+        },
+        Err(err) => {
+            log::error!("fetch sensor data failed: {:?}", err);
+            connectivity::set_fetch_error(&err.to_string(), timestamp_now());
+            false
+        }
+    }
+}
 
+fn update_task_cloudapi() -> ! {
+    let wifi_events = connectivity::subscribe();
     loop {
-        let has_network_connection = {
-            let mut wifi = wifi.lock().unwrap();
-            if wifi.is_connected().unwrap_or(false) {
-                true
-            } else {
-                let is_started = if !wifi.is_started().unwrap_or(false) {
-                    log::info!("Starting WiFi...");
-                    match wifi.start() {
-                        Ok(_) => {
-                            log::info!("Waiting WiFi starts...");
-                            wifi_wait.wait_with_timeout(Duration::from_secs(10), || wifi.is_started().unwrap_or(false))
-                        },
-                        Err(err) => {
-                            log::error!("Failed to start WiFi - {:?}", err);
-                            false
-                        },
-                    }
-                } else {
-                    true
-                };
-                if is_started {
-                    log::info!("Waiting WiFi gets connected...");
-                    if !wifi.is_connected().unwrap_or(false) {
-                        log::info!("Connectting WiFi...");
-                        match wifi.connect() {
-                            Ok(_) => {
-                                log::info!("Waiting WiFi connection...");
-                                wifi_wait.wait_with_timeout(Duration::from_secs(10), || wifi.is_connected().unwrap_or(false))
-                            },
-                            Err(err) => {
-                                log::error!("Failed to connect WiFi - {:?}", err);
-                                false
-                            },
-                        }
-                    } else {
-                        true
-                    }
-                } else {
-                    false
-                }
-            }
-        };
-        *IS_WIFI_CONNECTED.lock().unwrap() = has_network_connection;
-        prev_has_network_connection = has_network_connection;
-
-        if !has_network_connection {
-            std::thread::sleep(Duration::from_secs(1));
-            continue;
+        // Woken up by the connectivity manager when connected.
+        while !connectivity::is_connected() {
+            wifi_events.recv().ok();
         }
 
-        match fetch_remo_sensor_data() {
-            Ok((record, timestamp, rate_limit)) => {
-                *LAST_RECORD.lock().unwrap() = Some((record, timestamp));
-                *LAST_RATE_LIMIT.lock().unwrap() = rate_limit;
-            },
-            Err(err) => {
-                log::error!("fetch sensor data failed: {:?}", err);
+        #[cfg(target_os="linux")]
+        {
+            // The replay waits for the captured timing by itself. After a failed fetch, sleep as usual so that the loop does not spin.
+            if update_sensor_data() && capture::is_replaying() {
+                continue;
             }
         }
-        std::thread::sleep(Duration::from_secs(30));
+        #[cfg(not(target_os="linux"))]
+        update_sensor_data();
+        clock::sleep(Duration::from_secs(30));
     }
 }
 
@@ -288,184 +592,122 @@ fn sample_task() {
     let timestamp =  timestamp_now();
     log::info!("sample task: {:?}", timestamp);
     let last_record = LAST_RECORD.lock().unwrap().take();
-    *SAMPLED_RECORD.lock().unwrap() = last_record.and_then(|record| Some((record.0, timestamp)));
+    if let Some((record, _)) = last_record {
+        SENSOR_RECORDS.lock().unwrap().add_with_timestamp(record, timestamp);
+        mqtt::publish_sample(record, timestamp);
+        export::append(&record, &timestamp);
+        alert::evaluate(&record, timestamp);
+    }
+    automation::evaluate(last_record.as_ref().map(|(record, _)| record), timestamp);
 }
 
-fn ui_task(gfx: lgfx::SharedLgfxTarget) -> ! {
-    let sensor_records = unsafe { &mut SENSOR_RECORDS };
-    loop {
-        if let Some((record, timestamp)) = SAMPLED_RECORD.lock().unwrap().take() {
-            // New record has arrived.
-            sensor_records.add_with_timestamp(record, timestamp);
-        }
-        let rate_limit = LAST_RATE_LIMIT.lock().unwrap().clone();
+const UI_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
 
-        let (max, min) = if sensor_records.is_empty() {
-            // Default max/min
-            let max = SensorRecord {
-                ambient_temperature: 40.0,
-                relative_humidity: 100.0,
-                ambient_luminous_level: 100.0,
-                instant_power_usage: 1000.0,
-            };
-            let min = SensorRecord {
-                ambient_temperature: 0.0,
-                relative_humidity: 0.0,
-                ambient_luminous_level: 0.0,
-                instant_power_usage: 0.0,
-            };
-            (max, min)
-        } else {
-            // Calculate max/min
-            let mut max = SensorRecord {
-                ambient_temperature: f32::NEG_INFINITY,
-                relative_humidity: f32::NEG_INFINITY,
-                ambient_luminous_level: f32::NEG_INFINITY,
-                instant_power_usage: f32::NEG_INFINITY,
-            };
-            let mut min = SensorRecord {
-                ambient_temperature: f32::INFINITY,
-                relative_humidity: f32::INFINITY,
-                ambient_luminous_level: f32::INFINITY,
-                instant_power_usage: f32::INFINITY,
-            };
-            for record in sensor_records.iter() {
-                max.ambient_temperature = max.ambient_temperature.max(record.ambient_temperature);
-                max.relative_humidity = max.relative_humidity.max(record.relative_humidity);
-                max.ambient_luminous_level = max.ambient_luminous_level.max(record.ambient_luminous_level);
-                max.instant_power_usage = max.instant_power_usage.max(record.instant_power_usage);
-                min.ambient_temperature = min.ambient_temperature.min(record.ambient_temperature);
-                min.relative_humidity = min.relative_humidity.min(record.relative_humidity);
-                min.ambient_luminous_level = min.ambient_luminous_level.min(record.ambient_luminous_level);
-                min.instant_power_usage = min.instant_power_usage.min(record.instant_power_usage);
-            }
-            (max, min)
-        };
-
-        let mut min_temperature_str = heapless::String::<16>::new();
-        let mut cur_temperature_str = heapless::String::<16>::new();
-        let mut max_temperature_str = heapless::String::<16>::new();
-        let mut min_humidity_str = heapless::String::<16>::new();
-        let mut cur_humidity_str = heapless::String::<16>::new();
-        let mut max_humidity_str = heapless::String::<16>::new();
-        let mut min_power_str = heapless::String::<16>::new();
-        let mut cur_power_str = heapless::String::<16>::new();
-        let mut max_power_str = heapless::String::<16>::new();
-        let mut timestamp_str = heapless::String::<32>::new();
-        let mut rate_limit_str = heapless::String::<64>::new();
-        let mut wifi_connection_str = heapless::String::<16>::new();
-
-        let latest = sensor_records.latest();
-        if let Some((record, timestamp)) = latest {
-            write!(&mut min_temperature_str, "{:4.1}", min.ambient_temperature).ok();
-            write!(&mut cur_temperature_str, "{:4.1}", record.ambient_temperature).ok();
-            write!(&mut max_temperature_str, "{:4.1}", max.ambient_temperature).ok();
-            write!(&mut min_humidity_str, "{:4.1}", min.relative_humidity).ok();
-            write!(&mut cur_humidity_str, "{:4.1}", record.relative_humidity).ok();
-            write!(&mut max_humidity_str, "{:4.1}", max.relative_humidity).ok();
-            write!(&mut min_power_str, "{:5.0}", min.instant_power_usage).ok();
-            write!(&mut cur_power_str, "{:5.0}", record.instant_power_usage).ok();
-            write!(&mut max_power_str, "{:5.0}", max.instant_power_usage).ok();
-            write!(&mut timestamp_str, "{:?}", timestamp).ok();
-        } else {
-            min_temperature_str.write_str("--").ok();
-            cur_temperature_str.write_str("--").ok();
-            max_temperature_str.write_str("--").ok();
-            min_humidity_str.write_str("--").ok();
-            cur_humidity_str.write_str("--").ok();
-            max_humidity_str.write_str("--").ok();
-            min_power_str.write_str("--").ok();
-            cur_power_str.write_str("--").ok();
-            max_power_str.write_str("--").ok();
-            timestamp_str.write_str("--").ok();
-        }
+fn ui_context(sensor_records: &SensorRecords<SENSOR_RECORD_CAPACITY>) -> ui::UiContext<'_> {
+    let (min, max) = sensor_records.min_max();
+    ui::UiContext {
+        records: sensor_records,
+        min,
+        max,
+        network: connectivity::status(),
+        power: power::power_status(),
+        device_name: TARGET_DEVICE_NAME.lock().unwrap().clone(),
+        language: CONFIG.lock().unwrap().as_ref().map(|config| config.language).unwrap_or_default(),
+        local_time: clock::is_synced().then(|| clock::local(clock::now()).naive_local()),
+        alerts: alert::active_alerts(),
+    }
+}
 
-        // Draw top bar
+fn ui_task(mut display: Display) -> ! {
+    let mut navigator = ui::Navigator::new();
+    loop {
         {
-            write!(&mut rate_limit_str, "API: ").ok();
-            if let Some(limit) = rate_limit.limit {
-                if let Some(remaining) = rate_limit.remaining {
-                    write!(&mut rate_limit_str, "{}/{}", remaining, limit).ok();
-                }
+            // The records are locked only while rendering, so that the HTTP server can read them.
+            let sensor_records = SENSOR_RECORDS.lock().unwrap();
+            let context = ui_context(&sensor_records);
+            display.draw(|canvas| navigator.render(canvas, &context));
+            if context.power.map_or(false, |power| power.is_critical()) {
+                power::shutdown();
             }
-            // if let Some(reset) = rate_limit.reset {
-            //     let timestamp = Timestamp::from_utc(chrono::NaiveDateTime::from_timestamp(reset as i64, 0), chrono::Utc);
-            //     write!(&mut rate_limit_str, " reset at: {}", timestamp.format("%Y-%m-%d %H:%M:%S"));
-            // }
-            write!(&mut wifi_connection_str, "WIFI: ").ok();
-            write!(&mut wifi_connection_str, "{}", if *IS_WIFI_CONNECTED.lock().unwrap() { "OK" } else { "NC" } ).ok();
         }
-        {
-            let mut guard = gfx.lock_without_auto_update();
-            let foreground = ColorRgb332::new(0xff);
-            let background = ColorRgb332::new(0x00);
-            guard.set_font(lgfx::fonts::FreeMono24pt7b).ok();
-            let font_height = guard.font_height();
-            let line_height = font_height * 9 / 8;
-            guard.clear(lgfx::ColorRgb332::new(0xff));
-            let screen_width = 960;
-            let screen_height = 540;
-            let chart_left = 300;
-            let chart_width = screen_width - chart_left;
-            // Draw TOP BAR
-            {
-                guard.fill_rect(0, 0, screen_width, font_height, background);
-                guard.draw_string(&rate_limit_str, 0, 0, background, foreground, 0.75, 0.75, textdatum_top_left);
-                guard.draw_string(&rate_limit_str, 0, 0, background, foreground, 0.75, 0.75, textdatum_top_left);
-                guard.draw_string(&wifi_connection_str, 300, 0, background, foreground, 0.75, 0.75, textdatum_top_left);
-            }
-            let chart_height = (540 - line_height) / 3;
-            let value_margin_left = 20;
-            let value_width = 200;
-            {
-                let mut y_offset = font_height;
-                let mut record_iter = sensor_records.records.iter();
-                Chart::new(chart_width, chart_height, background, foreground)
-                    .draw(&mut guard, chart_left, y_offset, sensor_records.records.len(), min.ambient_temperature, max.ambient_temperature, move |_| record_iter.next().map(|item| item.ambient_temperature) )
-                    .ok();
-                guard.draw_string("Temperature:", 0, y_offset, foreground, background, 0.75, 0.75, textdatum_top_left);
-                y_offset += line_height;
-                guard.draw_string(&cur_temperature_str, value_margin_left, y_offset, foreground, background, 1.0, 1.0, textdatum_top_left);
-                y_offset += line_height;
-                guard.draw_string(&min_temperature_str, value_margin_left, y_offset, foreground, background, 0.75, 0.75, textdatum_top_left);
-                y_offset += line_height * 3 / 4;
-                guard.draw_string(&max_temperature_str, value_margin_left, y_offset, foreground, background, 0.75, 0.75, textdatum_top_left);
 
+        // Wait for the next refresh while handling input events.
+        // The clock is updated partially when the minute changes.
+        let next_refresh = Instant::now() + clock::real_duration(UI_REFRESH_INTERVAL);
+        let mut next_minute = Instant::now() + clock::until_next_minute();
+        loop {
+            let now = Instant::now();
+            if now >= next_refresh {
+                break;
             }
-            {
-                let mut y_offset = font_height + chart_height;
-                let mut record_iter = sensor_records.records.iter();
-                Chart::new(chart_width, chart_height, background, foreground)
-                    .draw(&mut guard, chart_left, y_offset, sensor_records.records.len(), min.relative_humidity, max.relative_humidity, move |_| record_iter.next().map(|item| item.relative_humidity) )
-                    .ok();
-                guard.draw_string("Humidity:", 0, y_offset, foreground, background, 0.75, 0.75, textdatum_top_left);
-                y_offset += line_height;
-                guard.draw_string(&cur_humidity_str, value_margin_left, y_offset, foreground, background, 1.0, 1.0, textdatum_top_left);
-                y_offset += line_height;
-                guard.draw_string(&max_humidity_str, value_margin_left, y_offset, foreground, background, 0.75, 0.75, textdatum_top_left);
-                y_offset += line_height * 3 / 4;
-                guard.draw_string(&min_humidity_str, value_margin_left, y_offset, foreground, background, 0.75, 0.75, textdatum_top_left);
+            if now >= next_minute {
+                let sensor_records = SENSOR_RECORDS.lock().unwrap();
+                let context = ui_context(&sensor_records);
+                display.draw_partial(|canvas| { navigator.render_clock(canvas, &context); });
+                next_minute = Instant::now() + clock::until_next_minute();
+                continue;
             }
-            {
-                let mut y_offset = font_height + chart_height * 2;
-                let mut record_iter = sensor_records.records.iter();
-                Chart::new(chart_width, chart_height, background, foreground)
-                    .draw(&mut guard, chart_left, y_offset, sensor_records.records.len(), min.instant_power_usage, max.instant_power_usage, move |_| record_iter.next().map(|item| item.instant_power_usage) )
-                    .ok();
-                guard.draw_string("Power:", 0, y_offset, foreground, background, 0.75, 0.75, textdatum_top_left);
-                y_offset += line_height;
-                guard.draw_string(&cur_power_str, value_margin_left, y_offset, foreground, background, 1.0, 1.0, textdatum_top_left);
-                y_offset += line_height;
-                guard.draw_string(&max_power_str, value_margin_left, y_offset, foreground, background, 0.75, 0.75, textdatum_top_left);    
-                y_offset += line_height * 3 / 4;
-                guard.draw_string(&min_power_str, value_margin_left, y_offset, foreground, background, 0.75, 0.75, textdatum_top_left);
+            if let Some(event) = input::wait_event(next_refresh.min(next_minute) - now) {
+                let sensor_records = SENSOR_RECORDS.lock().unwrap();
+                if navigator.handle_event(event, &ui_context(&sensor_records)) {
+                    break;
+                }
             }
+        }
+    }
+}
 
+/// Time to wait for Wi-Fi in the low-power mode. The data is not updated if it is not connected by then.
+const LOW_POWER_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time to wait for the automations to finish before sleeping.
+const LOW_POWER_ACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Low-power mode: fetch and show the data once, and sleep until the next update.
+/// The input, the UI task and the sample timer are not used, and the e-ink display keeps the image while sleeping.
+fn low_power_task(mut display: Display, interval: Duration) -> ! {
+    if let Some(evaluated) = power::restore_history(&mut SENSOR_RECORDS.lock().unwrap()) {
+        automation::set_last_evaluated(evaluated);
+    }
+    let wifi_events = connectivity::subscribe();
+    loop {
+        if connectivity::wait_connected(&wifi_events, LOW_POWER_CONNECT_TIMEOUT) {
+            update_sensor_data();
+        }
+        sample_task();
+        {
+            let sensor_records = SENSOR_RECORDS.lock().unwrap();
+            let context = ui_context(&sensor_records);
+            display.draw(|canvas| ui::Navigator::new().render(canvas, &context));
+            power::save_history(&sensor_records, automation::last_evaluated());
+            if context.power.map_or(false, |power| power.is_critical()) {
+                power::shutdown();
+            }
         }
-        std::thread::sleep(Duration::from_secs(30));
+        // Actions fired by the time rules would be cut off by deep sleep.
+        automation::wait_actions(LOW_POWER_ACTION_TIMEOUT);
+        power::sleep(interval);
     }
 }
 
+// Debug builds on Linux (reqwest in particular) need much more stack than on the device.
+#[cfg(target_os="espidf")]
+const UI_TASK_STACK_SIZE: usize = 8192;
+#[cfg(target_os="linux")]
+const UI_TASK_STACK_SIZE: usize = 256*1024;
+#[cfg(target_os="espidf")]
+const UPDATE_TASK_STACK_SIZE: usize = 15*1024;
+#[cfg(target_os="linux")]
+const UPDATE_TASK_STACK_SIZE: usize = 256*1024;
+#[cfg(target_os="espidf")]
+const MQTT_TASK_STACK_SIZE: usize = 6*1024;
+#[cfg(target_os="linux")]
+const MQTT_TASK_STACK_SIZE: usize = 64*1024;
+/// The same as the update task, since reading the appliances runs in the background.
+#[cfg(target_os="espidf")]
+const BACKGROUND_TASK_STACK_SIZE: usize = 15*1024;
+#[cfg(target_os="linux")]
+const BACKGROUND_TASK_STACK_SIZE: usize = 256*1024;
+
 static GFX: std::sync::Mutex<Option<Gfx>> = std::sync::Mutex::new(None);
 static SAMPLE_TIMER_SERVICE: std::sync::Mutex<Option<EspTaskTimerService>> = std::sync::Mutex::new(None);
 static SAMPLE_TIMER: std::sync::Mutex<Option<EspTimer>> = std::sync::Mutex::new(None);
@@ -489,80 +731,219 @@ fn main() -> anyhow::Result<()> {
         gfx.set_rotation(1);
     }
     #[cfg(target_os="linux")]
+    env_logger::init();
+    #[cfg(target_os="linux")]
+    let options = options::Options::from_args();
+    #[cfg(target_os="linux")]
+    let headless = options.headless.clone();
+    #[cfg(target_os="linux")]
+    let framebuffer = Arc::new(Mutex::new(framebuffer::Framebuffer::new(ui::SCREEN_WIDTH, ui::SCREEN_HEIGHT)));
+    #[cfg(target_os="linux")]
     {
-        env_logger::init();
-        *GFX.lock().unwrap() = Some(Gfx::setup(960, 540).unwrap());
+        // The SDL window is not created in the headless mode.
+        if headless.is_none() {
+            *GFX.lock().unwrap() = Some(Gfx::setup(960, 540).unwrap());
+        }
+        if let Some(mode) = headless.as_ref().and_then(|options| options.snapshot) {
+            std::process::exit(if snapshot::run(mode) { 0 } else { 1 });
+        }
+        if let Some(path) = &options.replay_path {
+            // The replay starts from the time of the first captured response.
+            let replayer = capture::Replayer::open(path)?;
+            let start = options.start_time.or(replayer.start_time()).unwrap_or_else(chrono::Utc::now);
+            clock::simulate(options.clock_speed.unwrap_or(1.0), start);
+            *capture::REPLAYER.lock().unwrap() = Some(replayer);
+        } else {
+            if let Some(path) = &options.record_path {
+                *capture::RECORDER.lock().unwrap() = Some(capture::Recorder::create(path)?);
+            }
+            if options.clock_speed.is_some() || options.start_time.is_some() {
+                clock::simulate(options.clock_speed.unwrap_or(1.0), options.start_time.unwrap_or_else(chrono::Utc::now));
+            }
+        }
     }
 
+    #[cfg(target_os="espidf")]
+    let peripherals = Peripherals::take().unwrap();
+
+    *STARTED_AT.lock().unwrap() = Some(Instant::now());
     // Initialize configuration.
     init_config();
+    clock::set_time_zone(&CONFIG.lock().unwrap().as_ref().unwrap().time_zone);
     log::info!("CONFIG: {:?}", CONFIG.lock().unwrap().as_ref().unwrap());
 
-    std::thread::Builder::new().stack_size(8192).spawn(|| {
+    #[cfg(target_os="espidf")]
+    let headless_display: Option<Display> = None;
+    #[cfg(target_os="linux")]
+    let headless_display = headless.as_ref().map(|options| Display::Headless {
+        framebuffer: framebuffer.clone(),
+        screenshot_path: options.screenshot_path.clone(),
+    });
+    let display = headless_display.unwrap_or_else(|| {
         let guard = GFX.lock().unwrap();
-        let gfx_shared = guard.as_ref().unwrap().as_shared();
-        ui_task(gfx_shared);
+        Display::Lgfx(guard.as_ref().unwrap().as_shared())
     });
-    *SAMPLE_TIMER_SERVICE.lock().unwrap() = Some(EspTaskTimerService::new().unwrap());
-    *SAMPLE_TIMER.lock().unwrap() = Some(SAMPLE_TIMER_SERVICE.lock().unwrap().as_mut().unwrap().timer(|| sample_task())
-        .expect("Failed to register sample task"));
-    SAMPLE_TIMER.lock().unwrap().as_mut().unwrap().every(Duration::from_secs(30)).unwrap();
+    let low_power_interval = match CONFIG.lock().unwrap().as_ref().unwrap().sleep_interval_minutes {
+        0 => None,
+        minutes => Some(Duration::from_secs(minutes as u64 * 60)),
+    };
+    // In the low-power mode, the main task renders the screen by itself.
+    let mut low_power_display = None;
+    if low_power_interval.is_some() {
+        low_power_display = Some(display);
+    } else {
+        std::thread::Builder::new().stack_size(UI_TASK_STACK_SIZE).spawn(move || ui_task(display)).expect("Failed to launch UI task");
+    }
+    // Initialize input devices
+    #[cfg(target_os="espidf")]
+    if low_power_interval.is_none() {
+        let i2c = I2cDriver::new(
+            peripherals.i2c0,
+            peripherals.pins.gpio21,
+            peripherals.pins.gpio22,
+            &I2cConfig::new().baudrate(400.kHz().into()),
+        )?;
+        let device = InputDevice::new(
+            input_esp::Gt911::new(i2c)?,
+            peripherals.pins.gpio37.downgrade_input(),
+            peripherals.pins.gpio38.downgrade_input(),
+            peripherals.pins.gpio39.downgrade_input(),
+        )?;
+        let poller = InputPoller::new(device);
+        std::thread::Builder::new()
+            .name("INPUT".into())
+            .stack_size(4096)
+            .spawn(move || input::input_task(poller))
+            .expect("Failed to launch INPUT task");
+    }
+
+    export::init();
+    alert::init();
+    automation::init();
+    if low_power_interval.is_none() {
+        *SAMPLE_TIMER_SERVICE.lock().unwrap() = Some(EspTaskTimerService::new().unwrap());
+        *SAMPLE_TIMER.lock().unwrap() = Some(SAMPLE_TIMER_SERVICE.lock().unwrap().as_mut().unwrap().timer(|| sample_task())
+            .expect("Failed to register sample task"));
+        SAMPLE_TIMER.lock().unwrap().as_mut().unwrap().every(SAMPLE_INTERVAL).unwrap();
+    }
     
     // Initialize WiFi
     #[cfg(target_os="espidf")]
     let (wifi, wifi_wait) = {
-        let peripherals = Peripherals::take().unwrap();
         let sysloop = esp_idf_svc::eventloop::EspSystemEventLoop::take()?;
-
-        let wifi = Arc::new(Mutex::new(EspWifi::new(
+        let wifi = EspWifi::new(
             peripherals.modem,
             sysloop.clone(),
             None,
-        )?));
-        {
-            log::info!("Configuring Wi-Fi");
-            let (ssid, pass) = {
-                let guard = CONFIG.lock().unwrap();
-                let config = guard.as_ref().unwrap();
-                (config.wifi_ssid.clone(), config.wifi_password.clone())
-            };
-            let mut wifi = wifi.lock().unwrap();
-            wifi.set_configuration(&embedded_svc::wifi::Configuration::Client(
-                embedded_svc::wifi::ClientConfiguration {
-                    ssid: ssid.into(),
-                    password: pass.into(),
-                    channel: None,
-                    ..Default::default()
-                },
-            ))?;
-        }
-
+        )?;
         let wifi_wait = WifiWait::new(&sysloop)?;
         (wifi, wifi_wait)
     };
     #[cfg(target_os="linux")]
     let (wifi, wifi_wait) = {
-        (Arc::new(Mutex::new(EspWifi{})), WifiWait {})
+        (EspWifi{}, WifiWait {})
     };
+    connectivity::start_task(wifi, wifi_wait);
+    clock::start_sync();
+    if let Some(interval) = low_power_interval {
+        log::info!("Low-power mode: updates every {:?}", interval);
+        low_power_task(low_power_display.unwrap(), interval);
+    }
+    #[cfg(target_os="espidf")]
+    let use_random_data = false;
+    #[cfg(target_os="linux")]
+    let use_random_data = options.random_data;
+    let http_port = CONFIG.lock().unwrap().as_ref().unwrap().http_port;
+    if http_port != 0 {
+        if let Err(err) = start_http_server(http_port, &http_api::PATHS, http_api::handle) {
+            log::error!("Failed to start the HTTP server - {:?}", err);
+        }
+    }
+    if !CONFIG.lock().unwrap().as_ref().unwrap().mqtt_broker.is_empty() {
+        log::info!("Starting MQTT task...");
+        std::thread::Builder::new()
+            .name("MQTT".into())
+            .stack_size(MQTT_TASK_STACK_SIZE)
+            .spawn(|| mqtt::mqtt_task())
+            .expect("Failed to launch MQTT task");
+    }
+    remo_local::start_task();
     log::info!("Starting update task...");
     std::thread::Builder::new()
         .name("UPDATE".into())
-        .stack_size(15*1024)
-        .spawn(|| update_task_cloudapi(wifi, wifi_wait))
-        //.spawn(|| update_task_random(wifi))
+        .stack_size(UPDATE_TASK_STACK_SIZE)
+        .spawn(move || if use_random_data { update_task_random() } else { update_task_cloudapi() })
         .expect("Failed to launch UPDATE task");
     #[cfg(target_os="linux")]
+    if headless.is_some() {
+        headless::command_loop(framebuffer);
+        // Keep running after the standard input is closed.
+        loop {
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }
+    #[cfg(target_os="linux")]
+    let mut input_poller = InputPoller::new(InputDevice::new());
+    #[cfg(target_os="linux")]
     loop { 
         Gfx::handle_sdl_event();
+        input_poller.poll();
         std::thread::sleep(Duration::from_millis(5)); 
     }
     Ok(())
 }
 
+/// Build the URL of the Cloud API endpoint from the configured base URL.
+fn api_url(path: &str) -> heapless::String<128> {
+    let mut url = heapless::String::new();
+    write!(&mut url, "{}{}", CONFIG.lock().unwrap().as_ref().unwrap().api_base_url.trim_end_matches('/'), path).ok();
+    url
+}
+
+/// POST the form to the Cloud API endpoint and return the response body. Fails on a non-2xx status.
+fn api_post(path: &str, form: &str) -> anyhow::Result<std::vec::Vec<u8>> {
+    let access_token = CONFIG.lock().unwrap().as_ref().unwrap().access_token.clone();
+    let authorization = format!("Bearer {}", access_token);
+    let headers = [
+        ("Authorization", authorization.as_str()),
+        ("Content-Type", "application/x-www-form-urlencoded"),
+    ];
+    let (status, body) = post_http(&api_url(path), &headers, form.as_bytes())?;
+    if !(200..300).contains(&status) {
+        anyhow::bail!("HTTP error status {}", status);
+    }
+    Ok(body)
+}
+
+/// Find the string value of the key, e.g. `"mode"`, in a small JSON response without parsing it entirely.
+fn json_string_of<'a>(body: &'a str, key: &str) -> Option<&'a str> {
+    let rest = json_value_of(body, key)?.strip_prefix('"')?;
+    Some(&rest[..rest.find('"')?])
+}
+
+/// Find the value of the key in a small JSON response. Returns the rest of the body from the start of the value.
+fn json_value_of<'a>(body: &'a str, key: &str) -> Option<&'a str> {
+    let start = body.find(key)? + key.len();
+    Some(body[start..].trim_start().strip_prefix(':')?.trim_start())
+}
+
+/// Run a short task such as a request to the Cloud API in a thread, so that it does not delay the UI or the sampling.
+/// Returns false if the thread cannot be launched.
+fn spawn_background(name: &str, task: impl FnOnce() + Send + 'static) -> bool {
+    let result = std::thread::Builder::new()
+        .name(name.into())
+        .stack_size(BACKGROUND_TASK_STACK_SIZE)
+        .spawn(task);
+    if let Err(err) = &result {
+        log::error!("Failed to launch the {} task - {:?}", name, err);
+    }
+    result.is_ok()
+}
+
 fn get_target_device() -> anyhow::Result<((Option<Device>, Option<NewestEvents>), RateLimitInfo)> {
     let sensor_remo_device_id = CONFIG.lock().unwrap().as_ref().unwrap().device_id;
     let access_token = CONFIG.lock().unwrap().as_ref().unwrap().access_token.clone();
-    fetch_http_and_parse("https://api.nature.global/1/devices", access_token.as_str(),|mut response| {
+    fetch_http_and_parse(&api_url("/1/devices"), access_token.as_str(),|mut response| {
         let content_length = response.content_len().map(|n| n as usize);
         let mut target_device: Option<Device> = None;
         let mut target_device_newest_events: Option<NewestEvents> = None;
@@ -582,12 +963,27 @@ fn get_target_device() -> anyhow::Result<((Option<Device>, Option<NewestEvents>)
 fn get_target_appliance() -> anyhow::Result<((Option<Appliance>, Vec<EchonetLiteProperty, 10>), RateLimitInfo)> {
     let echonetlite_appliance_id = CONFIG.lock().unwrap().as_ref().unwrap().appliance_id;
     let access_token = CONFIG.lock().unwrap().as_ref().unwrap().access_token.clone();
-    fetch_http_and_parse("https://api.nature.global/1/appliances", access_token.as_str(),|mut response| {
+    fetch_http_and_parse(&api_url("/1/appliances"), access_token.as_str(),|mut response| {
         let content_length = response.content_len().map(|n| n as usize);
         let mut target_appliance: Option<Appliance> = None;
         let mut properties = Vec::new();
+        let mut aircons: Vec<aircon::Aircon, { aircon::MAX_AIRCONS }> = Vec::new();
+        let mut signals: Vec<remote::RemoteSignal, { remote::MAX_REMOTE_SIGNALS }> = Vec::new();
+        let mut button_appliances = button_appliance::ButtonAppliances::new();
         read_appliances(&mut &mut response, content_length, &ParserOptions::default(), |appliance, sub_node| {
             //log::info!("read_appliances: {:?} {:?}", appliance, sub_node);
+            // The callback is called for each sub node, so the same appliance appears several times.
+            if !aircons.iter().any(|aircon| aircon.id == appliance.id) {
+                if let Some(aircon) = aircon::from_appliance(appliance) {
+                    aircons.push(aircon).ok();
+                }
+            }
+            if let Some(signal) = remote::signal_from(appliance, sub_node) {
+                if !signals.iter().any(|known| known.id == signal.id) {
+                    signals.push(signal).ok();
+                }
+            }
+            button_appliance::collect(&mut button_appliances, appliance, sub_node);
             if appliance.id == echonetlite_appliance_id {
                 target_appliance = Some(appliance.clone());
                 if let Some(ApplianceSubNode::EchonetLiteProperty(property)) = sub_node {
@@ -596,9 +992,56 @@ fn get_target_appliance() -> anyhow::Result<((Option<Appliance>, Vec<EchonetLite
             }
         })
         .map_err(|err| anyhow!("JSON parse error - {:?}", err))?;
+        aircon::update_aircons(aircons);
+        remote::update_signals(signals);
+        button_appliance::update_appliances(button_appliances);
+        *APPLIANCES_STATE.lock().unwrap() = AppliancesState::Read(Instant::now());
         Ok((target_appliance, properties))
     })
 }
+
+/// The appliances for the control pages are read again after this, to follow the changes made in the app.
+const APPLIANCES_TTL: Duration = Duration::from_secs(30 * 60);
+/// Wait before reading the appliances again after a failure.
+const APPLIANCES_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug)]
+enum AppliancesState {
+    NotRead,
+    Reading,
+    Read(Instant),
+    Failed(Instant),
+}
+
+static APPLIANCES_STATE: Mutex<AppliancesState> = Mutex::new(AppliancesState::NotRead);
+
+/// Read the appliances in the background if they have not been read recently,
+/// e.g. when the power is read from ECHONET Lite and the update task does not fetch them.
+fn refresh_appliances_if_needed() {
+    {
+        let mut state = APPLIANCES_STATE.lock().unwrap();
+        let needs_read = match *state {
+            AppliancesState::NotRead => true,
+            AppliancesState::Reading => false,
+            AppliancesState::Read(read_at) => read_at.elapsed() >= APPLIANCES_TTL,
+            AppliancesState::Failed(failed_at) => failed_at.elapsed() >= APPLIANCES_RETRY_INTERVAL,
+        };
+        if !needs_read {
+            return;
+        }
+        *state = AppliancesState::Reading;
+    }
+    let launched = spawn_background("APPLIANCES", || {
+        if let Err(err) = get_target_appliance() {
+            log::warn!("Failed to read the appliances - {:?}", err);
+            *APPLIANCES_STATE.lock().unwrap() = AppliancesState::Failed(Instant::now());
+        }
+        input::push_event(input::InputEvent::Refresh);
+    });
+    if !launched {
+        *APPLIANCES_STATE.lock().unwrap() = AppliancesState::NotRead;
+    }
+}
 // fn get_appliances() -> anyhow::Result<Appliances> {
 //     let appliances: Appliances = fetch_http("https://api.nature.global/1/appliances")?;
 //     Ok(appliances)
@@ -618,4 +1061,75 @@ impl RateLimitInfo {
             reset: None,
         }
     }
+
+    /// Write as a JSON object. Unknown values are written as null.
+    fn write_json<W: std::fmt::Write>(&self, writer: &mut W) -> std::fmt::Result {
+        let value = |value: Option<u64>| value.map(|value| value.to_string()).unwrap_or_else(|| "null".into());
+        write!(writer,
+            "{{\"limit\":{},\"remaining\":{},\"reset\":{}}}",
+            value(self.limit.map(|limit| limit as u64)),
+            value(self.remaining.map(|remaining| remaining as u64)),
+            value(self.reset),
+        )
+    }
+}
+
+#[cfg(all(test, target_os="linux"))]
+mod tests {
+    use super::*;
+    use mock_server::{Options, Step};
+
+    /// The same as the defaults of the mock server.
+    const MOCK_DEVICE_ID: &str = "00000000-0000-0000-0000-000000000001";
+    const MOCK_APPLIANCE_ID: &str = "00000000-0000-0000-0000-000000000002";
+
+    /// Start the mock server on an ephemeral port and point the configuration to it.
+    fn start_mock_server(options: Options, access_token: &str) {
+        let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut config = Config::default();
+        config.device_id = Uuid::parse_str(MOCK_DEVICE_ID).unwrap();
+        config.appliance_id = Uuid::parse_str(MOCK_APPLIANCE_ID).unwrap();
+        config.access_token = heapless::String::from_str(access_token).unwrap();
+        config.api_base_url = heapless::String::from_str(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        *CONFIG.lock().unwrap() = Some(config);
+        std::thread::spawn(move || mock_server::serve(listener, options));
+    }
+
+    fn fetch(scenario: &[Step], configure: impl FnOnce(&mut Options), access_token: &str) -> anyhow::Result<(SensorRecord, Timestamp, RateLimitInfo)> {
+        let mut options = Options::default();
+        options.scenario = scenario.to_vec();
+        configure(&mut options);
+        start_mock_server(options, access_token);
+        fetch_remo_sensor_data()
+    }
+
+    fn assert_status(result: anyhow::Result<(SensorRecord, Timestamp, RateLimitInfo)>, status: u16) {
+        let err = result.err().expect("the fetch must fail");
+        assert!(err.to_string().contains(&format!("HTTP error status {}", status)), "unexpected error: {:?}", err);
+    }
+
+    /// The scenarios share the configuration, so they run in order in a test.
+    #[test]
+    fn fetch_from_mock_server() {
+        let (record, _, rate_limit) = fetch(&[Step::Ok], |_| {}, "token").unwrap();
+        assert!((22.0..=26.0).contains(&record.ambient_temperature));
+        assert!((40.0..=60.0).contains(&record.relative_humidity));
+        assert!((100.0..=700.0).contains(&record.instant_power_usage));
+        assert_eq!(rate_limit.limit, Some(30));
+        assert_eq!(rate_limit.remaining, Some(28));
+        assert_eq!(TARGET_DEVICE_NAME.lock().unwrap().as_str(), "Living");
+
+        // Rejected by the access token.
+        assert_status(fetch(&[Step::Ok], |options| options.access_token = Some("token".into()), "wrong"), 401);
+        // The appliances exceed the rate limit after the devices.
+        assert_status(fetch(&[Step::Ok], |options| options.rate_limit = 1, "token"), 429);
+        assert_status(fetch(&[Step::Status(503)], |_| {}, "token"), 503);
+        assert_status(fetch(&[Step::Ok, Step::Status(500)], |_| {}, "token"), 500);
+
+        // A slow response within the timeout is fetched, and a slower one times out.
+        assert!(fetch(&[Step::Slow(Duration::from_millis(200))], |_| {}, "token").is_ok());
+        let started_at = Instant::now();
+        assert!(fetch(&[Step::Slow(HTTP_TIMEOUT * 3)], |_| {}, "token").is_err());
+        assert!(started_at.elapsed() < HTTP_TIMEOUT * 2);
+    }
 }
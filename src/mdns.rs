@@ -0,0 +1,240 @@
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+
+const MDNS_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+/// Maximum number of compression pointers followed while reading a name.
+const MAX_NAME_JUMPS: usize = 16;
+
+/// A service instance found by `browse`.
+#[derive(Clone, Debug)]
+pub struct ServiceInstance {
+    /// Instance name without the service type, e.g. "Remo-1A2B3C".
+    pub name: String,
+    pub hostname: String,
+    pub address: Option<Ipv4Addr>,
+    pub port: u16,
+}
+
+/// Resource records collected from the responses. Names are compared case-insensitively.
+#[derive(Default)]
+struct Records {
+    pointers: Vec<(String, String)>,
+    services: Vec<(String, u16, String)>,
+    addresses: Vec<(String, Ipv4Addr)>,
+}
+
+impl Records {
+    fn instances(&self, service: &str) -> Vec<ServiceInstance> {
+        let mut instances: Vec<ServiceInstance> = Vec::new();
+        for (_, instance) in self.pointers.iter().filter(|(owner, _)| owner.eq_ignore_ascii_case(service)) {
+            if instances.iter().any(|found| found.name == instance_name(instance, service)) {
+                continue;
+            }
+            let (port, hostname) = match self.services.iter().find(|(owner, _, _)| owner.eq_ignore_ascii_case(instance)) {
+                Some((_, port, hostname)) => (*port, hostname.clone()),
+                None => continue,
+            };
+            let address = self.addresses.iter().find(|(owner, _)| owner.eq_ignore_ascii_case(&hostname)).map(|(_, address)| *address);
+            instances.push(ServiceInstance {
+                name: instance_name(instance, service),
+                hostname,
+                address,
+                port,
+            });
+        }
+        instances
+    }
+}
+
+fn instance_name(instance: &str, service: &str) -> String {
+    let split = instance.len().saturating_sub(service.len());
+    match (instance.get(..split), instance.get(split..)) {
+        (Some(name), Some(suffix)) if suffix.eq_ignore_ascii_case(service) => name.trim_end_matches('.').to_string(),
+        _ => instance.to_string(),
+    }
+}
+
+fn build_query(service: &str) -> Vec<u8> {
+    // ID, flags, QDCOUNT=1, ANCOUNT, NSCOUNT, ARCOUNT
+    let mut query = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in service.split('.').filter(|label| !label.is_empty()) {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    query
+}
+
+fn read_u16(packet: &[u8], offset: usize) -> anyhow::Result<u16> {
+    packet.get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| anyhow!("truncated packet"))
+}
+
+/// Read a possibly compressed name. Returns the name and the offset after it.
+fn read_name(packet: &[u8], mut offset: usize) -> anyhow::Result<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    let mut jumps = 0;
+    loop {
+        let length = *packet.get(offset).ok_or_else(|| anyhow!("truncated name"))? as usize;
+        if length == 0 {
+            return Ok((name, end.unwrap_or(offset + 1)));
+        }
+        if length & 0xc0 == 0xc0 {
+            jumps += 1;
+            if jumps > MAX_NAME_JUMPS {
+                return Err(anyhow!("too many compression pointers"));
+            }
+            end.get_or_insert(offset + 2);
+            offset = (read_u16(packet, offset)? & 0x3fff) as usize;
+            continue;
+        }
+        let label = packet.get(offset + 1..offset + 1 + length).ok_or_else(|| anyhow!("truncated label"))?;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.push_str(&String::from_utf8_lossy(label));
+        offset += 1 + length;
+    }
+}
+
+fn parse_response(packet: &[u8], records: &mut Records) -> anyhow::Result<()> {
+    let flags = read_u16(packet, 2)?;
+    if flags & 0x8000 == 0 {
+        // Not a response.
+        return Ok(());
+    }
+    let question_count = read_u16(packet, 4)?;
+    let record_count = read_u16(packet, 6)? as usize + read_u16(packet, 8)? as usize + read_u16(packet, 10)? as usize;
+    let mut offset = 12;
+    for _ in 0..question_count {
+        let (_, next) = read_name(packet, offset)?;
+        offset = next + 4;
+    }
+    for _ in 0..record_count {
+        let (owner, next) = read_name(packet, offset)?;
+        let record_type = read_u16(packet, next)?;
+        let data_length = read_u16(packet, next + 8)? as usize;
+        let data_offset = next + 10;
+        if packet.len() < data_offset + data_length {
+            return Err(anyhow!("truncated record"));
+        }
+        match record_type {
+            TYPE_PTR => records.pointers.push((owner, read_name(packet, data_offset)?.0)),
+            TYPE_SRV => {
+                let port = read_u16(packet, data_offset + 4)?;
+                let (target, _) = read_name(packet, data_offset + 6)?;
+                records.services.push((owner, port, target));
+            },
+            TYPE_A if data_length == 4 => {
+                let bytes = &packet[data_offset..data_offset + 4];
+                records.addresses.push((owner, Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])));
+            },
+            _ => {},
+        }
+        offset = data_offset + data_length;
+    }
+    Ok(())
+}
+
+/// Browse the instances of the service such as "_remo._tcp.local" by a one-shot mDNS query.
+/// The responders reply directly to the querier, so the multicast group is not joined.
+pub fn browse(service: &str, timeout: Duration) -> anyhow::Result<Vec<ServiceInstance>> {
+    let service = service.trim_end_matches('.');
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.send_to(&build_query(service), (MDNS_ADDRESS, MDNS_PORT))?;
+
+    let deadline = Instant::now() + timeout;
+    let mut records = Records::default();
+    let mut buffer = [0u8; 1500];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        socket.set_read_timeout(Some(deadline - now))?;
+        match socket.recv_from(&mut buffer) {
+            Ok((length, _)) => {
+                if let Err(err) = parse_response(&buffer[..length], &mut records) {
+                    log::debug!("invalid mDNS response - {:?}", err);
+                }
+            },
+            Err(err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => break,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(records.instances(service))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Response in the form a Remo replies to `build_query("_remo._tcp.local")`: PTR in the answers, SRV, TXT and A in the additional records.
+    const REMO_RESPONSE: [u8; 115] = [
+        0x00, 0x00, 0x84, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x05, 0x5f, 0x72, 0x65,
+        0x6d, 0x6f, 0x04, 0x5f, 0x74, 0x63, 0x70, 0x05, 0x6c, 0x6f, 0x63, 0x61, 0x6c, 0x00, 0x00, 0x0c,
+        0x00, 0x01, 0x00, 0x00, 0x11, 0x94, 0x00, 0x0e, 0x0b, 0x52, 0x65, 0x6d, 0x6f, 0x2d, 0x31, 0x41,
+        0x32, 0x42, 0x33, 0x43, 0xc0, 0x0c, 0xc0, 0x28, 0x00, 0x21, 0x80, 0x01, 0x00, 0x00, 0x00, 0x78,
+        0x00, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x0b, 0x52, 0x65, 0x6d, 0x6f, 0x2d, 0x31, 0x41,
+        0x32, 0x42, 0x33, 0x43, 0xc0, 0x17, 0xc0, 0x28, 0x00, 0x10, 0x80, 0x01, 0x00, 0x00, 0x11, 0x94,
+        0x00, 0x01, 0x00, 0xc0, 0x48, 0x00, 0x01, 0x80, 0x01, 0x00, 0x00, 0x00, 0x78, 0x00, 0x04, 0xc0,
+        0xa8, 0x01, 0x17,
+    ];
+
+    #[test]
+    fn parse_remo_response() {
+        let mut records = Records::default();
+        parse_response(&REMO_RESPONSE, &mut records).unwrap();
+        let instances = records.instances("_remo._tcp.local");
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].name, "Remo-1A2B3C");
+        assert_eq!(instances[0].hostname, "Remo-1A2B3C.local");
+        assert_eq!(instances[0].address, Some(Ipv4Addr::new(192, 168, 1, 23)));
+        assert_eq!(instances[0].port, 80);
+    }
+
+    #[test]
+    fn read_compressed_name() {
+        // "Remo-1A2B3C" followed by a pointer to "_remo._tcp.local".
+        let (name, next) = read_name(&REMO_RESPONSE, 0x28).unwrap();
+        assert_eq!(name, "Remo-1A2B3C._remo._tcp.local");
+        assert_eq!(next, 0x36);
+    }
+
+    #[test]
+    fn reject_compression_loop() {
+        // The name at 12 points to itself.
+        let mut packet = REMO_RESPONSE;
+        packet[12] = 0xc0;
+        packet[13] = 0x0c;
+        assert!(read_name(&packet, 12).is_err());
+        assert!(parse_response(&packet, &mut Records::default()).is_err());
+    }
+
+    #[test]
+    fn reject_truncated_record() {
+        // The A record lacks the last byte of the address.
+        let packet = &REMO_RESPONSE[..REMO_RESPONSE.len() - 1];
+        let mut records = Records::default();
+        assert!(parse_response(packet, &mut records).is_err());
+        assert!(records.addresses.is_empty());
+        assert!(read_name(&REMO_RESPONSE[..20], 12).is_err());
+    }
+}
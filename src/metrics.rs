@@ -0,0 +1,144 @@
+//! Prometheus metrics of the sensor values and the data fetching, served at `/metrics`.
+
+use std::{fmt::Write, sync::Mutex, time::Instant};
+
+use crate::{connectivity, SENSOR_RECORDS, STARTED_AT};
+
+/// Upper bounds of the fetch latency histogram in seconds.
+const LATENCY_BUCKETS: [f64; 8] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Source of the sensor data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FetchTarget {
+    Devices,
+    Appliances,
+    EchonetLite,
+}
+
+impl FetchTarget {
+    const ALL: [FetchTarget; 3] = [FetchTarget::Devices, FetchTarget::Appliances, FetchTarget::EchonetLite];
+
+    fn label(&self) -> &'static str {
+        match self {
+            FetchTarget::Devices => "devices",
+            FetchTarget::Appliances => "appliances",
+            FetchTarget::EchonetLite => "echonet_lite",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct FetchStats {
+    requests: u64,
+    errors: u64,
+    /// Cumulative counts of the histogram, one per bucket.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    duration_sum: f64,
+}
+
+impl FetchStats {
+    const fn new() -> Self {
+        Self {
+            requests: 0,
+            errors: 0,
+            buckets: [0; LATENCY_BUCKETS.len()],
+            duration_sum: 0.0,
+        }
+    }
+}
+
+struct Metrics {
+    fetch: [FetchStats; FetchTarget::ALL.len()],
+    /// Normal direction cumulative energy of the smart meter in kWh.
+    cumulative_energy: Option<f64>,
+}
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    fetch: [FetchStats::new(); FetchTarget::ALL.len()],
+    cumulative_energy: None,
+});
+
+/// Run the fetch and record its result and latency.
+pub fn observe_fetch<T>(target: FetchTarget, fetch: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+    let started_at = Instant::now();
+    let result = fetch();
+    let duration = started_at.elapsed().as_secs_f64();
+    let mut metrics = METRICS.lock().unwrap();
+    let stats = &mut metrics.fetch[target as usize];
+    stats.requests += 1;
+    if result.is_err() {
+        stats.errors += 1;
+    }
+    for (bucket, upper_bound) in stats.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+        if duration <= upper_bound {
+            *bucket += 1;
+        }
+    }
+    stats.duration_sum += duration;
+    result
+}
+
+pub fn set_cumulative_energy(energy_kwh: f64) {
+    METRICS.lock().unwrap().cumulative_energy = Some(energy_kwh);
+}
+
+fn write_gauge(body: &mut String, name: &str, help: &str, value: Option<f64>) {
+    if let Some(value) = value {
+        write!(body, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n", name = name, help = help, value = value).ok();
+    }
+}
+
+/// Render the metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut body = String::new();
+    let latest = SENSOR_RECORDS.lock().unwrap().latest();
+    if let Some((record, timestamp)) = latest {
+        write_gauge(&mut body, "remo_monitor_temperature_celsius", "Ambient temperature.", Some(record.ambient_temperature as f64));
+        write_gauge(&mut body, "remo_monitor_humidity_percent", "Relative humidity.", Some(record.relative_humidity as f64));
+        write_gauge(&mut body, "remo_monitor_illuminance_lux", "Ambient illuminance.", Some(record.ambient_luminous_level as f64));
+        write_gauge(&mut body, "remo_monitor_instant_power_watts", "Instantaneous power usage.", Some(record.instant_power_usage as f64));
+        write_gauge(&mut body, "remo_monitor_last_sample_timestamp_seconds", "Time of the latest sample.", Some(timestamp.timestamp() as f64));
+    }
+
+    let (fetch, cumulative_energy) = {
+        let metrics = METRICS.lock().unwrap();
+        (metrics.fetch, metrics.cumulative_energy)
+    };
+    if let Some(energy) = cumulative_energy {
+        body.push_str("# HELP remo_monitor_energy_kwh_total Normal direction cumulative energy of the smart meter.\n");
+        body.push_str("# TYPE remo_monitor_energy_kwh_total counter\n");
+        writeln!(&mut body, "remo_monitor_energy_kwh_total {}", energy).ok();
+    }
+
+    let status = connectivity::status();
+    let rate_limit = status.rate_limit;
+    write_gauge(&mut body, "remo_monitor_api_rate_limit", "Number of Cloud API requests allowed in the window.", rate_limit.limit.map(|limit| limit as f64));
+    write_gauge(&mut body, "remo_monitor_api_rate_limit_remaining", "Number of Cloud API requests remaining in the window.", rate_limit.remaining.map(|remaining| remaining as f64));
+    write_gauge(&mut body, "remo_monitor_wifi_connected", "1 if Wi-Fi is connected.", Some(if status.wifi.connected { 1.0 } else { 0.0 }));
+    write_gauge(&mut body, "remo_monitor_wifi_rssi_dbm", "Signal strength of Wi-Fi.", status.wifi.rssi.map(|rssi| rssi as f64));
+    let uptime = STARTED_AT.lock().unwrap().map(|started_at| started_at.elapsed().as_secs_f64());
+    write_gauge(&mut body, "remo_monitor_uptime_seconds", "Time since the monitor started.", uptime);
+
+    body.push_str("# HELP remo_monitor_fetch_requests_total Number of data fetches.\n");
+    body.push_str("# TYPE remo_monitor_fetch_requests_total counter\n");
+    for target in FetchTarget::ALL {
+        writeln!(&mut body, "remo_monitor_fetch_requests_total{{target=\"{}\"}} {}", target.label(), fetch[target as usize].requests).ok();
+    }
+    body.push_str("# HELP remo_monitor_fetch_errors_total Number of failed data fetches.\n");
+    body.push_str("# TYPE remo_monitor_fetch_errors_total counter\n");
+    for target in FetchTarget::ALL {
+        writeln!(&mut body, "remo_monitor_fetch_errors_total{{target=\"{}\"}} {}", target.label(), fetch[target as usize].errors).ok();
+    }
+    body.push_str("# HELP remo_monitor_fetch_duration_seconds Latency of data fetches.\n");
+    body.push_str("# TYPE remo_monitor_fetch_duration_seconds histogram\n");
+    for target in FetchTarget::ALL {
+        let stats = &fetch[target as usize];
+        for (count, upper_bound) in stats.buckets.iter().zip(LATENCY_BUCKETS) {
+            writeln!(&mut body, "remo_monitor_fetch_duration_seconds_bucket{{target=\"{}\",le=\"{}\"}} {}", target.label(), upper_bound, count).ok();
+        }
+        writeln!(&mut body, "remo_monitor_fetch_duration_seconds_bucket{{target=\"{}\",le=\"+Inf\"}} {}", target.label(), stats.requests).ok();
+        writeln!(&mut body, "remo_monitor_fetch_duration_seconds_sum{{target=\"{}\"}} {}", target.label(), stats.duration_sum).ok();
+        writeln!(&mut body, "remo_monitor_fetch_duration_seconds_count{{target=\"{}\"}} {}", target.label(), stats.requests).ok();
+    }
+    body
+}
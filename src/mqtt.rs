@@ -0,0 +1,370 @@
+//! Publishes the sampled sensor records and the rate limit of the Cloud API to an MQTT broker.
+//!
+//! Only the subset of MQTT 3.1.1 needed to publish with QoS 0 is implemented.
+//! Home Assistant discovers the sensors from the retained config messages.
+
+use std::{
+    fmt::Write as _,
+    io::{Read, Write},
+    net::TcpStream,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+
+use crate::{connectivity, RateLimitInfo, SensorRecord, Timestamp, CONFIG};
+
+const DISCOVERY_PREFIX: &str = "homeassistant";
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Reconnection interval, doubled at each failure.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+/// Number of samples kept while the broker is not reachable. The oldest one is dropped first.
+const MAX_QUEUED_SAMPLES: usize = 64;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+
+const CONNECT_FLAG_USERNAME: u8 = 0x80;
+const CONNECT_FLAG_PASSWORD: u8 = 0x40;
+const CONNECT_FLAG_WILL_RETAIN: u8 = 0x20;
+const CONNECT_FLAG_WILL: u8 = 0x04;
+const CONNECT_FLAG_CLEAN_SESSION: u8 = 0x02;
+const PUBLISH_FLAG_RETAIN: u8 = 0x01;
+
+/// Sensors announced to Home Assistant: (key in the state JSON, name, unit, device class).
+const SENSORS: [(&str, &str, &str, &str); 4] = [
+    ("temperature", "Temperature", "°C", "temperature"),
+    ("humidity", "Humidity", "%", "humidity"),
+    ("illuminance", "Illuminance", "lx", "illuminance"),
+    ("power", "Instantaneous power", "W", "power"),
+];
+
+struct Queue {
+    samples: heapless::Deque<(SensorRecord, Timestamp), MAX_QUEUED_SAMPLES>,
+    rate_limit: Option<RateLimitInfo>,
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    samples: heapless::Deque::new(),
+    rate_limit: None,
+});
+
+/// Queue the sample to publish.
+pub fn publish_sample(record: SensorRecord, timestamp: Timestamp) {
+    let mut queue = QUEUE.lock().unwrap();
+    if queue.samples.is_full() {
+        queue.samples.pop_front();
+    }
+    queue.samples.push_back((record, timestamp)).ok();
+}
+
+/// Publish the rate limit. Only the latest one is kept while offline.
+pub fn publish_rate_limit(rate_limit: RateLimitInfo) {
+    QUEUE.lock().unwrap().rate_limit = Some(rate_limit);
+}
+
+/// Settings of the broker and the topics.
+#[derive(Clone, Debug)]
+struct Settings {
+    broker: heapless::String<64>,
+    username: heapless::String<32>,
+    password: heapless::String<64>,
+    topic_prefix: heapless::String<32>,
+}
+
+impl Settings {
+    fn load() -> Self {
+        let guard = CONFIG.lock().unwrap();
+        let config = guard.as_ref().unwrap();
+        Self {
+            broker: config.mqtt_broker.clone(),
+            username: config.mqtt_username.clone(),
+            password: config.mqtt_password.clone(),
+            topic_prefix: config.mqtt_topic_prefix.clone(),
+        }
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.topic_prefix, name)
+    }
+
+    /// Identifier of this monitor used for the client ID and the unique IDs of Home Assistant.
+    fn node_id(&self) -> String {
+        self.topic_prefix.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+    }
+}
+
+fn write_remaining_length(packet: &mut Vec<u8>, mut length: usize) {
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+}
+
+fn write_string(body: &mut Vec<u8>, value: &[u8]) {
+    body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    body.extend_from_slice(value);
+}
+
+fn connect_body(settings: &Settings) -> Vec<u8> {
+    // The broker publishes "offline" when the connection is lost.
+    let mut flags = CONNECT_FLAG_CLEAN_SESSION | CONNECT_FLAG_WILL | CONNECT_FLAG_WILL_RETAIN;
+    if !settings.username.is_empty() {
+        flags |= CONNECT_FLAG_USERNAME;
+    }
+    if !settings.password.is_empty() {
+        flags |= CONNECT_FLAG_PASSWORD;
+    }
+    let mut body = Vec::new();
+    write_string(&mut body, b"MQTT");
+    body.push(4);
+    body.push(flags);
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    write_string(&mut body, settings.node_id().as_bytes());
+    write_string(&mut body, settings.topic("status").as_bytes());
+    write_string(&mut body, b"offline");
+    if !settings.username.is_empty() {
+        write_string(&mut body, settings.username.as_bytes());
+    }
+    if !settings.password.is_empty() {
+        write_string(&mut body, settings.password.as_bytes());
+    }
+    body
+}
+
+struct Connection {
+    stream: TcpStream,
+    last_sent: Instant,
+}
+
+impl Connection {
+    fn connect(settings: &Settings) -> anyhow::Result<Self> {
+        let address = std::net::ToSocketAddrs::to_socket_addrs(settings.broker.as_str())?
+            .next()
+            .ok_or_else(|| anyhow!("failed to resolve {}", settings.broker))?;
+        let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+        stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+        let mut connection = Self {
+            stream,
+            last_sent: Instant::now(),
+        };
+
+        connection.send(CONNECT, &connect_body(settings))?;
+
+        let mut connack = [0u8; 4];
+        connection.stream.read_exact(&mut connack)?;
+        if connack[0] != CONNACK {
+            return Err(anyhow!("unexpected packet {:#04x}", connack[0]));
+        }
+        if connack[3] != 0 {
+            return Err(anyhow!("connection refused - return code {}", connack[3]));
+        }
+        Ok(connection)
+    }
+
+    fn send(&mut self, packet_type: u8, body: &[u8]) -> anyhow::Result<()> {
+        let mut packet = Vec::with_capacity(body.len() + 5);
+        packet.push(packet_type);
+        write_remaining_length(&mut packet, body.len());
+        packet.extend_from_slice(body);
+        self.stream.write_all(&packet)?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    fn publish(&mut self, topic: &str, payload: &str, retain: bool) -> anyhow::Result<()> {
+        let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+        write_string(&mut body, topic.as_bytes());
+        body.extend_from_slice(payload.as_bytes());
+        self.send(PUBLISH | if retain { PUBLISH_FLAG_RETAIN } else { 0 }, &body)
+    }
+
+    /// Send PINGREQ if nothing has been sent for a while, to keep the connection alive.
+    fn keep_alive(&mut self) -> anyhow::Result<()> {
+        if self.last_sent.elapsed() < KEEP_ALIVE / 2 {
+            return Ok(());
+        }
+        self.send(PINGREQ, &[])?;
+        let mut response = [0u8; 2];
+        self.stream.read_exact(&mut response)?;
+        if response[0] != PINGRESP {
+            return Err(anyhow!("unexpected packet {:#04x}", response[0]));
+        }
+        Ok(())
+    }
+}
+
+fn discovery_config(settings: &Settings, key: &str, name: &str, unit: &str, device_class: &str) -> String {
+    let node_id = settings.node_id();
+    let mut config = String::new();
+    write!(&mut config,
+        concat!(
+            "{{\"name\":\"{}\",\"unique_id\":\"{}_{}\",\"state_topic\":\"{}\",",
+            "\"value_template\":\"{{{{ value_json.{} }}}}\",\"unit_of_measurement\":\"{}\",",
+            "\"device_class\":\"{}\",\"state_class\":\"measurement\",\"availability_topic\":\"{}\",",
+            "\"device\":{{\"identifiers\":[\"{}\"],\"name\":\"M5Paper Remo Monitor\",\"manufacturer\":\"M5Stack\",\"model\":\"M5Paper\"}}}}"
+        ),
+        name, node_id, key, settings.topic("sensor"),
+        key, unit,
+        device_class, settings.topic("status"),
+        node_id,
+    ).ok();
+    config
+}
+
+fn sample_json(record: &SensorRecord, timestamp: &Timestamp) -> String {
+    let mut json = String::new();
+    record.write_json(&mut json, timestamp).ok();
+    json
+}
+
+fn rate_limit_json(rate_limit: &RateLimitInfo) -> String {
+    let mut json = String::new();
+    rate_limit.write_json(&mut json).ok();
+    json
+}
+
+/// Announce the sensors and the availability. These messages are retained by the broker.
+fn announce(connection: &mut Connection, settings: &Settings) -> anyhow::Result<()> {
+    let node_id = settings.node_id();
+    for (key, name, unit, device_class) in SENSORS {
+        let topic = format!("{}/sensor/{}/{}/config", DISCOVERY_PREFIX, node_id, key);
+        connection.publish(&topic, &discovery_config(settings, key, name, unit, device_class), true)?;
+    }
+    connection.publish(&settings.topic("status"), "online", true)
+}
+
+/// Publish the queued messages. A sample is removed from the queue only after it is sent.
+fn publish_queued(connection: &mut Connection, settings: &Settings) -> anyhow::Result<()> {
+    loop {
+        let sample = QUEUE.lock().unwrap().samples.front().cloned();
+        let (record, timestamp) = match sample {
+            Some(sample) => sample,
+            None => break,
+        };
+        connection.publish(&settings.topic("sensor"), &sample_json(&record, &timestamp), false)?;
+        QUEUE.lock().unwrap().samples.pop_front();
+    }
+    let rate_limit = QUEUE.lock().unwrap().rate_limit.take();
+    if let Some(rate_limit) = rate_limit {
+        if let Err(err) = connection.publish(&settings.topic("rate_limit"), &rate_limit_json(&rate_limit), false) {
+            // Keep it unless a newer one has been queued.
+            QUEUE.lock().unwrap().rate_limit.get_or_insert(rate_limit);
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Publish while connected. Returns only when the connection is lost.
+fn run(connection: &mut Connection, settings: &Settings) -> anyhow::Result<()> {
+    announce(connection, settings)?;
+    loop {
+        publish_queued(connection, settings)?;
+        connection.keep_alive()?;
+        std::thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// Connect to the broker and publish the queued messages. Reconnects with exponential backoff.
+pub fn mqtt_task() -> ! {
+    let settings = Settings::load();
+    let mut backoff = MIN_BACKOFF;
+    let wifi_events = connectivity::subscribe();
+    loop {
+        // Woken up by the connectivity manager when connected.
+        while !connectivity::is_connected() {
+            wifi_events.recv().ok();
+        }
+        log::info!("Connecting to the MQTT broker {}...", settings.broker);
+        let result = Connection::connect(&settings).and_then(|mut connection| {
+            log::info!("Connected to the MQTT broker");
+            backoff = MIN_BACKOFF;
+            run(&mut connection, &settings)
+        });
+        if let Err(err) = result {
+            log::warn!("MQTT connection failed - {:?}. Retrying in {:?}", err, backoff);
+        }
+        std::thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(username: &str, password: &str) -> Settings {
+        Settings {
+            broker: "localhost:1883".into(),
+            username: username.into(),
+            password: password.into(),
+            topic_prefix: "remo-monitor".into(),
+        }
+    }
+
+    fn remaining_length(length: usize) -> Vec<u8> {
+        let mut packet = Vec::new();
+        write_remaining_length(&mut packet, length);
+        packet
+    }
+
+    #[test]
+    fn remaining_length_boundaries() {
+        assert_eq!(remaining_length(0), [0x00]);
+        assert_eq!(remaining_length(127), [0x7f]);
+        assert_eq!(remaining_length(128), [0x80, 0x01]);
+        assert_eq!(remaining_length(16383), [0xff, 0x7f]);
+        assert_eq!(remaining_length(16384), [0x80, 0x80, 0x01]);
+    }
+
+    #[test]
+    fn connect_without_credentials() {
+        let body = connect_body(&settings("", ""));
+        // Flags 0x26: clean session and the retained will. Keep alive 60 seconds.
+        let mut expected = vec![0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0x26, 0x00, 0x3c];
+        write_string(&mut expected, b"remo_monitor");
+        write_string(&mut expected, b"remo-monitor/status");
+        write_string(&mut expected, b"offline");
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn connect_with_credentials() {
+        let body = connect_body(&settings("user", "secret"));
+        assert_eq!(body[7], CONNECT_FLAG_USERNAME | CONNECT_FLAG_PASSWORD | 0x26);
+        let mut tail = Vec::new();
+        write_string(&mut tail, b"user");
+        write_string(&mut tail, b"secret");
+        assert!(body.ends_with(&tail));
+
+        let body = connect_body(&settings("user", ""));
+        assert_eq!(body[7], CONNECT_FLAG_USERNAME | 0x26);
+        assert!(body.ends_with(&[0x00, 0x04, b'u', b's', b'e', b'r']));
+    }
+
+    #[test]
+    fn home_assistant_discovery() {
+        let config = discovery_config(&settings("", ""), "temperature", "Temperature", "°C", "temperature");
+        assert_eq!(config, concat!(
+            "{\"name\":\"Temperature\",\"unique_id\":\"remo_monitor_temperature\",\"state_topic\":\"remo-monitor/sensor\",",
+            "\"value_template\":\"{{ value_json.temperature }}\",\"unit_of_measurement\":\"°C\",",
+            "\"device_class\":\"temperature\",\"state_class\":\"measurement\",\"availability_topic\":\"remo-monitor/status\",",
+            "\"device\":{\"identifiers\":[\"remo_monitor\"],\"name\":\"M5Paper Remo Monitor\",\"manufacturer\":\"M5Stack\",\"model\":\"M5Paper\"}}"
+        ));
+    }
+}
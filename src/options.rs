@@ -0,0 +1,67 @@
+use std::path::PathBuf;
+
+use crate::headless::HeadlessOptions;
+use crate::snapshot::SnapshotMode;
+use crate::Timestamp;
+
+/// Command line options of the Linux build.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Run without the SDL window if set.
+    pub headless: Option<HeadlessOptions>,
+    /// Record the Cloud API responses to this file.
+    pub record_path: Option<PathBuf>,
+    /// Replay the Cloud API responses from this file instead of accessing the Cloud API.
+    pub replay_path: Option<PathBuf>,
+    /// Speed of the simulated clock. 1.0 is the real time.
+    pub clock_speed: Option<f64>,
+    /// Start time of the simulated clock.
+    pub start_time: Option<Timestamp>,
+    /// Generate random sensor values instead of accessing the Cloud API.
+    pub random_data: bool,
+}
+
+impl Options {
+    /// Parse the options from the command line arguments.
+    ///
+    /// * `--headless`, `--screenshot <PATH>`
+    /// * `--snapshot-test`, `--update-snapshots`
+    /// * `--record <PATH>`, `--replay <PATH>`
+    /// * `--speed <SPEED>`, `--start-time <RFC3339>`, `--random-data`
+    pub fn from_args() -> Self {
+        let mut options = Self::default();
+        let mut headless = false;
+        let mut headless_options = HeadlessOptions::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => headless = true,
+                "--screenshot" => headless_options.screenshot_path = args.next().map(PathBuf::from),
+                "--snapshot-test" => {
+                    headless = true;
+                    headless_options.snapshot = Some(SnapshotMode::Verify);
+                },
+                "--update-snapshots" => {
+                    headless = true;
+                    headless_options.snapshot = Some(SnapshotMode::Update);
+                },
+                "--record" => options.record_path = args.next().map(PathBuf::from),
+                "--replay" => options.replay_path = args.next().map(PathBuf::from),
+                "--speed" => match args.next().and_then(|speed| speed.parse().ok()) {
+                    Some(speed) if speed > 0.0 => options.clock_speed = Some(speed),
+                    _ => log::warn!("{} requires a positive number", arg),
+                },
+                "--start-time" => match args.next().and_then(|time| chrono::DateTime::parse_from_rfc3339(&time).ok()) {
+                    Some(time) => options.start_time = Some(time.with_timezone(&chrono::Utc)),
+                    None => log::warn!("--start-time requires a time in RFC 3339 format"),
+                },
+                "--random-data" => options.random_data = true,
+                _ => log::warn!("Unknown argument: {}", arg),
+            }
+        }
+        if headless {
+            options.headless = Some(headless_options);
+        }
+        options
+    }
+}
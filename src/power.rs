@@ -0,0 +1,296 @@
+//! Low-power mode for battery operation and the battery status.
+//!
+//! In the low-power mode, the monitor wakes up every configured interval by the RTC timer,
+//! fetches and shows the data once and goes back to deep sleep. The e-ink display retains the image
+//! while sleeping, and the history is kept in the RTC memory, which survives deep sleep.
+//!
+//! The battery status is estimated from the voltage of the battery, since M5Paper has neither a fuel gauge
+//! nor a signal of the USB power. Charging is detected by the rise of the voltage when the USB power is connected,
+//! or by the voltage of the constant-voltage phase of the charger.
+
+use std::{sync::Mutex, time::Duration};
+
+use crate::{SensorRecords, Timestamp};
+#[cfg(target_os="espidf")]
+use crate::SensorRecord;
+
+/// Time for the e-ink display to finish the refresh before sleeping.
+const EPD_REFRESH_DURATION: Duration = Duration::from_secs(3);
+
+/// Number of records kept in the RTC memory, which is 8KiB in total.
+/// 24 hours with 5 minutes interval.
+#[cfg(target_os="espidf")]
+const RTC_HISTORY_CAPACITY: usize = 288;
+#[cfg(target_os="espidf")]
+const RTC_HISTORY_MAGIC: u32 = 0x5245_4d4f;
+
+#[cfg(target_os="espidf")]
+struct RtcHistory {
+    /// Set when the history is valid. The RTC memory is not initialized after the power is turned on.
+    magic: u32,
+    len: usize,
+    /// Timestamp of the last record in seconds since the UNIX epoch.
+    last_timestamp: i64,
+    /// When the automations were evaluated last time, in seconds since the UNIX epoch. 0 if never.
+    automations_evaluated: i64,
+    records: [SensorRecord; RTC_HISTORY_CAPACITY],
+}
+
+#[cfg(target_os="espidf")]
+#[link_section = ".rtc_noinit"]
+static mut RTC_HISTORY: RtcHistory = RtcHistory {
+    magic: 0,
+    len: 0,
+    last_timestamp: 0,
+    automations_evaluated: 0,
+    records: [SensorRecord {
+        ambient_temperature: 0.0,
+        relative_humidity: 0.0,
+        ambient_luminous_level: 0.0,
+        instant_power_usage: 0.0,
+    }; RTC_HISTORY_CAPACITY],
+};
+
+/// Restore the records saved before the last deep sleep.
+/// Returns when the automations were evaluated before the sleep.
+#[cfg(target_os="espidf")]
+pub fn restore_history<const N: usize>(records: &mut SensorRecords<N>) -> Option<Timestamp> {
+    use chrono::TimeZone;
+    // Only the main task accesses the history, before and after deep sleep.
+    let history = unsafe { &RTC_HISTORY };
+    let is_deep_sleep_wakeup = unsafe { esp_idf_sys::esp_reset_reason() } == esp_idf_sys::esp_reset_reason_t_ESP_RST_DEEPSLEEP;
+    if !is_deep_sleep_wakeup || history.magic != RTC_HISTORY_MAGIC || history.len > RTC_HISTORY_CAPACITY {
+        return None;
+    }
+    let automations_evaluated = match history.automations_evaluated {
+        0 => None,
+        seconds => chrono::Utc.timestamp_opt(seconds, 0).single(),
+    };
+    if let Some(timestamp) = chrono::Utc.timestamp_opt(history.last_timestamp, 0).single() {
+        for record in &history.records[..history.len] {
+            records.add_with_timestamp(*record, timestamp);
+        }
+        log::info!("Restored {} records from the RTC memory", history.len);
+    }
+    automations_evaluated
+}
+#[cfg(target_os="linux")]
+pub fn restore_history<const N: usize>(_records: &mut SensorRecords<N>) -> Option<Timestamp> {
+    // The records stay in the memory since the process keeps running.
+    None
+}
+
+/// Save the latest records and when the automations were evaluated, to be restored after deep sleep.
+#[cfg(target_os="espidf")]
+pub fn save_history<const N: usize>(records: &SensorRecords<N>, automations_evaluated: Option<Timestamp>) {
+    let history = unsafe { &mut RTC_HISTORY };
+    let skip = records.len().saturating_sub(RTC_HISTORY_CAPACITY);
+    history.len = 0;
+    for record in records.iter().skip(skip) {
+        history.records[history.len] = *record;
+        history.len += 1;
+    }
+    history.last_timestamp = records.last_timestamp().map(|timestamp| timestamp.timestamp()).unwrap_or(0);
+    history.automations_evaluated = automations_evaluated.map(|timestamp| timestamp.timestamp()).unwrap_or(0);
+    history.magic = RTC_HISTORY_MAGIC;
+}
+#[cfg(target_os="linux")]
+pub fn save_history<const N: usize>(_records: &SensorRecords<N>, _automations_evaluated: Option<Timestamp>) {
+}
+
+/// Wait for the display and sleep until the next wakeup.
+/// On the device, this does not return since the application restarts from `main` after deep sleep.
+/// The side button (GPIO38) also wakes it up.
+#[cfg(target_os="espidf")]
+pub fn sleep(duration: Duration) {
+    std::thread::sleep(EPD_REFRESH_DURATION);
+    log::info!("Entering deep sleep for {:?}", duration);
+    unsafe {
+        // Keep the main power (GPIO2) on while running on the battery.
+        esp_idf_sys::gpio_hold_en(2);
+        esp_idf_sys::gpio_deep_sleep_hold_en();
+        esp_idf_sys::esp_sleep_enable_timer_wakeup(duration.as_micros() as u64);
+        esp_idf_sys::esp_sleep_enable_ext0_wakeup(38, 0);
+        esp_idf_sys::esp_deep_sleep_start();
+    }
+}
+#[cfg(target_os="linux")]
+pub fn sleep(duration: Duration) {
+    std::thread::sleep(EPD_REFRESH_DURATION);
+    // Deep sleep is simulated by sleeping the main task.
+    log::info!("Entering deep sleep for {:?}", duration);
+    crate::clock::sleep(duration);
+}
+
+/// The battery is regarded as charging above this voltage. The charger holds the battery at 4.2 V
+/// in the constant-voltage phase, and a discharging battery soon drops below 4.15 V, where `DISCHARGE_CURVE` reaches 100%.
+/// The margin below 4.2 V is for the error of the ADC.
+const CHARGING_VOLTAGE: f32 = 4.18;
+/// Change of the voltage between the readings regarded as connecting or disconnecting the USB power.
+/// The charging current raises the voltage at once. The step is chosen to be larger than the noise of the averaged readings.
+const CHARGING_STEP: f32 = 0.05;
+/// Remaining percentage to show the warning.
+const LOW_BATTERY_PERCENTAGE: u8 = 10;
+/// The monitor shuts down below this voltage, before the battery protection cuts off the power.
+const SHUTDOWN_VOLTAGE: f32 = 3.4;
+/// Remaining percentage at each voltage of the discharge curve of a LiPo battery.
+const DISCHARGE_CURVE: [(f32, u8); 7] = [(3.3, 0), (3.5, 5), (3.6, 10), (3.7, 30), (3.8, 50), (3.95, 75), (4.15, 100)];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PowerStatus {
+    pub voltage: f32,
+    pub percentage: u8,
+    pub charging: bool,
+}
+
+impl PowerStatus {
+    pub fn from_voltage(voltage: f32) -> Self {
+        let percentage = match DISCHARGE_CURVE.windows(2).find(|points| voltage < points[1].0) {
+            Some([(v0, p0), (v1, p1)]) if voltage > *v0 => (*p0 as f32 + (voltage - v0) / (v1 - v0) * (p1 - p0) as f32) as u8,
+            Some(_) => 0,
+            None => 100,
+        };
+        Self {
+            voltage,
+            percentage,
+            charging: voltage >= CHARGING_VOLTAGE,
+        }
+    }
+
+    pub fn is_low(&self) -> bool {
+        !self.charging && self.percentage <= LOW_BATTERY_PERCENTAGE
+    }
+
+    /// True if the monitor must shut down to protect the battery.
+    pub fn is_critical(&self) -> bool {
+        !self.charging && self.voltage < SHUTDOWN_VOLTAGE
+    }
+}
+
+/// Detects charging from the steps of the voltage between the readings.
+struct ChargingDetector {
+    charging: bool,
+    /// The last voltage, or the highest one while charging so that the drop on disconnecting is detected.
+    reference: Option<f32>,
+}
+
+impl ChargingDetector {
+    const fn new() -> Self {
+        Self {
+            charging: false,
+            reference: None,
+        }
+    }
+
+    fn update(&mut self, voltage: f32) -> bool {
+        self.reference = Some(match self.reference {
+            Some(reference) if !self.charging && voltage >= reference + CHARGING_STEP => {
+                self.charging = true;
+                voltage
+            },
+            Some(reference) if self.charging && voltage <= reference - CHARGING_STEP => {
+                self.charging = false;
+                voltage
+            },
+            Some(reference) if self.charging => reference.max(voltage),
+            _ => voltage,
+        });
+        self.charging
+    }
+}
+
+/// Kept while running. After the wakeup from deep sleep, charging is detected only by `CHARGING_VOLTAGE`.
+static CHARGING_DETECTOR: Mutex<ChargingDetector> = Mutex::new(ChargingDetector::new());
+
+pub fn power_status() -> Option<PowerStatus> {
+    let voltage = battery_voltage()?;
+    let mut status = PowerStatus::from_voltage(voltage);
+    status.charging |= CHARGING_DETECTOR.lock().unwrap().update(voltage);
+    Some(status)
+}
+
+/// Turn off the power after the display shows the warning. Pressing the power button turns it on again.
+#[cfg(target_os="espidf")]
+pub fn shutdown() -> ! {
+    std::thread::sleep(EPD_REFRESH_DURATION);
+    log::warn!("Shutting down to protect the battery");
+    unsafe {
+        // Release the main power (GPIO2), which turns off the power while running on the battery.
+        esp_idf_sys::gpio_hold_dis(2);
+        esp_idf_sys::gpio_set_level(2, 0);
+        // Sleep until the button is pressed if the power is still supplied.
+        esp_idf_sys::esp_sleep_enable_ext0_wakeup(38, 0);
+        esp_idf_sys::esp_deep_sleep_start();
+    }
+}
+#[cfg(target_os="linux")]
+pub fn shutdown() -> ! {
+    std::thread::sleep(EPD_REFRESH_DURATION);
+    log::warn!("Shutting down to protect the battery");
+    std::process::exit(0);
+}
+
+/// Number of the ADC readings averaged.
+#[cfg(target_os="espidf")]
+const ADC_SAMPLES: u32 = 16;
+
+/// Voltage of the battery in volts.
+#[cfg(target_os="espidf")]
+pub fn battery_voltage() -> Option<f32> {
+    use esp_idf_sys::*;
+    // The battery is connected to GPIO35 (ADC1 channel 7) through a 1/2 voltage divider.
+    static CONFIGURE_ADC: std::sync::Once = std::sync::Once::new();
+    CONFIGURE_ADC.call_once(|| unsafe {
+        adc1_config_width(adc_bits_width_t_ADC_WIDTH_BIT_12);
+        adc1_config_channel_atten(adc1_channel_t_ADC1_CHANNEL_7, adc_atten_t_ADC_ATTEN_DB_11);
+    });
+    let mut characteristics = esp_adc_cal_characteristics_t::default();
+    unsafe {
+        esp_adc_cal_characterize(adc_unit_t_ADC_UNIT_1, adc_atten_t_ADC_ATTEN_DB_11, adc_bits_width_t_ADC_WIDTH_BIT_12, 3600, &mut characteristics);
+    }
+    // Averaged to reduce the noise, so that the steps of charging are detected.
+    let mut sum = 0u32;
+    for _ in 0..ADC_SAMPLES {
+        let raw = unsafe { adc1_get_raw(adc1_channel_t_ADC1_CHANNEL_7) };
+        if raw < 0 {
+            return None;
+        }
+        sum += raw as u32;
+    }
+    let millivolts = unsafe { esp_adc_cal_raw_to_voltage(sum / ADC_SAMPLES, &characteristics) };
+    Some(millivolts as f32 * 2.0 / 1000.0)
+}
+/// Voltage given by the environment variable `BATTERY_VOLTAGE`, since there is no battery.
+#[cfg(target_os="linux")]
+pub fn battery_voltage() -> Option<f32> {
+    std::env::var("BATTERY_VOLTAGE").ok().and_then(|voltage| voltage.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charging_by_voltage() {
+        assert!(!PowerStatus::from_voltage(4.15).charging);
+        assert!(PowerStatus::from_voltage(4.19).charging);
+        assert_eq!(PowerStatus::from_voltage(4.19).percentage, 100);
+        assert_eq!(PowerStatus::from_voltage(3.75).percentage, 40);
+    }
+
+    #[test]
+    fn charging_by_steps() {
+        let mut detector = ChargingDetector::new();
+        assert!(!detector.update(3.80));
+        // The noise and the discharge are not charging.
+        assert!(!detector.update(3.82));
+        assert!(!detector.update(3.79));
+        // Connected.
+        assert!(detector.update(3.90));
+        assert!(detector.update(3.95));
+        assert!(detector.update(3.92));
+        // Disconnected.
+        assert!(!detector.update(3.88));
+        assert!(!detector.update(3.87));
+    }
+}
@@ -0,0 +1,171 @@
+//! Remos on the LAN, discovered by mDNS and read through their local API.
+//!
+//! The local API only provides the last IR signal received by each Remo, not the sensor values,
+//! so the sensor values are always read from the Cloud API. The local data is read by its own task,
+//! so that unreachable Remos do not delay the sensor updates.
+//!
+//! Nothing falls back to the local API: the appliances are still controlled through the Cloud API,
+//! since it does not return the IR data of the registered signals. During an outage of the Cloud,
+//! only the reachability of the Remos and their last received IR signal are shown.
+
+use std::{fmt::Write, net::Ipv4Addr, sync::Mutex, time::Duration};
+
+use crate::{clock, connectivity, copy_truncated, fetch_local, json_string_of, json_value_of, mdns, Timestamp};
+
+const REMO_SERVICE: &str = "_remo._tcp.local";
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Timeout of each request to the local API, which responds quickly if reachable.
+const LOCAL_API_TIMEOUT: Duration = Duration::from_secs(2);
+const UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+/// Remos are discovered again at this interval, or more often while none is found.
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const RETRY_DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_LOCAL_REMOS: usize = 4;
+
+#[cfg(target_os="espidf")]
+const REMO_LOCAL_TASK_STACK_SIZE: usize = 8 * 1024;
+#[cfg(target_os="linux")]
+const REMO_LOCAL_TASK_STACK_SIZE: usize = 256 * 1024;
+
+/// Summary of the last IR signal received by a Remo.
+#[derive(Clone, Debug, Default)]
+pub struct IrMessage {
+    pub format: heapless::String<8>,
+    /// Carrier frequency in kHz.
+    pub freq: u32,
+    /// Number of the on/off durations.
+    pub length: usize,
+}
+
+/// A Remo found on the LAN.
+#[derive(Clone, Debug)]
+pub struct LocalRemo {
+    pub name: heapless::String<64>,
+    pub address: Ipv4Addr,
+    pub port: u16,
+    /// True if the last request to the local API succeeded.
+    pub reachable: bool,
+    pub last_message: Option<IrMessage>,
+}
+
+pub struct LocalStatus {
+    pub remos: heapless::Vec<LocalRemo, MAX_LOCAL_REMOS>,
+    pub last_discovery: Option<Timestamp>,
+}
+
+impl LocalStatus {
+    pub const fn new() -> Self {
+        Self {
+            remos: heapless::Vec::new(),
+            last_discovery: None,
+        }
+    }
+}
+
+/// State of the Remos on the LAN.
+pub static LOCAL_STATUS: Mutex<LocalStatus> = Mutex::new(LocalStatus::new());
+
+fn discover() -> anyhow::Result<heapless::Vec<LocalRemo, MAX_LOCAL_REMOS>> {
+    let mut remos = heapless::Vec::new();
+    for instance in mdns::browse(REMO_SERVICE, DISCOVERY_TIMEOUT)? {
+        log::info!("Remo found: {} ({}) {:?}:{}", instance.name, instance.hostname, instance.address, instance.port);
+        let address = match instance.address {
+            Some(address) => address,
+            None => continue,
+        };
+        let remo = LocalRemo {
+            name: copy_truncated(&instance.name),
+            address,
+            port: instance.port,
+            reachable: false,
+            last_message: None,
+        };
+        if remos.push(remo).is_err() {
+            break;
+        }
+    }
+    Ok(remos)
+}
+
+/// Extract the summary from the body of `GET /messages`, e.g. `{"format":"us","freq":38,"data":[...]}`.
+fn parse_ir_message(body: &str) -> Option<IrMessage> {
+    let mut message = IrMessage::default();
+    message.format.push_str(json_string_of(body, "\"format\"")?).ok()?;
+    let freq = json_value_of(body, "\"freq\"")?;
+    message.freq = freq[..freq.find(|c: char| !c.is_ascii_digit()).unwrap_or(freq.len())].parse().ok()?;
+    let data = json_value_of(body, "\"data\"")?.strip_prefix('[')?;
+    let data = &data[..data.find(']')?];
+    message.length = if data.trim().is_empty() { 0 } else { data.split(',').count() };
+    Some(message)
+}
+
+fn read_messages(remo: &LocalRemo) -> anyhow::Result<Option<IrMessage>> {
+    let mut url = heapless::String::<64>::new();
+    write!(&mut url, "http://{}:{}/messages", remo.address, remo.port).ok();
+    let body = fetch_local(&url, LOCAL_API_TIMEOUT)?;
+    Ok(parse_ir_message(&String::from_utf8_lossy(&body)))
+}
+
+/// Discover the Remos when needed and read the last IR signals from them.
+fn update() {
+    let now = clock::now();
+    let needs_discovery = {
+        let status = LOCAL_STATUS.lock().unwrap();
+        let interval = if status.remos.is_empty() { RETRY_DISCOVERY_INTERVAL } else { DISCOVERY_INTERVAL };
+        status.last_discovery
+            .and_then(|last| (now - last).to_std().ok())
+            .map(|elapsed| elapsed >= interval)
+            .unwrap_or(true)
+    };
+    if needs_discovery {
+        let remos = match discover() {
+            Ok(remos) => remos,
+            Err(err) => {
+                log::warn!("Remo discovery failed - {:?}", err);
+                heapless::Vec::new()
+            },
+        };
+        log::info!("{} Remo(s) found on the LAN", remos.len());
+        let mut status = LOCAL_STATUS.lock().unwrap();
+        status.remos = remos;
+        status.last_discovery = Some(now);
+    }
+
+    // Access the local API without holding the lock.
+    let mut remos = LOCAL_STATUS.lock().unwrap().remos.clone();
+    for remo in remos.iter_mut() {
+        match read_messages(remo) {
+            Ok(message) => {
+                remo.reachable = true;
+                if message.is_some() {
+                    remo.last_message = message;
+                }
+            },
+            Err(err) => {
+                log::warn!("Failed to access the local API of {} - {:?}", remo.name, err);
+                remo.reachable = false;
+            },
+        }
+    }
+    LOCAL_STATUS.lock().unwrap().remos = remos;
+}
+
+fn remo_local_task() -> ! {
+    let wifi_events = connectivity::subscribe();
+    loop {
+        while !connectivity::is_connected() {
+            wifi_events.recv().ok();
+        }
+        update();
+        clock::sleep(UPDATE_INTERVAL);
+    }
+}
+
+/// Start the task which reads the Remos on the LAN periodically.
+pub fn start_task() {
+    std::thread::Builder::new()
+        .name("REMO_LOCAL".into())
+        .stack_size(REMO_LOCAL_TASK_STACK_SIZE)
+        .spawn(|| remo_local_task())
+        .expect("Failed to launch REMO_LOCAL task");
+}
@@ -0,0 +1,131 @@
+//! Sends the IR signals registered to the appliances, so that the monitor works as a wall-mounted remote.
+//!
+//! The signals are read from `GET /1/appliances` and sent by `POST /1/signals/{id}/send`.
+
+use std::{fmt::Write, sync::Mutex};
+
+use fuga_remo_api::{Appliance, ApplianceSubNode};
+use uuid::Uuid;
+
+use crate::{api_post, copy_truncated, input::{self, InputEvent}, spawn_background, CONFIG};
+
+pub const MAX_REMOTE_SIGNALS: usize = 27;
+/// Number of appliances which can be selected for the remote.
+pub const MAX_REMOTE_APPLIANCES: usize = 4;
+
+#[derive(Clone, Debug)]
+pub struct RemoteSignal {
+    pub id: Uuid,
+    pub name: heapless::String<32>,
+    /// Nickname of the appliance which the signal belongs to.
+    pub appliance: heapless::String<32>,
+}
+
+/// Result of the last signal sent.
+#[derive(Clone, Debug)]
+pub enum SendResult {
+    Sending,
+    Sent,
+    Failed(heapless::String<64>),
+}
+
+pub struct RemoteStatus {
+    pub signals: heapless::Vec<RemoteSignal, MAX_REMOTE_SIGNALS>,
+    /// The last signal and its result.
+    pub last_sent: Option<(Uuid, SendResult)>,
+}
+
+impl RemoteStatus {
+    pub const fn new() -> Self {
+        Self {
+            signals: heapless::Vec::new(),
+            last_sent: None,
+        }
+    }
+
+    pub fn is_sending(&self) -> bool {
+        matches!(self.last_sent, Some((_, SendResult::Sending)))
+    }
+}
+
+/// Signals of the selected appliances, shown on the remote page.
+pub static REMOTE_STATUS: Mutex<RemoteStatus> = Mutex::new(RemoteStatus::new());
+
+/// Parse the appliance IDs separated by ','. Invalid IDs are skipped.
+pub fn parse_appliance_ids(ids: &str) -> heapless::Vec<Uuid, MAX_REMOTE_APPLIANCES> {
+    let mut parsed = heapless::Vec::new();
+    for id in ids.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+        match Uuid::parse_str(id) {
+            Ok(id) => {
+                if parsed.push(id).is_err() {
+                    log::warn!("Too many remote appliances. {} is ignored", id);
+                }
+            },
+            Err(err) => log::warn!("Invalid appliance ID {} - {}", id, err),
+        }
+    }
+    parsed
+}
+
+/// Read a signal from a sub node of `GET /1/appliances` if the appliance is selected for the remote.
+/// All appliances are selected if none is configured.
+pub fn signal_from(appliance: &Appliance, sub_node: Option<&ApplianceSubNode>) -> Option<RemoteSignal> {
+    let signal = match sub_node {
+        Some(ApplianceSubNode::Signal(signal)) => signal,
+        _ => return None,
+    };
+    let is_selected = {
+        let guard = CONFIG.lock().unwrap();
+        let selected = &guard.as_ref().unwrap().remote_appliances;
+        selected.is_empty() || selected.contains(&appliance.id)
+    };
+    is_selected.then(|| RemoteSignal {
+        id: signal.id,
+        name: copy_truncated(&signal.name),
+        appliance: copy_truncated(&appliance.nickname),
+    })
+}
+
+/// Update the signals with the ones read from the Cloud API.
+pub fn update_signals(signals: heapless::Vec<RemoteSignal, MAX_REMOTE_SIGNALS>) {
+    REMOTE_STATUS.lock().unwrap().signals = signals;
+}
+
+pub fn send_signal(id: Uuid) -> anyhow::Result<()> {
+    let mut path = heapless::String::<64>::new();
+    write!(&mut path, "/1/signals/{}/send", id).ok();
+    api_post(&path, "")?;
+    Ok(())
+}
+
+/// Send the signal at the index in the background.
+/// Returns false if there is no such signal or another signal is being sent.
+pub fn send(index: usize) -> bool {
+    let id = {
+        let mut status = REMOTE_STATUS.lock().unwrap();
+        if status.is_sending() {
+            return false;
+        }
+        let id = match status.signals.get(index) {
+            Some(signal) => signal.id,
+            None => return false,
+        };
+        status.last_sent = Some((id, SendResult::Sending));
+        id
+    };
+    log::info!("send signal {}", id);
+    spawn_background("REMOTE", move || {
+        let result = match send_signal(id) {
+            Ok(()) => SendResult::Sent,
+            Err(err) => {
+                log::warn!("Failed to send the signal - {:?}", err);
+                let mut message = heapless::String::new();
+                write!(&mut message, "{}", err).ok();
+                SendResult::Failed(message)
+            },
+        };
+        REMOTE_STATUS.lock().unwrap().last_sent = Some((id, result));
+        input::push_event(InputEvent::Refresh);
+    });
+    true
+}
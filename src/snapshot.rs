@@ -0,0 +1,206 @@
+use std::path::Path;
+
+use chrono::TimeZone;
+
+use crate::canvas::Color;
+use crate::chart::Chart;
+use crate::connectivity::{NetworkStatus, WifiStatus};
+use crate::framebuffer::Framebuffer;
+use crate::{ui, RateLimitInfo, SensorRecord, SensorRecords, SENSOR_RECORD_CAPACITY};
+
+const REFERENCE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/snapshots");
+/// Rendered images which do not match the references are written here.
+const OUTPUT_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/target/snapshots");
+
+/// A pixel is regarded as different if any channel differs more than this.
+const CHANNEL_TOLERANCE: u8 = 16;
+/// Ratio of the different pixels allowed in an image.
+const MAX_DIFF_RATIO: f32 = 0.001;
+
+const SAMPLE_COUNT: usize = 120;
+const CHART_WIDTH: i32 = 660;
+const CHART_HEIGHT: i32 = 160;
+const CHART_MARGIN: i32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotMode {
+    /// Compare the rendered images with the references.
+    Verify,
+    /// Overwrite the references with the rendered images.
+    Update,
+}
+
+/// Sequences of sensor values fed to the chart and the dashboard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Fixture {
+    /// Small fluctuation around a steady value.
+    Flat,
+    /// Steady value with a single spike.
+    Spike,
+    /// Some samples are missing.
+    MissingData,
+    /// All values are equal, so the range of the chart is zero.
+    AllEqual,
+}
+
+impl Fixture {
+    const ALL: [Fixture; 4] = [Fixture::Flat, Fixture::Spike, Fixture::MissingData, Fixture::AllEqual];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Fixture::Flat => "flat",
+            Fixture::Spike => "spike",
+            Fixture::MissingData => "missing_data",
+            Fixture::AllEqual => "all_equal",
+        }
+    }
+
+    /// Offset from the base value of each sensor at the index.
+    fn value(&self, index: usize) -> Option<f32> {
+        match self {
+            Fixture::Flat => Some(0.1 * (index as f32 * 0.3).sin()),
+            Fixture::Spike => Some(if index == SAMPLE_COUNT * 2 / 3 { 10.0 } else { 0.0 }),
+            Fixture::MissingData if (SAMPLE_COUNT / 3..SAMPLE_COUNT / 2).contains(&index) || index % 10 == 9 => None,
+            Fixture::MissingData => Some(index as f32 * 0.05),
+            Fixture::AllEqual => Some(0.0),
+        }
+    }
+
+    /// Records of the values. The missing samples are not stored, as the sample task does.
+    fn records(&self) -> Box<SensorRecords<SENSOR_RECORD_CAPACITY>> {
+        let mut records = Box::new(SensorRecords::new());
+        let timestamp = chrono::Utc.timestamp_opt(1_672_531_200, 0).single().unwrap();
+        for value in (0..SAMPLE_COUNT).filter_map(|index| self.value(index)) {
+            let record = SensorRecord {
+                ambient_temperature: 24.0 + value,
+                relative_humidity: 50.0 + value * 2.0,
+                ambient_luminous_level: 100.0 + value * 10.0,
+                instant_power_usage: 400.0 + value * 50.0,
+            };
+            records.add_with_timestamp(record, timestamp);
+        }
+        records
+    }
+}
+
+fn render_chart(fixture: Fixture) -> Framebuffer {
+    let mut framebuffer = Framebuffer::new(CHART_WIDTH + CHART_MARGIN * 2, CHART_HEIGHT + CHART_MARGIN * 2);
+    let values: Vec<Option<f32>> = (0..SAMPLE_COUNT).map(|index| fixture.value(index)).collect();
+    let (min, max) = values.iter().flatten().fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| (min.min(*value), max.max(*value)));
+    Chart::new(CHART_WIDTH, CHART_HEIGHT, Color::BLACK, Color::WHITE)
+        .draw(&mut framebuffer, CHART_MARGIN, CHART_MARGIN, values.len(), min, max, |index| values[index])
+        .ok();
+    framebuffer
+}
+
+fn render_dashboard(fixture: Fixture, language: ui::Language) -> Framebuffer {
+    let mut framebuffer = Framebuffer::new(ui::SCREEN_WIDTH, ui::SCREEN_HEIGHT);
+    let records = fixture.records();
+    let (min, max) = records.min_max();
+    let context = ui::UiContext {
+        records: &records,
+        min,
+        max,
+        network: NetworkStatus {
+            wifi: WifiStatus {
+                connected: true,
+                ssid: heapless::String::from("wifi ap"),
+                rssi: Some(-55),
+                ip: Some(std::net::Ipv4Addr::new(192, 168, 1, 20)),
+            },
+            rate_limit: RateLimitInfo {
+                limit: Some(30),
+                remaining: Some(25),
+                reset: None,
+            },
+            // Not shown to keep the images independent of the current time.
+            last_fetched: None,
+            last_error: None,
+            time_synced: true,
+        },
+        power: None,
+        device_name: heapless::String::from(match language {
+            ui::Language::English => "Living",
+            ui::Language::Japanese => "リビング",
+        }),
+        language,
+        local_time: chrono::NaiveDate::from_ymd_opt(2023, 1, 1).and_then(|date| date.and_hms_opt(12, 34, 0)),
+        alerts: heapless::Vec::new(),
+    };
+    ui::Navigator::new().render(&mut framebuffer, &context);
+    framebuffer
+}
+
+/// Count the pixels which differ from the reference image.
+fn count_different_pixels(framebuffer: &Framebuffer, reference: &Path) -> anyhow::Result<usize> {
+    let decoder = png::Decoder::new(std::fs::File::open(reference)?);
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    if info.color_type != png::ColorType::Rgb || info.bit_depth != png::BitDepth::Eight {
+        anyhow::bail!("unsupported format {:?} {:?}", info.color_type, info.bit_depth);
+    }
+    if info.width != framebuffer.width() as u32 || info.height != framebuffer.height() as u32 {
+        anyhow::bail!("size mismatch: expected {}x{}, actual {}x{}", info.width, info.height, framebuffer.width(), framebuffer.height());
+    }
+    let count = buffer.chunks_exact(3)
+        .zip(framebuffer.to_rgb888().chunks_exact(3))
+        .filter(|(expected, actual)| expected.iter().zip(actual.iter()).any(|(expected, actual)| expected.abs_diff(*actual) > CHANNEL_TOLERANCE))
+        .count();
+    Ok(count)
+}
+
+/// Render the chart and the dashboard with each fixture and check them against the reference images.
+/// Returns true if all images match.
+pub fn run(mode: SnapshotMode) -> bool {
+    let mut failures = 0;
+    let mut snapshots = Vec::new();
+    for fixture in Fixture::ALL {
+        snapshots.push((format!("chart_{}", fixture.name()), render_chart(fixture)));
+        snapshots.push((format!("dashboard_{}", fixture.name()), render_dashboard(fixture, ui::Language::English)));
+    }
+    // The Japanese UI uses the other fonts, also for the device name.
+    snapshots.push((format!("dashboard_{}_ja", Fixture::Flat.name()), render_dashboard(Fixture::Flat, ui::Language::Japanese)));
+    for (name, framebuffer) in snapshots {
+        let reference = Path::new(REFERENCE_DIR).join(format!("{}.png", name));
+        if mode == SnapshotMode::Update {
+            if let Err(err) = std::fs::create_dir_all(REFERENCE_DIR).map_err(anyhow::Error::from).and_then(|_| framebuffer.write_png(&reference)) {
+                log::error!("{}: failed to update the reference - {:?}", name, err);
+                failures += 1;
+            } else {
+                log::info!("{}: updated", name);
+            }
+            continue;
+        }
+        let allowed = ((framebuffer.width() * framebuffer.height()) as f32 * MAX_DIFF_RATIO) as usize;
+        match count_different_pixels(&framebuffer, &reference) {
+            Ok(count) if count <= allowed => log::info!("{}: ok", name),
+            result => {
+                match result {
+                    Ok(count) => log::error!("{}: {} pixels differ (allowed {})", name, count, allowed),
+                    Err(err) => log::error!("{}: failed to compare with {:?} - {:?}", name, reference, err),
+                }
+                let actual = Path::new(OUTPUT_DIR).join(format!("{}.png", name));
+                if std::fs::create_dir_all(OUTPUT_DIR).is_ok() && framebuffer.write_png(&actual).is_ok() {
+                    log::error!("{}: rendered image is written to {:?}", name, actual);
+                }
+                failures += 1;
+            },
+        }
+    }
+    if failures > 0 {
+        log::error!("{} snapshot(s) failed", failures);
+    }
+    failures == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots() {
+        env_logger::builder().is_test(true).try_init().ok();
+        assert!(run(SnapshotMode::Verify), "snapshots differ from the references. See the log for details.");
+    }
+}
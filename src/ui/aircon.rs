@@ -0,0 +1,105 @@
+use std::fmt::Write;
+
+use crate::aircon::{self, AirconChange, AIRCON_STATUS};
+use crate::canvas::{Align, Canvas, Color};
+use crate::input::InputEvent;
+use super::{Page, UiContext, Layout, Navigation, Text, FontRole, draw_name, draw_text};
+
+/// Buttons under each air conditioner, from left to right.
+const BUTTONS: [(AirconChange, Option<Text>, &str); 5] = [
+    (AirconChange::TogglePower, Some(Text::PowerButton), ""),
+    (AirconChange::NextMode, Some(Text::ModeButton), ""),
+    (AirconChange::TemperatureDown, None, "-"),
+    (AirconChange::TemperatureUp, None, "+"),
+    (AirconChange::NextVolume, Some(Text::FanButton), ""),
+];
+const BUTTON_LEFT: i32 = 20;
+const BUTTON_WIDTH: i32 = 150;
+const BUTTON_GAP: i32 = 20;
+/// Lines of each air conditioner: the name and the settings, the buttons, and the state of the last change.
+const LINES_PER_AIRCON: i32 = 3;
+
+/// Settings of the air conditioners with the buttons to change them.
+pub struct AirconPage {
+    top: i32,
+    line_height: i32,
+}
+
+impl AirconPage {
+    pub fn new() -> Self {
+        Self {
+            top: 0,
+            line_height: 0,
+        }
+    }
+
+    /// Find the air conditioner and the button at the point.
+    fn hit_test(&self, x: i32, y: i32) -> Option<(usize, AirconChange)> {
+        if self.line_height <= 0 || y < self.top {
+            return None;
+        }
+        let line = (y - self.top) / self.line_height;
+        if line % LINES_PER_AIRCON != 1 || x < BUTTON_LEFT {
+            return None;
+        }
+        let column = (x - BUTTON_LEFT) / (BUTTON_WIDTH + BUTTON_GAP);
+        let in_button = (x - BUTTON_LEFT) % (BUTTON_WIDTH + BUTTON_GAP) < BUTTON_WIDTH;
+        let (change, _, _) = BUTTONS.get(column as usize).filter(|_| in_button)?;
+        Some(((line / LINES_PER_AIRCON) as usize, *change))
+    }
+}
+
+impl Page for AirconPage {
+    fn title(&self) -> Text {
+        Text::Aircon
+    }
+
+    fn render(&mut self, canvas: &mut dyn Canvas, context: &UiContext, layout: &Layout) {
+        crate::refresh_appliances_if_needed();
+        self.top = layout.top;
+        self.line_height = layout.small_line_height();
+        let status = AIRCON_STATUS.lock().unwrap();
+        if status.aircons.is_empty() {
+            draw_text(canvas, context, FontRole::Body, context.text(Text::NoAircon), 20, layout.top, Align::Left);
+            return;
+        }
+        for (index, aircon) in status.aircons.iter().enumerate() {
+            let top = self.top + self.line_height * LINES_PER_AIRCON * index as i32;
+            let settings = &aircon.settings;
+            let mut settings_str = heapless::String::<48>::new();
+            write!(&mut settings_str, "{} {}", if settings.is_on() { "ON" } else { "OFF" }, settings.mode).ok();
+            if !settings.temperature.is_empty() {
+                write!(&mut settings_str, " {}C", settings.temperature).ok();
+            }
+            write!(&mut settings_str, " {}:{}", context.text(Text::FanButton), settings.volume).ok();
+            draw_name(canvas, FontRole::Body, &aircon.nickname, 20, top, Align::Left);
+            draw_text(canvas, context, FontRole::Body, &settings_str, 360, top, Align::Left);
+
+            let button_top = top + self.line_height;
+            for (column, (_, label, symbol)) in BUTTONS.iter().enumerate() {
+                let left = BUTTON_LEFT + (BUTTON_WIDTH + BUTTON_GAP) * column as i32;
+                let label = label.map(|label| context.text(label)).unwrap_or(symbol);
+                canvas.fill_rect(left, button_top, BUTTON_WIDTH, self.line_height - 4, Color::BLACK);
+                draw_text(canvas, context, FontRole::TopBar, label, left + 10, button_top, Align::Left);
+            }
+
+            let state_top = top + self.line_height * 2;
+            if aircon.pending {
+                draw_text(canvas, context, FontRole::Body, context.text(Text::Sending), 20, state_top, Align::Left);
+            } else if let Some(error) = aircon.error.as_ref() {
+                draw_text(canvas, context, FontRole::Body, error, 20, state_top, Align::Left);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: InputEvent, _context: &UiContext) -> Navigation {
+        match event {
+            InputEvent::Tap { x, y } => match self.hit_test(x, y) {
+                // Redraw at once to show the new settings before the Cloud API responds.
+                Some((index, change)) if aircon::change(index, change) => Navigation::Redraw,
+                _ => Navigation::None,
+            },
+            _ => Navigation::None,
+        }
+    }
+}
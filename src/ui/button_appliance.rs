@@ -0,0 +1,129 @@
+use std::fmt::Write;
+
+use crate::button_appliance::{self, ButtonApplianceKind, BUTTON_APPLIANCES};
+use crate::canvas::{Align, Canvas, Color};
+use crate::input::{InputEvent, SwipeDirection};
+use super::{Page, UiContext, Layout, Navigation, Text, FontRole, SCREEN_HEIGHT, SCREEN_WIDTH, draw_name, draw_text};
+
+const COLUMNS: usize = 4;
+const ROWS: usize = 4;
+const MARGIN: i32 = 20;
+
+/// Buttons of a light or a TV. Swipe up/down to switch the appliances of the kind.
+pub struct ButtonAppliancePage {
+    kind: ButtonApplianceKind,
+    /// Index of the appliance among the ones of the kind.
+    appliance: usize,
+    top: i32,
+    button_height: i32,
+}
+
+impl ButtonAppliancePage {
+    pub fn new(kind: ButtonApplianceKind) -> Self {
+        Self {
+            kind,
+            appliance: 0,
+            top: 0,
+            button_height: 0,
+        }
+    }
+
+    fn button_width() -> i32 {
+        (SCREEN_WIDTH - MARGIN * (COLUMNS as i32 + 1)) / COLUMNS as i32
+    }
+
+    /// Index of the button at the point.
+    fn hit_test(&self, x: i32, y: i32) -> Option<usize> {
+        if self.button_height <= 0 || x < MARGIN || y < self.top {
+            return None;
+        }
+        let column = ((x - MARGIN) / (Self::button_width() + MARGIN)) as usize;
+        let row = ((y - self.top) / (self.button_height + MARGIN)) as usize;
+        let in_button = (x - MARGIN) % (Self::button_width() + MARGIN) < Self::button_width()
+            && (y - self.top) % (self.button_height + MARGIN) < self.button_height;
+        (column < COLUMNS && row < ROWS && in_button).then(|| row * COLUMNS + column)
+    }
+}
+
+impl Page for ButtonAppliancePage {
+    fn title(&self) -> Text {
+        match self.kind {
+            ButtonApplianceKind::Light => Text::Light,
+            ButtonApplianceKind::Tv => Text::Tv,
+        }
+    }
+
+    fn render(&mut self, canvas: &mut dyn Canvas, context: &UiContext, layout: &Layout) {
+        crate::refresh_appliances_if_needed();
+        let appliances = BUTTON_APPLIANCES.lock().unwrap();
+        let count = appliances.iter().filter(|appliance| appliance.kind == self.kind).count();
+        if count == 0 {
+            let text = match self.kind {
+                ButtonApplianceKind::Light => Text::NoLight,
+                ButtonApplianceKind::Tv => Text::NoTv,
+            };
+            draw_text(canvas, context, FontRole::Body, context.text(text), MARGIN, layout.top, Align::Left);
+            return;
+        }
+        self.appliance = self.appliance.min(count - 1);
+        let appliance = appliances.iter().filter(|appliance| appliance.kind == self.kind).nth(self.appliance).unwrap();
+
+        let line_height = layout.small_line_height();
+        draw_name(canvas, FontRole::Body, &appliance.nickname, MARGIN, layout.top, Align::Left);
+        draw_text(canvas, context, FontRole::Body, &appliance.state, SCREEN_WIDTH - MARGIN, layout.top, Align::Right);
+        // The last line shows the result of the last press and the appliance number.
+        let status_top = SCREEN_HEIGHT - line_height;
+        self.top = layout.top + line_height + MARGIN / 2;
+        self.button_height = (status_top - self.top - MARGIN * ROWS as i32) / ROWS as i32;
+
+        let button_width = Self::button_width();
+        for (index, button) in appliance.buttons.iter().enumerate().take(COLUMNS * ROWS) {
+            let left = MARGIN + (button_width + MARGIN) * (index % COLUMNS) as i32;
+            let top = self.top + (self.button_height + MARGIN) * (index / COLUMNS) as i32;
+            canvas.fill_rect(left, top, button_width, self.button_height, Color::BLACK);
+            // The button being sent is drawn with the normal colors.
+            if appliance.pending == Some(index) {
+                canvas.fill_rect(left + 4, top + 4, button_width - 8, self.button_height - 8, Color::WHITE);
+                draw_name(canvas, FontRole::Body, button.text(), left + 12, top + 8, Align::Left);
+            } else {
+                draw_name(canvas, FontRole::TopBar, button.text(), left + 12, top + 8, Align::Left);
+            }
+        }
+
+        if appliance.pending.is_some() {
+            draw_text(canvas, context, FontRole::Body, context.text(Text::Sending), MARGIN, status_top, Align::Left);
+        } else if let Some(error) = appliance.error.as_ref() {
+            draw_text(canvas, context, FontRole::Body, error, MARGIN, status_top, Align::Left);
+        }
+        if count > 1 {
+            let mut number_str = heapless::String::<8>::new();
+            write!(&mut number_str, "{}/{}", self.appliance + 1, count).ok();
+            draw_text(canvas, context, FontRole::Body, &number_str, SCREEN_WIDTH - MARGIN, status_top, Align::Right);
+        }
+    }
+
+    fn handle_event(&mut self, event: InputEvent, _context: &UiContext) -> Navigation {
+        match event {
+            InputEvent::Tap { x, y } => {
+                let id = BUTTON_APPLIANCES.lock().unwrap().iter()
+                    .filter(|appliance| appliance.kind == self.kind)
+                    .nth(self.appliance)
+                    .map(|appliance| appliance.id);
+                match (id, self.hit_test(x, y)) {
+                    (Some(id), Some(index)) if button_appliance::press(id, index) => Navigation::Redraw,
+                    _ => Navigation::None,
+                }
+            },
+            InputEvent::Swipe { direction: SwipeDirection::Up, .. } => {
+                // Clamped to the last appliance while rendering.
+                self.appliance += 1;
+                Navigation::Redraw
+            },
+            InputEvent::Swipe { direction: SwipeDirection::Down, .. } if self.appliance > 0 => {
+                self.appliance -= 1;
+                Navigation::Redraw
+            },
+            _ => Navigation::None,
+        }
+    }
+}
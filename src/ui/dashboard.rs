@@ -0,0 +1,77 @@
+use std::fmt::Write;
+
+use chrono::Datelike;
+
+use crate::canvas::{Align, Canvas, Color};
+use crate::input::InputEvent;
+use super::{Page, UiContext, Layout, Navigation, PageId, SensorKind, Text, FontRole, SCREEN_HEIGHT, SCREEN_WIDTH, draw_sensor_panel, draw_text};
+
+const PANELS: [SensorKind; 3] = [SensorKind::Temperature, SensorKind::Humidity, SensorKind::Power];
+
+/// Clock, current values and charts of temperature, humidity and power.
+pub struct DashboardPage {
+    panel_top: i32,
+    panel_height: i32,
+}
+
+impl DashboardPage {
+    pub fn new() -> Self {
+        Self {
+            panel_top: 0,
+            panel_height: 0,
+        }
+    }
+
+    fn clock_height(layout: &Layout) -> i32 {
+        layout.line_height() * 3 / 2
+    }
+
+    /// Draw the time and the date over the area of the clock.
+    fn draw_clock(canvas: &mut dyn Canvas, context: &UiContext, layout: &Layout) {
+        let height = Self::clock_height(layout);
+        let mut time_str = heapless::String::<8>::new();
+        let mut date_str = heapless::String::<48>::new();
+        match context.local_time {
+            Some(time) => {
+                write!(&mut time_str, "{}", time.format("%H:%M")).ok();
+                write!(&mut date_str, "{} {}", time.format(context.text(Text::DateFormat)), context.text(Text::weekday(time.weekday()))).ok();
+            },
+            None => {
+                time_str.push_str("--:--").ok();
+            },
+        }
+        canvas.fill_rect(0, layout.top, SCREEN_WIDTH, height, Color::WHITE);
+        draw_text(canvas, context, FontRole::Clock, &time_str, 20, layout.top, Align::Left);
+        draw_text(canvas, context, FontRole::Label, &date_str, 280, layout.top + (height - layout.line_height()) / 2, Align::Left);
+    }
+}
+
+impl Page for DashboardPage {
+    fn title(&self) -> Text {
+        Text::Dashboard
+    }
+
+    fn render(&mut self, canvas: &mut dyn Canvas, context: &UiContext, layout: &Layout) {
+        Self::draw_clock(canvas, context, layout);
+        self.panel_top = layout.top + Self::clock_height(layout);
+        self.panel_height = (SCREEN_HEIGHT - layout.line_height() - Self::clock_height(layout)) / PANELS.len() as i32;
+        for (index, kind) in PANELS.iter().enumerate() {
+            draw_sensor_panel(canvas, context, layout, *kind, self.panel_top + self.panel_height * index as i32, self.panel_height);
+        }
+    }
+
+    fn handle_event(&mut self, event: InputEvent, _context: &UiContext) -> Navigation {
+        match event {
+            InputEvent::Tap { y, .. } if y >= self.panel_top && self.panel_height > 0 => {
+                let index = (((y - self.panel_top) / self.panel_height) as usize).min(PANELS.len() - 1);
+                Navigation::Open(PageId::SensorDetail(PANELS[index]))
+            },
+            _ => Navigation::None,
+        }
+    }
+
+    fn render_clock(&mut self, canvas: &mut dyn Canvas, context: &UiContext, layout: &Layout) -> bool {
+        Self::draw_clock(canvas, context, layout);
+        true
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt::Write;
+
+use chrono::TimeZone;
+
+use crate::canvas::Canvas;
+use crate::input::InputEvent;
+use crate::{clock, CONFIG, SENSOR_RECORD_CAPACITY};
+use crate::remo_local::LOCAL_STATUS;
+use super::{Page, UiContext, Layout, Navigation, Text, draw_rows};
+
+/// Connection state, API quota and the target devices.
+pub struct DeviceStatusPage {}
+
+impl DeviceStatusPage {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Page for DeviceStatusPage {
+    fn title(&self) -> Text {
+        Text::Status
+    }
+
+    fn render(&mut self, canvas: &mut dyn Canvas, context: &UiContext, layout: &Layout) {
+        let mut wifi_str = heapless::String::<64>::new();
+        let mut quota_str = heapless::String::<32>::new();
+        let mut reset_str = heapless::String::<32>::new();
+        let mut last_update_str = heapless::String::<32>::new();
+        let mut records_str = heapless::String::<32>::new();
+        let mut device_id_str = heapless::String::<40>::new();
+        let mut appliance_id_str = heapless::String::<40>::new();
+        let mut local_remo_str = heapless::String::<64>::new();
+        let mut ir_signal_str = heapless::String::<32>::new();
+
+        if context.network.wifi.connected {
+            write!(&mut wifi_str, "{} {}", context.text(Text::Connected), context.network.wifi.ssid).ok();
+            if let Some(rssi) = context.network.wifi.rssi {
+                write!(&mut wifi_str, " {}dBm", rssi).ok();
+            }
+        } else {
+            wifi_str.write_str(context.text(Text::NotConnected)).ok();
+        }
+        match (context.network.rate_limit.remaining, context.network.rate_limit.limit) {
+            (Some(remaining), Some(limit)) => write!(&mut quota_str, "{}/{}", remaining, limit).ok(),
+            _ => quota_str.write_str("--").ok(),
+        };
+        match context.network.rate_limit.reset.and_then(|reset| chrono::Utc.timestamp_opt(reset as i64, 0).single()) {
+            Some(reset) => write!(&mut reset_str, "{}", clock::local(reset).format("%H:%M:%S")).ok(),
+            None => reset_str.write_str("--").ok(),
+        };
+        match context.records.last_timestamp() {
+            Some(timestamp) => write!(&mut last_update_str, "{}", clock::local(timestamp).format("%m-%d %H:%M:%S")).ok(),
+            None => last_update_str.write_str("--").ok(),
+        };
+        write!(&mut records_str, "{}/{}", context.records.len(), SENSOR_RECORD_CAPACITY).ok();
+        if let Some(config) = CONFIG.lock().unwrap().as_ref() {
+            write!(&mut device_id_str, "{}", config.device_id).ok();
+            write!(&mut appliance_id_str, "{}", config.appliance_id).ok();
+        }
+        {
+            let local_status = LOCAL_STATUS.lock().unwrap();
+            match local_status.remos.first() {
+                Some(remo) => {
+                    write!(&mut local_remo_str, "{} {} ({})", remo.name, remo.address, if remo.reachable { "OK" } else { "NG" }).ok();
+                    if local_status.remos.len() > 1 {
+                        write!(&mut local_remo_str, " +{}", local_status.remos.len() - 1).ok();
+                    }
+                },
+                None => {
+                    local_remo_str.write_str(context.text(Text::NotFound)).ok();
+                },
+            }
+            match local_status.remos.iter().find_map(|remo| remo.last_message.as_ref()) {
+                Some(message) => write!(&mut ir_signal_str, "{} {}kHz {}", message.format, message.freq, message.length).ok(),
+                None => ir_signal_str.write_str("--").ok(),
+            };
+        }
+
+        let rows = [
+            (Text::WifiState, wifi_str.as_str()),
+            (Text::ApiQuota, quota_str.as_str()),
+            (Text::ApiReset, reset_str.as_str()),
+            (Text::LastUpdate, last_update_str.as_str()),
+            (Text::Records, records_str.as_str()),
+            (Text::SensorDevice, device_id_str.as_str()),
+            (Text::PowerAppliance, appliance_id_str.as_str()),
+            (Text::LocalRemo, local_remo_str.as_str()),
+            (Text::LastIrSignal, ir_signal_str.as_str()),
+        ];
+        draw_rows(canvas, context, layout, &rows, layout.top);
+    }
+
+    fn handle_event(&mut self, _event: InputEvent, _context: &UiContext) -> Navigation {
+        Navigation::None
+    }
+}
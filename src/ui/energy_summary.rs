@@ -0,0 +1,58 @@
+use std::fmt::Write;
+
+use crate::canvas::Canvas;
+use crate::input::InputEvent;
+use crate::sample_interval;
+use super::{Page, UiContext, Layout, Navigation, SensorKind, Text, SCREEN_HEIGHT, draw_rows, draw_sensor_panel, records_duration};
+
+/// Statistics of the instantaneous power and the energy consumed within the recorded period.
+pub struct EnergySummaryPage {}
+
+impl EnergySummaryPage {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl Page for EnergySummaryPage {
+    fn title(&self) -> Text {
+        Text::Energy
+    }
+
+    fn render(&mut self, canvas: &mut dyn Canvas, context: &UiContext, layout: &Layout) {
+        let count = context.records.len();
+        let total_power: f32 = context.records.iter().map(|record| record.instant_power_usage).sum();
+        // Each record represents the power during a sampling interval.
+        let energy_kwh = total_power * sample_interval().as_secs_f32() / 3600.0 / 1000.0;
+
+        let mut average_str = heapless::String::<16>::new();
+        let mut energy_str = heapless::String::<16>::new();
+        let mut period_str = heapless::String::<16>::new();
+        let mut per_hour_str = heapless::String::<16>::new();
+        if count > 0 {
+            let average = total_power / count as f32;
+            write!(&mut average_str, "{:5.0} W", average).ok();
+            write!(&mut energy_str, "{:.2} kWh", energy_kwh).ok();
+            write!(&mut per_hour_str, "{:.2} kWh", average / 1000.0).ok();
+        } else {
+            average_str.write_str("--").ok();
+            energy_str.write_str("--").ok();
+            per_hour_str.write_str("--").ok();
+        }
+        let duration = records_duration(context);
+        write!(&mut period_str, "{}h {:02}m", duration.as_secs() / 3600, duration.as_secs() / 60 % 60).ok();
+
+        let rows = [
+            (Text::AveragePower, average_str.as_str()),
+            (Text::EnergyInPeriod, energy_str.as_str()),
+            (Text::PerHour, per_hour_str.as_str()),
+            (Text::Period, period_str.as_str()),
+        ];
+        let chart_top = draw_rows(canvas, context, layout, &rows, layout.top);
+        draw_sensor_panel(canvas, context, layout, SensorKind::Power, chart_top, (SCREEN_HEIGHT - chart_top).max(layout.line_height()));
+    }
+
+    fn handle_event(&mut self, _event: InputEvent, _context: &UiContext) -> Navigation {
+        Navigation::None
+    }
+}
@@ -0,0 +1,55 @@
+use crate::canvas::{Align, Canvas, Color, FontFace};
+
+use super::{Language, UiContext};
+
+/// Role of a text element. Each role is mapped to a font and a scale per language.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FontRole {
+    /// Texts on the top bar. Drawn inverted.
+    TopBar,
+    /// Labels and headings.
+    Label,
+    /// Current value of a sensor.
+    Value,
+    /// Smaller values such as max/min.
+    SubValue,
+    /// Rows of the status and settings pages.
+    Body,
+    /// Time of the clock on the dashboard.
+    Clock,
+}
+
+/// Returns the font and the scale for the role.
+/// Numeric values always use the monospace font so that the digits are aligned.
+pub fn font_for(language: Language, role: FontRole) -> (FontFace, f32) {
+    match (language, role) {
+        (_, FontRole::Value) => (FontFace::FreeMono24, 1.0),
+        (_, FontRole::SubValue) => (FontFace::FreeMono24, 0.75),
+        (_, FontRole::Clock) => (FontFace::FreeMono24, 1.5),
+        (Language::English, FontRole::TopBar) => (FontFace::FreeMono24, 0.5),
+        (Language::English, FontRole::Label) | (Language::English, FontRole::Body) => (FontFace::FreeSans18, 0.8),
+        (Language::Japanese, FontRole::TopBar) => (FontFace::JapanGothicP28, 1.0),
+        (Language::Japanese, FontRole::Label) | (Language::Japanese, FontRole::Body) => (FontFace::JapanGothicP32, 1.0),
+    }
+}
+
+/// Draw a text with the font for the role.
+pub fn draw_text(canvas: &mut dyn Canvas, context: &UiContext, role: FontRole, text: &str, x: i32, y: i32, align: Align) {
+    draw_with_font(canvas, role, font_for(context.language, role), text, x, y, align);
+}
+
+/// Draw a name set by the user, e.g. the name of a device, an appliance or a signal.
+/// The names are often Japanese, so they are drawn with the Japanese font in either language.
+pub fn draw_name(canvas: &mut dyn Canvas, role: FontRole, name: &str, x: i32, y: i32, align: Align) {
+    draw_with_font(canvas, role, font_for(Language::Japanese, role), name, x, y, align);
+}
+
+fn draw_with_font(canvas: &mut dyn Canvas, role: FontRole, (font, scale): (FontFace, f32), text: &str, x: i32, y: i32, align: Align) {
+    canvas.set_font(font);
+    // The top bar is drawn with the inverted colors.
+    if role == FontRole::TopBar {
+        canvas.draw_string(text, x, y, Color::WHITE, Color::BLACK, scale, align);
+    } else {
+        canvas.draw_string(text, x, y, Color::BLACK, Color::WHITE, scale, align);
+    }
+}
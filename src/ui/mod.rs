@@ -0,0 +1,490 @@
+use std::fmt::Write;
+
+use crate::canvas::{Align, Canvas, Color, FontFace};
+use crate::{sample_interval, SensorRecord, SensorRecords, SENSOR_RECORD_CAPACITY};
+use crate::chart::Chart;
+use crate::alert::{ActiveAlert, MAX_ALERT_RULES};
+use crate::power::PowerStatus;
+use crate::connectivity::NetworkStatus;
+use crate::input::{InputEvent, Button, SwipeDirection};
+use crate::button_appliance::ButtonApplianceKind;
+
+mod text;
+mod font;
+mod dashboard;
+mod sensor_detail;
+mod energy_summary;
+mod device_status;
+mod settings;
+mod aircon;
+mod remote;
+mod button_appliance;
+
+pub use text::{Language, Text};
+pub use font::{FontRole, draw_name, draw_text};
+pub use dashboard::DashboardPage;
+pub use sensor_detail::SensorDetailPage;
+pub use energy_summary::EnergySummaryPage;
+pub use device_status::DeviceStatusPage;
+pub use settings::SettingsPage;
+pub use aircon::AirconPage;
+pub use remote::RemotePage;
+pub use button_appliance::ButtonAppliancePage;
+
+pub const SCREEN_WIDTH: i32 = 960;
+pub const SCREEN_HEIGHT: i32 = 540;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SensorKind {
+    Temperature,
+    Humidity,
+    Illuminance,
+    Power,
+}
+
+impl SensorKind {
+    pub const ALL: [SensorKind; 4] = [SensorKind::Temperature, SensorKind::Humidity, SensorKind::Illuminance, SensorKind::Power];
+
+    pub fn label(&self) -> Text {
+        match self {
+            SensorKind::Temperature => Text::Temperature,
+            SensorKind::Humidity => Text::Humidity,
+            SensorKind::Illuminance => Text::Illuminance,
+            SensorKind::Power => Text::Power,
+        }
+    }
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            SensorKind::Temperature => "C",
+            SensorKind::Humidity => "%",
+            SensorKind::Illuminance => "",
+            SensorKind::Power => "W",
+        }
+    }
+
+    pub fn value(&self, record: &SensorRecord) -> f32 {
+        match self {
+            SensorKind::Temperature => record.ambient_temperature,
+            SensorKind::Humidity => record.relative_humidity,
+            SensorKind::Illuminance => record.ambient_luminous_level,
+            SensorKind::Power => record.instant_power_usage,
+        }
+    }
+
+    pub fn write_value<const N: usize>(&self, s: &mut heapless::String<N>, value: Option<f32>) {
+        match (self, value) {
+            (SensorKind::Temperature, Some(value)) | (SensorKind::Humidity, Some(value)) => write!(s, "{:4.1}", value).ok(),
+            (SensorKind::Illuminance, Some(value)) | (SensorKind::Power, Some(value)) => write!(s, "{:5.0}", value).ok(),
+            (_, None) => s.write_str("--").ok(),
+        };
+    }
+
+    pub fn next(&self) -> Self {
+        let index = Self::ALL.iter().position(|kind| kind == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn previous(&self) -> Self {
+        let index = Self::ALL.iter().position(|kind| kind == self).unwrap_or(0);
+        Self::ALL[(index + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+}
+
+/// Values shared by all pages while rendering and handling events.
+pub struct UiContext<'a> {
+    pub records: &'a SensorRecords<SENSOR_RECORD_CAPACITY>,
+    pub min: SensorRecord,
+    pub max: SensorRecord,
+    pub network: NetworkStatus,
+    /// Status of the battery, if it can be measured.
+    pub power: Option<PowerStatus>,
+    /// Name of the sensor device, which is usually the name of the room.
+    pub device_name: heapless::String<64>,
+    pub language: Language,
+    /// Current time in the configured time zone. `None` until the clock is synchronized.
+    pub local_time: Option<chrono::NaiveDateTime>,
+    /// Alerts currently raised, shown on the banner at the bottom of every page.
+    pub alerts: heapless::Vec<ActiveAlert, MAX_ALERT_RULES>,
+}
+
+impl<'a> UiContext<'a> {
+    pub fn text(&self, text: Text) -> &'static str {
+        text.get(self.language)
+    }
+
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageId {
+    Dashboard,
+    SensorDetail(SensorKind),
+    EnergySummary,
+    DeviceStatus,
+    Aircon,
+    Remote,
+    Light,
+    Tv,
+    Settings,
+}
+
+impl PageId {
+    /// Pages which can be switched by swiping or the up/down buttons.
+    pub const TOP_LEVEL: [PageId; 8] = [PageId::Dashboard, PageId::EnergySummary, PageId::DeviceStatus, PageId::Aircon, PageId::Remote, PageId::Light, PageId::Tv, PageId::Settings];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Navigation {
+    /// The event is not consumed by the page.
+    None,
+    /// The page state has been changed and must be redrawn.
+    Redraw,
+    Open(PageId),
+    Back,
+    Next,
+    Previous,
+}
+
+/// Vertical metrics of the page content.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    /// The y coordinate where the content area below the top bar starts.
+    pub top: i32,
+    /// Height of the base font (FreeMono24pt7b).
+    pub font_height: i32,
+}
+
+impl Layout {
+    pub fn line_height(&self) -> i32 {
+        self.font_height * 9 / 8
+    }
+
+    pub fn small_line_height(&self) -> i32 {
+        self.font_height * 3 / 4 * 9 / 8
+    }
+}
+
+pub trait Page {
+    fn title(&self) -> Text;
+    fn render(&mut self, canvas: &mut dyn Canvas, context: &UiContext, layout: &Layout);
+    fn handle_event(&mut self, event: InputEvent, context: &UiContext) -> Navigation;
+    /// Redraw only the clock for the partial update every minute. Returns false if the page has no clock.
+    fn render_clock(&mut self, _canvas: &mut dyn Canvas, _context: &UiContext, _layout: &Layout) -> bool {
+        false
+    }
+}
+
+const MAX_HISTORY: usize = 4;
+
+pub struct Navigator {
+    current: PageId,
+    history: heapless::Vec<PageId, MAX_HISTORY>,
+    dashboard: DashboardPage,
+    sensor_detail: SensorDetailPage,
+    energy_summary: EnergySummaryPage,
+    device_status: DeviceStatusPage,
+    aircon: AirconPage,
+    remote: RemotePage,
+    light: ButtonAppliancePage,
+    tv: ButtonAppliancePage,
+    settings: SettingsPage,
+}
+
+impl Navigator {
+    pub fn new() -> Self {
+        Self {
+            current: PageId::Dashboard,
+            history: heapless::Vec::new(),
+            dashboard: DashboardPage::new(),
+            sensor_detail: SensorDetailPage::new(SensorKind::Temperature),
+            energy_summary: EnergySummaryPage::new(),
+            device_status: DeviceStatusPage::new(),
+            aircon: AirconPage::new(),
+            remote: RemotePage::new(),
+            light: ButtonAppliancePage::new(ButtonApplianceKind::Light),
+            tv: ButtonAppliancePage::new(ButtonApplianceKind::Tv),
+            settings: SettingsPage::new(),
+        }
+    }
+
+    fn page_mut(&mut self) -> &mut dyn Page {
+        match self.current {
+            PageId::Dashboard => &mut self.dashboard,
+            PageId::SensorDetail(_) => &mut self.sensor_detail,
+            PageId::EnergySummary => &mut self.energy_summary,
+            PageId::DeviceStatus => &mut self.device_status,
+            PageId::Aircon => &mut self.aircon,
+            PageId::Remote => &mut self.remote,
+            PageId::Light => &mut self.light,
+            PageId::Tv => &mut self.tv,
+            PageId::Settings => &mut self.settings,
+        }
+    }
+
+    fn layout(canvas: &mut dyn Canvas) -> Layout {
+        canvas.set_font(FontFace::FreeMono24);
+        let font_height = canvas.font_height();
+        Layout {
+            top: font_height,
+            font_height,
+        }
+    }
+
+    pub fn render(&mut self, canvas: &mut dyn Canvas, context: &UiContext) {
+        let layout = Self::layout(canvas);
+        canvas.clear(Color::WHITE);
+        if context.power.map_or(false, |power| power.is_critical()) {
+            // Shown while the power is off.
+            draw_text(canvas, context, FontRole::Body, context.text(Text::BatteryEmpty), 20, SCREEN_HEIGHT / 2, Align::Left);
+            return;
+        }
+        let page = self.page_mut();
+        draw_top_bar(canvas, context, context.text(page.title()), layout.top);
+        page.render(canvas, context, &layout);
+        draw_alert_banner(canvas, context, layout.top);
+    }
+
+    /// Redraw only the clock of the current page. Returns false if the page has no clock.
+    pub fn render_clock(&mut self, canvas: &mut dyn Canvas, context: &UiContext) -> bool {
+        if context.power.map_or(false, |power| power.is_critical()) {
+            return false;
+        }
+        let layout = Self::layout(canvas);
+        self.page_mut().render_clock(canvas, context, &layout)
+    }
+
+    /// Handle an input event. Returns true if the screen must be redrawn.
+    pub fn handle_event(&mut self, event: InputEvent, context: &UiContext) -> bool {
+        let navigation = match self.page_mut().handle_event(event, context) {
+            Navigation::None => Self::default_navigation(event),
+            navigation => navigation,
+        };
+        match navigation {
+            Navigation::None => false,
+            Navigation::Redraw => true,
+            Navigation::Open(page) => {
+                if self.history.is_full() {
+                    self.history.remove(0);
+                }
+                self.history.push(self.current).ok();
+                self.open(page);
+                true
+            },
+            Navigation::Back => {
+                let page = self.history.pop().unwrap_or(PageId::Dashboard);
+                let changed = page != self.current;
+                self.open(page);
+                changed
+            },
+            Navigation::Next | Navigation::Previous => {
+                // Sub pages are switched relative to the top level page which they are opened from.
+                let base = if PageId::TOP_LEVEL.contains(&self.current) {
+                    self.current
+                } else {
+                    self.history.first().copied().unwrap_or(PageId::Dashboard)
+                };
+                let index = PageId::TOP_LEVEL.iter().position(|page| *page == base).unwrap_or(0);
+                let count = PageId::TOP_LEVEL.len();
+                let index = if navigation == Navigation::Next { (index + 1) % count } else { (index + count - 1) % count };
+                self.history.clear();
+                self.open(PageId::TOP_LEVEL[index]);
+                true
+            },
+        }
+    }
+
+    fn open(&mut self, page: PageId) {
+        log::info!("open page: {:?}", page);
+        if let PageId::SensorDetail(kind) = page {
+            self.sensor_detail.set_kind(kind);
+        }
+        self.current = page;
+    }
+
+    fn default_navigation(event: InputEvent) -> Navigation {
+        match event {
+            InputEvent::Swipe { direction: SwipeDirection::Left, .. } | InputEvent::Button(Button::Down) => Navigation::Next,
+            InputEvent::Swipe { direction: SwipeDirection::Right, .. } | InputEvent::Button(Button::Up) => Navigation::Previous,
+            InputEvent::LongPress { .. } | InputEvent::Button(Button::Push) => Navigation::Back,
+            InputEvent::Refresh => Navigation::Redraw,
+            _ => Navigation::None,
+        }
+    }
+}
+
+/// Minimum RSSI in dBm to light each bar of the Wi-Fi signal.
+const RSSI_LEVELS: [i8; 4] = [-90, -80, -70, -60];
+
+/// Draw the bars of the Wi-Fi signal strength. Unlit bars are drawn as short lines.
+fn draw_rssi_bars(canvas: &mut dyn Canvas, rssi: Option<i8>, x: i32, height: i32) {
+    const BAR_WIDTH: i32 = 5;
+    const BAR_GAP: i32 = 2;
+    let bottom = height - 4;
+    let max_bar_height = height - 8;
+    for (index, level) in RSSI_LEVELS.iter().enumerate() {
+        let left = x + (BAR_WIDTH + BAR_GAP) * index as i32;
+        if rssi.map_or(false, |rssi| rssi >= *level) {
+            let bar_height = max_bar_height * (index as i32 + 1) / RSSI_LEVELS.len() as i32;
+            canvas.fill_rect(left, bottom - bar_height, BAR_WIDTH, bar_height, Color::WHITE);
+        } else {
+            canvas.fill_rect(left, bottom - 2, BAR_WIDTH, 2, Color::WHITE);
+        }
+    }
+}
+
+/// Short form of the time elapsed, e.g. "45s", "12m" or "3h".
+fn format_age(age: chrono::Duration) -> heapless::String<8> {
+    let seconds = age.num_seconds().max(0);
+    let mut age_str = heapless::String::new();
+    match seconds {
+        0..=59 => write!(&mut age_str, "{}s", seconds).ok(),
+        60..=3599 => write!(&mut age_str, "{}m", seconds / 60).ok(),
+        3600..=359999 => write!(&mut age_str, "{}h", seconds / 3600).ok(),
+        _ => write!(&mut age_str, "{}d", seconds / 86400).ok(),
+    };
+    age_str
+}
+
+/// Short form of the fetch error for the top bar, e.g. "HTTP 429".
+fn error_summary(message: &str) -> heapless::String<16> {
+    let mut summary = heapless::String::new();
+    let status = message.split_once("status ")
+        .map(|(_, rest)| rest.split(|c: char| !c.is_ascii_digit()).next().unwrap_or(""))
+        .filter(|status| !status.is_empty());
+    if let Some(status) = status {
+        write!(&mut summary, "HTTP {}", status).ok();
+        return summary;
+    }
+    for c in message.chars().take(12) {
+        summary.push(c).ok();
+    }
+    summary
+}
+
+fn draw_top_bar(canvas: &mut dyn Canvas, context: &UiContext, title: &str, height: i32) {
+    let network = &context.network;
+    let mut rate_limit_str = heapless::String::<64>::new();
+    write!(&mut rate_limit_str, "API:").ok();
+    if let Some(limit) = network.rate_limit.limit {
+        if let Some(remaining) = network.rate_limit.remaining {
+            write!(&mut rate_limit_str, "{}/{}", remaining, limit).ok();
+        }
+    }
+    // The error is shown until the next fetch succeeds.
+    let mut fetch_str = heapless::String::<32>::new();
+    if let Some((message, _)) = network.last_error.as_ref() {
+        write!(&mut fetch_str, "ERR:{}", error_summary(message)).ok();
+    } else if let Some(last_fetched) = network.last_fetched {
+        write!(&mut fetch_str, "{} ago", format_age(crate::clock::now() - last_fetched)).ok();
+    }
+    let mut ip_str = heapless::String::<16>::new();
+    match network.wifi.ip {
+        Some(ip) if network.wifi.connected => write!(&mut ip_str, "{}", ip).ok(),
+        _ => write!(&mut ip_str, "NC").ok(),
+    };
+    let ntp_str = if network.time_synced { "NTP:OK" } else { "NTP:--" };
+    let mut battery_str = heapless::String::<16>::new();
+    if let Some(power) = context.power {
+        write!(&mut battery_str, "BAT:{}%{}", power.percentage, if power.charging { "+" } else { "" }).ok();
+    }
+
+    canvas.fill_rect(0, 0, SCREEN_WIDTH, height, Color::BLACK);
+    draw_text(canvas, context, FontRole::TopBar, &rate_limit_str, 0, 0, Align::Left);
+    draw_text(canvas, context, FontRole::TopBar, &fetch_str, 150, 0, Align::Left);
+    draw_rssi_bars(canvas, network.wifi.rssi.filter(|_| network.wifi.connected), 300, height);
+    draw_text(canvas, context, FontRole::TopBar, &ip_str, 332, 0, Align::Left);
+    draw_text(canvas, context, FontRole::TopBar, ntp_str, 520, 0, Align::Left);
+    draw_text(canvas, context, FontRole::TopBar, &battery_str, 605, 0, Align::Left);
+    draw_name(canvas, FontRole::TopBar, &context.device_name, 725, 0, Align::Left);
+    draw_text(canvas, context, FontRole::TopBar, title, SCREEN_WIDTH, 0, Align::Right);
+}
+
+/// Number of alerts shown on the banner. The rest are summarized as "+N".
+const MAX_BANNER_LINES: usize = 2;
+
+/// Draw the raised alerts over the bottom of the page with the inverted colors.
+fn draw_alert_banner(canvas: &mut dyn Canvas, context: &UiContext, line_height: i32) {
+    // The low battery warning comes first.
+    let low_battery = context.power.filter(|power| power.is_low());
+    let count = context.alerts.len() + low_battery.iter().count();
+    if count == 0 {
+        return;
+    }
+    let lines = count.min(MAX_BANNER_LINES);
+    let top = SCREEN_HEIGHT - line_height * lines as i32;
+    canvas.fill_rect(0, top, SCREEN_WIDTH, SCREEN_HEIGHT - top, Color::BLACK);
+    let mut texts = low_battery.iter()
+        .map(|power| {
+            let mut text = heapless::String::<128>::new();
+            write!(&mut text, "! {} {}%", context.text(Text::BatteryLow), power.percentage).ok();
+            text
+        })
+        .chain(context.alerts.iter().map(|alert| {
+            let rule = &alert.rule;
+            let mut text = heapless::String::<128>::new();
+            write!(&mut text, "! {} ", context.text(rule.kind.label())).ok();
+            rule.kind.write_value(&mut text, Some(alert.value));
+            write!(&mut text, "{} {} ", rule.kind.unit(), rule.comparison.symbol()).ok();
+            rule.kind.write_value(&mut text, Some(rule.threshold));
+            text.push_str(rule.kind.unit()).ok();
+            text
+        }));
+    for index in 0..lines {
+        let y = top + line_height * index as i32;
+        if let Some(text) = texts.next() {
+            draw_text(canvas, context, FontRole::TopBar, &text, 0, y, Align::Left);
+        }
+        if index == lines - 1 && count > lines {
+            let mut more = heapless::String::<8>::new();
+            write!(&mut more, "+{}", count - lines).ok();
+            draw_text(canvas, context, FontRole::TopBar, &more, SCREEN_WIDTH, y, Align::Right);
+        }
+    }
+}
+
+/// Draw the label, the current/max/min values and the chart of a sensor.
+pub(crate) fn draw_sensor_panel(canvas: &mut dyn Canvas, context: &UiContext, layout: &Layout, kind: SensorKind, top: i32, height: i32) {
+    let line_height = layout.line_height();
+    let chart_left = 300;
+    let chart_width = SCREEN_WIDTH - chart_left;
+    let value_margin_left = 20;
+
+    let mut cur_str = heapless::String::<16>::new();
+    let mut max_str = heapless::String::<16>::new();
+    let mut min_str = heapless::String::<16>::new();
+    let latest = context.records.latest();
+    kind.write_value(&mut cur_str, latest.map(|(record, _)| kind.value(&record)));
+    kind.write_value(&mut max_str, latest.map(|_| kind.value(&context.max)));
+    kind.write_value(&mut min_str, latest.map(|_| kind.value(&context.min)));
+
+    let mut record_iter = context.records.iter();
+    Chart::new(chart_width, height, Color::BLACK, Color::WHITE)
+        .draw(canvas, chart_left, top, context.records.len(), kind.value(&context.min), kind.value(&context.max), move |_| record_iter.next().map(|record| kind.value(record)))
+        .ok();
+    let mut y_offset = top;
+    draw_text(canvas, context, FontRole::Label, context.text(kind.label()), 0, y_offset, Align::Left);
+    y_offset += line_height;
+    draw_text(canvas, context, FontRole::Value, &cur_str, value_margin_left, y_offset, Align::Left);
+    y_offset += line_height;
+    draw_text(canvas, context, FontRole::SubValue, &max_str, value_margin_left, y_offset, Align::Left);
+    y_offset += line_height * 3 / 4;
+    draw_text(canvas, context, FontRole::SubValue, &min_str, value_margin_left, y_offset, Align::Left);
+}
+
+/// Draw rows of label and value pairs. Returns the y coordinate of the next row.
+pub(crate) fn draw_rows(canvas: &mut dyn Canvas, context: &UiContext, layout: &Layout, rows: &[(Text, &str)], top: i32) -> i32 {
+    let line_height = layout.small_line_height();
+    let value_left = 360;
+    let mut y_offset = top;
+    for (label, value) in rows {
+        draw_text(canvas, context, FontRole::Body, context.text(*label), 20, y_offset, Align::Left);
+        draw_text(canvas, context, FontRole::Body, value, value_left, y_offset, Align::Left);
+        y_offset += line_height;
+    }
+    y_offset
+}
+
+/// Sampling period which the records cover.
+pub(crate) fn records_duration(context: &UiContext) -> std::time::Duration {
+    sample_interval() * context.records.len() as u32
+}
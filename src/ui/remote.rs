@@ -0,0 +1,122 @@
+use std::fmt::Write;
+
+use crate::canvas::{Align, Canvas, Color};
+use crate::input::{InputEvent, SwipeDirection};
+use crate::remote::{self, SendResult, REMOTE_STATUS};
+use super::{Page, UiContext, Layout, Navigation, Text, FontRole, SCREEN_HEIGHT, SCREEN_WIDTH, draw_name, draw_text};
+
+const COLUMNS: usize = 3;
+const ROWS: usize = 3;
+const BUTTONS_PER_PAGE: usize = COLUMNS * ROWS;
+const MARGIN: i32 = 20;
+
+/// Large buttons to send the IR signals of the appliances. Swipe up/down to switch the pages of the buttons.
+pub struct RemotePage {
+    page: usize,
+    top: i32,
+    button_height: i32,
+}
+
+impl RemotePage {
+    pub fn new() -> Self {
+        Self {
+            page: 0,
+            top: 0,
+            button_height: 0,
+        }
+    }
+
+    fn button_width() -> i32 {
+        (SCREEN_WIDTH - MARGIN * (COLUMNS as i32 + 1)) / COLUMNS as i32
+    }
+
+    /// Index of the signal whose button is at the point.
+    fn hit_test(&self, x: i32, y: i32) -> Option<usize> {
+        if self.button_height <= 0 || x < MARGIN || y < self.top {
+            return None;
+        }
+        let column = ((x - MARGIN) / (Self::button_width() + MARGIN)) as usize;
+        let row = ((y - self.top) / (self.button_height + MARGIN)) as usize;
+        let in_button = (x - MARGIN) % (Self::button_width() + MARGIN) < Self::button_width()
+            && (y - self.top) % (self.button_height + MARGIN) < self.button_height;
+        (column < COLUMNS && row < ROWS && in_button).then(|| self.page * BUTTONS_PER_PAGE + row * COLUMNS + column)
+    }
+}
+
+impl Page for RemotePage {
+    fn title(&self) -> Text {
+        Text::Remote
+    }
+
+    fn render(&mut self, canvas: &mut dyn Canvas, context: &UiContext, layout: &Layout) {
+        crate::refresh_appliances_if_needed();
+        let status = REMOTE_STATUS.lock().unwrap();
+        if status.signals.is_empty() {
+            draw_text(canvas, context, FontRole::Body, context.text(Text::NoSignal), MARGIN, layout.top, Align::Left);
+            return;
+        }
+        let pages = (status.signals.len() + BUTTONS_PER_PAGE - 1) / BUTTONS_PER_PAGE;
+        self.page = self.page.min(pages - 1);
+        // The last line shows the result of the last signal and the page number.
+        let status_top = SCREEN_HEIGHT - layout.small_line_height();
+        self.top = layout.top + MARGIN / 2;
+        self.button_height = (status_top - self.top - MARGIN * ROWS as i32) / ROWS as i32;
+
+        let button_width = Self::button_width();
+        for (index, signal) in status.signals.iter().enumerate().skip(self.page * BUTTONS_PER_PAGE).take(BUTTONS_PER_PAGE) {
+            let position = index % BUTTONS_PER_PAGE;
+            let left = MARGIN + (button_width + MARGIN) * (position % COLUMNS) as i32;
+            let top = self.top + (self.button_height + MARGIN) * (position / COLUMNS) as i32;
+            // The button being sent is drawn with the normal colors.
+            let is_sending = matches!(status.last_sent, Some((id, SendResult::Sending)) if id == signal.id);
+            if is_sending {
+                canvas.fill_rect(left, top, button_width, self.button_height, Color::BLACK);
+                canvas.fill_rect(left + 4, top + 4, button_width - 8, self.button_height - 8, Color::WHITE);
+                draw_name(canvas, FontRole::Label, &signal.name, left + 12, top + 8, Align::Left);
+                draw_name(canvas, FontRole::Body, &signal.appliance, left + 12, top + self.button_height / 2, Align::Left);
+            } else {
+                canvas.fill_rect(left, top, button_width, self.button_height, Color::BLACK);
+                draw_name(canvas, FontRole::TopBar, &signal.name, left + 12, top + 8, Align::Left);
+                draw_name(canvas, FontRole::TopBar, &signal.appliance, left + 12, top + self.button_height / 2, Align::Left);
+            }
+        }
+
+        let mut result_str = heapless::String::<96>::new();
+        if let Some((id, result)) = status.last_sent.as_ref() {
+            let name = status.signals.iter().find(|signal| signal.id == *id).map(|signal| signal.name.as_str()).unwrap_or("");
+            let text = match result {
+                SendResult::Sending => context.text(Text::Sending),
+                SendResult::Sent => context.text(Text::Sent),
+                SendResult::Failed(message) => message.as_str(),
+            };
+            result_str.push_str(name).ok();
+            result_str.push_str(": ").ok();
+            result_str.push_str(text).ok();
+        }
+        draw_text(canvas, context, FontRole::Body, &result_str, MARGIN, status_top, Align::Left);
+        if pages > 1 {
+            let mut page_str = heapless::String::<8>::new();
+            write!(&mut page_str, "{}/{}", self.page + 1, pages).ok();
+            draw_text(canvas, context, FontRole::Body, &page_str, SCREEN_WIDTH - MARGIN, status_top, Align::Right);
+        }
+    }
+
+    fn handle_event(&mut self, event: InputEvent, _context: &UiContext) -> Navigation {
+        match event {
+            InputEvent::Tap { x, y } => match self.hit_test(x, y) {
+                Some(index) if remote::send(index) => Navigation::Redraw,
+                _ => Navigation::None,
+            },
+            InputEvent::Swipe { direction: SwipeDirection::Up, .. } => {
+                // Clamped to the last page while rendering.
+                self.page += 1;
+                Navigation::Redraw
+            },
+            InputEvent::Swipe { direction: SwipeDirection::Down, .. } if self.page > 0 => {
+                self.page -= 1;
+                Navigation::Redraw
+            },
+            _ => Navigation::None,
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::fmt::Write;
+
+use crate::canvas::{Align, Canvas};
+use crate::input::{InputEvent, SwipeDirection};
+use super::{Page, UiContext, Layout, Navigation, SensorKind, Text, FontRole, SCREEN_HEIGHT, draw_sensor_panel, draw_text, records_duration};
+
+/// Full screen chart and statistics of a sensor.
+pub struct SensorDetailPage {
+    kind: SensorKind,
+}
+
+impl SensorDetailPage {
+    pub fn new(kind: SensorKind) -> Self {
+        Self { kind }
+    }
+
+    pub fn set_kind(&mut self, kind: SensorKind) {
+        self.kind = kind;
+    }
+}
+
+impl Page for SensorDetailPage {
+    fn title(&self) -> Text {
+        Text::Detail
+    }
+
+    fn render(&mut self, canvas: &mut dyn Canvas, context: &UiContext, layout: &Layout) {
+        let line_height = layout.small_line_height();
+        let chart_height = SCREEN_HEIGHT - layout.top - line_height;
+        draw_sensor_panel(canvas, context, layout, self.kind, layout.top, chart_height);
+
+        // Average and the period at the bottom.
+        let kind = self.kind;
+        let count = context.records.len();
+        let average = if count > 0 {
+            Some(context.records.iter().map(|record| kind.value(record)).sum::<f32>() / count as f32)
+        } else {
+            None
+        };
+        let mut summary_str = heapless::String::<64>::new();
+        write!(&mut summary_str, "{}: ", context.text(Text::Average)).ok();
+        kind.write_value(&mut summary_str, average);
+        write!(&mut summary_str, "{}  {}: {}min", kind.unit(), context.text(Text::Period), records_duration(context).as_secs() / 60).ok();
+        draw_text(canvas, context, FontRole::Body, &summary_str, 0, SCREEN_HEIGHT - line_height, Align::Left);
+    }
+
+    fn handle_event(&mut self, event: InputEvent, _context: &UiContext) -> Navigation {
+        match event {
+            InputEvent::Swipe { direction: SwipeDirection::Up, .. } => {
+                self.kind = self.kind.next();
+                Navigation::Redraw
+            },
+            InputEvent::Swipe { direction: SwipeDirection::Down, .. } => {
+                self.kind = self.kind.previous();
+                Navigation::Redraw
+            },
+            InputEvent::Tap { .. } => Navigation::Back,
+            _ => Navigation::None,
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt::Write;
+
+use crate::canvas::Canvas;
+use crate::input::InputEvent;
+use crate::{sample_interval, CONFIG, UI_REFRESH_INTERVAL};
+use super::{Page, UiContext, Layout, Navigation, Text, draw_rows};
+
+const LANGUAGE_ROW: i32 = 4;
+
+/// Current configuration. The access token is masked.
+/// Tapping the language row switches the UI language.
+pub struct SettingsPage {
+    top: i32,
+    row_height: i32,
+}
+
+impl SettingsPage {
+    pub fn new() -> Self {
+        Self {
+            top: 0,
+            row_height: 0,
+        }
+    }
+}
+
+impl Page for SettingsPage {
+    fn title(&self) -> Text {
+        Text::Settings
+    }
+
+    fn render(&mut self, canvas: &mut dyn Canvas, context: &UiContext, layout: &Layout) {
+        let mut ssid_str = heapless::String::<48>::new();
+        let mut token_str = heapless::String::<32>::new();
+        let mut sample_interval_str = heapless::String::<16>::new();
+        let mut refresh_interval_str = heapless::String::<16>::new();
+        if let Some(config) = CONFIG.lock().unwrap().as_ref() {
+            if let Some(network) = config.wifi_networks.first() {
+                ssid_str.push_str(&network.ssid).ok();
+            }
+            if config.wifi_networks.len() > 1 {
+                write!(&mut ssid_str, " +{}", config.wifi_networks.len() - 1).ok();
+            }
+            let token = config.access_token.as_str();
+            if token.len() > 4 {
+                write!(&mut token_str, "****{}", &token[token.len() - 4..]).ok();
+            } else {
+                token_str.write_str(context.text(Text::NotSet)).ok();
+            }
+        }
+        write!(&mut sample_interval_str, "{}s", sample_interval().as_secs()).ok();
+        write!(&mut refresh_interval_str, "{}s", UI_REFRESH_INTERVAL.as_secs()).ok();
+
+        let rows = [
+            (Text::WifiSsid, ssid_str.as_str()),
+            (Text::AccessToken, token_str.as_str()),
+            (Text::SampleInterval, sample_interval_str.as_str()),
+            (Text::RefreshInterval, refresh_interval_str.as_str()),
+            (Text::LanguageSetting, context.text(Text::LanguageName)),
+        ];
+        self.top = layout.top;
+        self.row_height = layout.small_line_height();
+        draw_rows(canvas, context, layout, &rows, layout.top);
+    }
+
+    fn handle_event(&mut self, event: InputEvent, _context: &UiContext) -> Navigation {
+        match event {
+            InputEvent::Tap { y, .. } if self.row_height > 0 && (y - self.top) / self.row_height == LANGUAGE_ROW => {
+                if let Some(config) = CONFIG.lock().unwrap().as_mut() {
+                    config.language = config.language.next();
+                    log::info!("language: {:?}", config.language);
+                }
+                Navigation::Redraw
+            },
+            _ => Navigation::None,
+        }
+    }
+}
@@ -0,0 +1,235 @@
+/// Language of the UI strings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    English,
+    Japanese,
+}
+
+impl Default for Language {
+    fn default() -> Self {
+        Language::English
+    }
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::Japanese];
+
+    /// Parse a language code such as "en" or "ja".
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "en" => Some(Language::English),
+            "ja" => Some(Language::Japanese),
+            _ => None,
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        let index = Self::ALL.iter().position(|language| language == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// Localisable UI strings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Text {
+    Temperature,
+    Humidity,
+    Illuminance,
+    Power,
+    Dashboard,
+    Detail,
+    Energy,
+    Status,
+    Settings,
+    Average,
+    Period,
+    AveragePower,
+    EnergyInPeriod,
+    PerHour,
+    WifiState,
+    Connected,
+    NotConnected,
+    ApiQuota,
+    ApiReset,
+    LastUpdate,
+    Records,
+    SensorDevice,
+    PowerAppliance,
+    WifiSsid,
+    AccessToken,
+    NotSet,
+    SampleInterval,
+    RefreshInterval,
+    LanguageSetting,
+    LanguageName,
+    LocalRemo,
+    LastIrSignal,
+    NotFound,
+    Aircon,
+    PowerButton,
+    ModeButton,
+    FanButton,
+    Sending,
+    NoAircon,
+    Remote,
+    NoSignal,
+    Sent,
+    Light,
+    NoLight,
+    Tv,
+    NoTv,
+    BatteryLow,
+    BatteryEmpty,
+    /// Format of the date on the clock, for `chrono::format`.
+    DateFormat,
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Text {
+    pub fn weekday(weekday: chrono::Weekday) -> Self {
+        match weekday {
+            chrono::Weekday::Mon => Text::Monday,
+            chrono::Weekday::Tue => Text::Tuesday,
+            chrono::Weekday::Wed => Text::Wednesday,
+            chrono::Weekday::Thu => Text::Thursday,
+            chrono::Weekday::Fri => Text::Friday,
+            chrono::Weekday::Sat => Text::Saturday,
+            chrono::Weekday::Sun => Text::Sunday,
+        }
+    }
+
+    pub fn get(self, language: Language) -> &'static str {
+        match language {
+            Language::English => self.english(),
+            Language::Japanese => self.japanese(),
+        }
+    }
+
+    fn english(self) -> &'static str {
+        match self {
+            Text::Temperature => "Temperature:",
+            Text::Humidity => "Humidity:",
+            Text::Illuminance => "Illuminance:",
+            Text::Power => "Power:",
+            Text::Dashboard => "Dashboard",
+            Text::Detail => "Detail",
+            Text::Energy => "Energy",
+            Text::Status => "Status",
+            Text::Settings => "Settings",
+            Text::Average => "Avg",
+            Text::Period => "Period",
+            Text::AveragePower => "Average power:",
+            Text::EnergyInPeriod => "Energy in period:",
+            Text::PerHour => "Per hour (avg):",
+            Text::WifiState => "Wi-Fi:",
+            Text::Connected => "Connected",
+            Text::NotConnected => "Not connected",
+            Text::ApiQuota => "API quota:",
+            Text::ApiReset => "API reset:",
+            Text::LastUpdate => "Last update:",
+            Text::Records => "Records:",
+            Text::SensorDevice => "Sensor device:",
+            Text::PowerAppliance => "Power appliance:",
+            Text::WifiSsid => "Wi-Fi SSID:",
+            Text::AccessToken => "Access token:",
+            Text::NotSet => "(not set)",
+            Text::SampleInterval => "Sample interval:",
+            Text::RefreshInterval => "Refresh interval:",
+            Text::LanguageSetting => "Language:",
+            Text::LanguageName => "English",
+            Text::LocalRemo => "LAN Remo:",
+            Text::LastIrSignal => "Last IR signal:",
+            Text::NotFound => "Not found",
+            Text::Aircon => "Aircon",
+            Text::PowerButton => "Power",
+            Text::ModeButton => "Mode",
+            Text::FanButton => "Fan",
+            Text::Sending => "Sending...",
+            Text::NoAircon => "No air conditioner",
+            Text::Remote => "Remote",
+            Text::NoSignal => "No signal",
+            Text::Sent => "Sent",
+            Text::Light => "Light",
+            Text::NoLight => "No light",
+            Text::Tv => "TV",
+            Text::NoTv => "No TV",
+            Text::BatteryLow => "Low battery. Connect USB power",
+            Text::BatteryEmpty => "Battery is empty. Charge and press the power button",
+            Text::DateFormat => "%Y-%m-%d",
+            Text::Monday => "Mon",
+            Text::Tuesday => "Tue",
+            Text::Wednesday => "Wed",
+            Text::Thursday => "Thu",
+            Text::Friday => "Fri",
+            Text::Saturday => "Sat",
+            Text::Sunday => "Sun",
+        }
+    }
+
+    fn japanese(self) -> &'static str {
+        match self {
+            Text::Temperature => "室温:",
+            Text::Humidity => "湿度:",
+            Text::Illuminance => "照度:",
+            Text::Power => "瞬時電力:",
+            Text::Dashboard => "ダッシュボード",
+            Text::Detail => "詳細",
+            Text::Energy => "電力量",
+            Text::Status => "状態",
+            Text::Settings => "設定",
+            Text::Average => "平均",
+            Text::Period => "期間",
+            Text::AveragePower => "平均電力:",
+            Text::EnergyInPeriod => "期間中の電力量:",
+            Text::PerHour => "1時間あたり(平均):",
+            Text::WifiState => "Wi-Fi:",
+            Text::Connected => "接続中",
+            Text::NotConnected => "未接続",
+            Text::ApiQuota => "API残り回数:",
+            Text::ApiReset => "APIリセット:",
+            Text::LastUpdate => "最終更新:",
+            Text::Records => "記録数:",
+            Text::SensorDevice => "センサ機器:",
+            Text::PowerAppliance => "電力計測機器:",
+            Text::WifiSsid => "Wi-Fi SSID:",
+            Text::AccessToken => "アクセストークン:",
+            Text::NotSet => "(未設定)",
+            Text::SampleInterval => "記録間隔:",
+            Text::RefreshInterval => "画面更新間隔:",
+            Text::LanguageSetting => "言語:",
+            Text::LanguageName => "日本語",
+            Text::LocalRemo => "LAN内のRemo:",
+            Text::LastIrSignal => "最後の赤外線信号:",
+            Text::NotFound => "見つかりません",
+            Text::Aircon => "エアコン",
+            Text::PowerButton => "電源",
+            Text::ModeButton => "モード",
+            Text::FanButton => "風量",
+            Text::Sending => "送信中...",
+            Text::NoAircon => "エアコンがありません",
+            Text::Remote => "リモコン",
+            Text::NoSignal => "信号がありません",
+            Text::Sent => "送信しました",
+            Text::Light => "照明",
+            Text::NoLight => "照明がありません",
+            Text::Tv => "テレビ",
+            Text::NoTv => "テレビがありません",
+            Text::BatteryLow => "電池残量が少なくなっています。USB電源をつないでください",
+            Text::BatteryEmpty => "電池残量がありません。充電して電源ボタンを押してください",
+            Text::DateFormat => "%Y年%-m月%-d日",
+            Text::Monday => "(月)",
+            Text::Tuesday => "(火)",
+            Text::Wednesday => "(水)",
+            Text::Thursday => "(木)",
+            Text::Friday => "(金)",
+            Text::Saturday => "(土)",
+            Text::Sunday => "(日)",
+        }
+    }
+}